@@ -0,0 +1,441 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::network;
+use crate::outcall::{self, header};
+use crate::transform::Transform;
+use crate::sep10::sep10_token;
+use crate::stellar_toml::{fetch_stellar_toml, StellarToml, TRANSFER_SERVER, TRANSFER_SERVER_SEP0024};
+use crate::tx_ledger::{TxKind, TxRequest, TxStatus};
+use crate::{parse_asset, parse_memo, parse_stellar_amount, public_key_stellar, send_payment};
+use crate::rate_limit::{
+    guard_record_anchor_transfer, guard_refresh_anchor_transfer, guard_start_anchor_deposit,
+    guard_start_anchor_withdrawal,
+};
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AnchorProtocol {
+    // Non-interactive deposit and withdrawal
+    Sep6,
+    // Interactive flow completed by the user in the anchor's web page
+    Sep24,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AnchorTransferKind {
+    Deposit,
+    Withdrawal,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AnchorTransferRequest {
+    pub home_domain: String,
+    pub asset_code: String,
+    pub amount: Option<String>,
+    pub interactive: bool,
+    // SEP-6 only: transfer method (e.g. "bank_account"), destination and its extra field
+    pub transfer_type: Option<String>,
+    pub dest: Option<String>,
+    pub dest_extra: Option<String>,
+    pub network: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AnchorTransfer {
+    pub id: String,
+    pub home_domain: String,
+    pub network: String,
+    pub protocol: AnchorProtocol,
+    pub kind: AnchorTransferKind,
+    pub asset_code: String,
+    pub status: String,
+    pub interactive_url: Option<String>,
+    pub more_info_url: Option<String>,
+    // SEP-6 deposit instructions, as returned by the anchor
+    pub instructions: Option<String>,
+    pub amount_in: Option<String>,
+    pub amount_out: Option<String>,
+    pub amount_fee: Option<String>,
+    pub withdraw_anchor_account: Option<String>,
+    pub withdraw_memo: Option<String>,
+    pub withdraw_memo_type: Option<String>,
+    pub stellar_transaction_id: Option<String>,
+    // Explorer link of the withdrawal payment sent on the user's behalf
+    pub payment_result: Option<String>,
+    pub message: Option<String>,
+    pub updated_at: u64,
+}
+
+// Marks a withdrawal payment that is being sent so concurrent refreshes don't pay twice
+const PAYMENT_IN_FLIGHT: &str = "in_flight";
+
+thread_local! {
+    static ANCHOR_TRANSFERS: RefCell<HashMap<(Principal, String), AnchorTransfer>> = RefCell::new(HashMap::new());
+}
+
+pub fn snapshot() -> Vec<((Principal, String), AnchorTransfer)> {
+    ANCHOR_TRANSFERS.with(|transfers| transfers.borrow().iter().map(|(key, t)| (key.clone(), t.clone())).collect())
+}
+
+pub fn restore(entries: Vec<((Principal, String), AnchorTransfer)>) {
+    ANCHOR_TRANSFERS.with(|transfers| *transfers.borrow_mut() = entries.into_iter().collect());
+}
+
+// Each call to an anchor's deposit or withdraw endpoint opens a new transaction there, with
+// its own id and interactive URL, so replicas making the call would open one each and never
+// agree on the response. As with SEP-10, the client calls the anchor: the start endpoints
+// return the request to send with the caller's JWT, and record_anchor_transfer keeps the
+// transaction the anchor answered with.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AnchorTransferCall {
+    // "POST" with `body` as JSON for SEP-24, "GET" for SEP-6
+    pub method: String,
+    pub url: String,
+    pub body: Option<String>,
+}
+
+#[ic_cdk::update(guard = "guard_start_anchor_deposit")]
+async fn start_anchor_deposit(request: AnchorTransferRequest) -> Result<AnchorTransferCall, String> {
+    transfer_call(AnchorTransferKind::Deposit, request).await
+}
+
+#[ic_cdk::update(guard = "guard_start_anchor_withdrawal")]
+async fn start_anchor_withdrawal(request: AnchorTransferRequest) -> Result<AnchorTransferCall, String> {
+    transfer_call(AnchorTransferKind::Withdrawal, request).await
+}
+
+// Keep the transaction the anchor opened for a call from start_anchor_deposit or
+// start_anchor_withdrawal, given the HTTP status and body the client got back
+#[ic_cdk::update(guard = "guard_record_anchor_transfer")]
+fn record_anchor_transfer(
+    kind: AnchorTransferKind,
+    request: AnchorTransferRequest,
+    status: u16,
+    body: String,
+) -> Result<AnchorTransfer, String> {
+    let network = network::resolve(request.network.clone())?;
+    let transfer = parse_transfer(kind, request, network, status, &body)?;
+    ic_cdk::println!("Started {:?} {:?} {} with {}", transfer.protocol, transfer.kind, transfer.id, transfer.home_domain);
+    store(&transfer);
+    Ok(transfer)
+}
+
+// Poll the anchor's /transaction endpoint; withdrawals waiting on the user's payment get it sent here
+#[ic_cdk::update(guard = "guard_refresh_anchor_transfer")]
+async fn refresh_anchor_transfer(id: String) -> Result<AnchorTransfer, String> {
+    let key = (ic_cdk::caller(), id.clone());
+    let mut transfer = ANCHOR_TRANSFERS
+        .with(|transfers| transfers.borrow().get(&key).cloned())
+        .ok_or_else(|| format!("Unknown anchor transfer: {}", id))?;
+
+    let toml = fetch_stellar_toml(&transfer.home_domain).await?;
+    let token = sep10_token(&transfer.home_domain, &transfer.network)?;
+    let server = transfer_server(&toml, &transfer.protocol)?;
+
+    let url = url::Url::parse_with_params(&format!("{}/transaction", server), &[("id", id.as_str())])
+        .map_err(|e| format!("Invalid transfer server URL: {}", e))?;
+    let response = outcall::get(url.as_str(), vec![bearer(&token)], 50_000, Transform::Headers).await?;
+    if !response.is_success() {
+        return Err(format!("Anchor status request failed: HTTP {} {}", response.status, response.body));
+    }
+
+    let body: serde_json::Value = serde_json::from_str(&response.body)
+        .map_err(|e| format!("Failed to parse anchor transaction: {}", e))?;
+    let record = body.get("transaction").ok_or("Anchor response has no transaction")?;
+    apply_status(&mut transfer, record);
+    store(&transfer);
+
+    if transfer.kind == AnchorTransferKind::Withdrawal
+        && transfer.status == "pending_user_transfer_start"
+        && transfer.payment_result.is_none()
+    {
+        transfer = send_withdrawal_payment(transfer, &toml).await?;
+    }
+
+    Ok(transfer)
+}
+
+#[ic_cdk::query]
+fn get_anchor_transfers() -> Vec<AnchorTransfer> {
+    let caller = ic_cdk::caller();
+    ANCHOR_TRANSFERS.with(|transfers| {
+        transfers
+            .borrow()
+            .iter()
+            .filter(|((owner, _), _)| *owner == caller)
+            .map(|(_, transfer)| transfer.clone())
+            .collect()
+    })
+}
+
+fn direction(kind: &AnchorTransferKind) -> &'static str {
+    match kind {
+        AnchorTransferKind::Deposit => "deposit",
+        AnchorTransferKind::Withdrawal => "withdraw",
+    }
+}
+
+async fn transfer_call(kind: AnchorTransferKind, request: AnchorTransferRequest) -> Result<AnchorTransferCall, String> {
+    let network = network::resolve(request.network.clone())?;
+    if let Some(amount) = &request.amount {
+        parse_stellar_amount(amount)?;
+    }
+
+    let toml = fetch_stellar_toml(&request.home_domain).await?;
+    let protocol = if request.interactive { AnchorProtocol::Sep24 } else { AnchorProtocol::Sep6 };
+    let server = transfer_server(&toml, &protocol)?;
+    // The client sends the call with this token; fail early when it has not registered one
+    sep10_token(&request.home_domain, &network)?;
+    let account = public_key_stellar().await?;
+    let direction = direction(&kind);
+
+    match protocol {
+        AnchorProtocol::Sep24 => {
+            let mut body = serde_json::json!({
+                "asset_code": request.asset_code,
+                "account": account,
+            });
+            if let Some(amount) = &request.amount {
+                body["amount"] = serde_json::Value::String(amount.clone());
+            }
+            if let Some(issuer) = toml.currency_issuer(&request.asset_code) {
+                body["asset_issuer"] = serde_json::Value::String(issuer.to_string());
+            }
+
+            Ok(AnchorTransferCall {
+                method: "POST".to_string(),
+                url: format!("{}/transactions/{}/interactive", server, direction),
+                body: Some(body.to_string()),
+            })
+        }
+        AnchorProtocol::Sep6 => {
+            let mut params = vec![
+                ("asset_code", request.asset_code.clone()),
+                ("account", account.clone()),
+            ];
+            let optional = [
+                ("amount", &request.amount),
+                ("type", &request.transfer_type),
+                ("dest", &request.dest),
+                ("dest_extra", &request.dest_extra),
+            ];
+            params.extend(
+                optional
+                    .into_iter()
+                    .filter_map(|(name, value)| value.clone().map(|value| (name, value))),
+            );
+
+            let url = url::Url::parse_with_params(&format!("{}/{}", server, direction), &params)
+                .map_err(|e| format!("Invalid transfer server URL: {}", e))?;
+            Ok(AnchorTransferCall { method: "GET".to_string(), url: url.to_string(), body: None })
+        }
+    }
+}
+
+// The anchor's answer to a transfer call. The body comes from the client, so a forged one can
+// at most misdirect the caller's own withdrawal payment, which the caller could send anyway;
+// refresh_anchor_transfer overwrites the payment details with the anchor's record first.
+fn parse_transfer(
+    kind: AnchorTransferKind,
+    request: AnchorTransferRequest,
+    network: String,
+    status: u16,
+    response: &str,
+) -> Result<AnchorTransfer, String> {
+    let protocol = if request.interactive { AnchorProtocol::Sep24 } else { AnchorProtocol::Sep6 };
+    let direction = direction(&kind);
+    let body: serde_json::Value = serde_json::from_str(response)
+        .map_err(|e| format!("Failed to parse anchor response: {}", e))?;
+
+    if !(200..300).contains(&status) {
+        // SEP-6 uses 403 to ask for KYC, either as missing fields or a pending review
+        let message = match body.get("type").and_then(|t| t.as_str()) {
+            Some("non_interactive_customer_info_needed") => format!(
+                "Anchor needs customer information: {}",
+                body.get("fields").map(|f| f.to_string()).unwrap_or_default()
+            ),
+            Some("customer_info_status") => format!(
+                "Customer information is {}: {}",
+                str_field(&body, "status").unwrap_or_default(),
+                str_field(&body, "more_info_url").unwrap_or_default()
+            ),
+            _ => str_field(&body, "error").unwrap_or_else(|| response.to_string()),
+        };
+        return Err(format!("Anchor rejected {} request: {}", direction, message));
+    }
+
+    let id = str_field(&body, "id").ok_or("Anchor response has no transaction id")?;
+    let instructions = body
+        .get("instructions")
+        .map(|i| i.to_string())
+        .or_else(|| str_field(&body, "how"));
+
+    Ok(AnchorTransfer {
+        id,
+        home_domain: request.home_domain,
+        network,
+        status: match protocol {
+            AnchorProtocol::Sep24 => "incomplete",
+            AnchorProtocol::Sep6 => "pending_user_transfer_start",
+        }
+        .to_string(),
+        protocol,
+        kind,
+        asset_code: request.asset_code,
+        interactive_url: str_field(&body, "url"),
+        more_info_url: None,
+        instructions,
+        amount_in: request.amount,
+        amount_out: None,
+        amount_fee: None,
+        withdraw_anchor_account: str_field(&body, "account_id"),
+        withdraw_memo: str_field(&body, "memo"),
+        withdraw_memo_type: str_field(&body, "memo_type"),
+        stellar_transaction_id: None,
+        payment_result: None,
+        message: str_field(&body, "extra_info").or_else(|| str_field(&body, "eta")),
+        updated_at: ic_cdk::api::time(),
+    })
+}
+
+async fn send_withdrawal_payment(mut transfer: AnchorTransfer, toml: &StellarToml) -> Result<AnchorTransfer, String> {
+    let destination = transfer
+        .withdraw_anchor_account
+        .clone()
+        .ok_or("Anchor did not provide a withdrawal account")?;
+    let amount = transfer
+        .amount_in
+        .as_deref()
+        .ok_or("Anchor did not provide the withdrawal amount")
+        .and_then(|amount| parse_stellar_amount(amount).map_err(|_| "Anchor sent an invalid withdrawal amount"))?;
+    let asset = parse_asset(&transfer.asset_code, toml.currency_issuer(&transfer.asset_code))?;
+    let memo = parse_memo(transfer.withdraw_memo_type.as_deref(), transfer.withdraw_memo.as_deref())?;
+
+    // Claim the payment before awaiting so a concurrent refresh sees it
+    let key = (ic_cdk::caller(), transfer.id.clone());
+    let claimed = ANCHOR_TRANSFERS.with(|transfers| {
+        let mut transfers = transfers.borrow_mut();
+        match transfers.get_mut(&key) {
+            Some(stored) if stored.payment_result.is_none() => {
+                stored.payment_result = Some(PAYMENT_IN_FLIGHT.to_string());
+                true
+            }
+            _ => false,
+        }
+    });
+    if !claimed {
+        return Err(format!("Withdrawal payment for {} is already being sent", transfer.id));
+    }
+
+    ic_cdk::println!("Sending withdrawal payment for {} to {}", transfer.id, destination);
+    let request = TxRequest::new(TxKind::Payment, serde_json::json!({
+        "anchor": transfer.home_domain,
+        "anchor_transfer_id": transfer.id,
+        "destination_address": destination,
+        "asset_code": transfer.asset_code,
+        "amount": transfer.amount_in,
+    }));
+    let result = send_payment(&destination, asset, amount, memo, &transfer.network, request).await;
+
+    // Only an accepted submission keeps the claim; anything else can be retried
+    match result {
+        Ok(submitted) => {
+            transfer.stellar_transaction_id = Some(submitted.hash);
+            transfer.payment_result = Some(submitted.explorer_url);
+            transfer.updated_at = ic_cdk::api::time();
+            store(&transfer);
+            Ok(transfer)
+        }
+        Err(e) => {
+            ANCHOR_TRANSFERS.with(|transfers| {
+                if let Some(stored) = transfers.borrow_mut().get_mut(&key) {
+                    stored.payment_result = None;
+                }
+            });
+            Err(format!("Withdrawal payment failed: {}", e.message))
+        }
+    }
+}
+
+// Called once a withdrawal payment reaches its final status. A payment that failed in a
+// ledger or expired releases the claim, so the next refresh sends it again.
+pub fn payment_settled(owner: Principal, hash: &str, status: &TxStatus) {
+    let TxStatus::Failed(reason) = status else {
+        return;
+    };
+    ANCHOR_TRANSFERS.with(|transfers| {
+        let mut transfers = transfers.borrow_mut();
+        let paid_by = transfers
+            .iter_mut()
+            .find(|((principal, _), transfer)| *principal == owner && transfer.stellar_transaction_id.as_deref() == Some(hash));
+        if let Some((_, transfer)) = paid_by {
+            ic_cdk::println!("Withdrawal payment {} for {} failed, releasing the claim", hash, transfer.id);
+            transfer.stellar_transaction_id = None;
+            transfer.payment_result = None;
+            transfer.message = Some(format!("Withdrawal payment failed: {}", reason));
+            transfer.updated_at = ic_cdk::api::time();
+        }
+    });
+}
+
+fn apply_status(transfer: &mut AnchorTransfer, record: &serde_json::Value) {
+    let update = |field: &mut Option<String>, key: &str| {
+        if let Some(value) = str_field(record, key) {
+            *field = Some(value);
+        }
+    };
+
+    if let Some(status) = str_field(record, "status") {
+        transfer.status = status;
+    }
+    update(&mut transfer.more_info_url, "more_info_url");
+    update(&mut transfer.amount_in, "amount_in");
+    update(&mut transfer.amount_out, "amount_out");
+    update(&mut transfer.amount_fee, "amount_fee");
+    update(&mut transfer.withdraw_anchor_account, "withdraw_anchor_account");
+    update(&mut transfer.withdraw_memo, "withdraw_memo");
+    update(&mut transfer.withdraw_memo_type, "withdraw_memo_type");
+    update(&mut transfer.stellar_transaction_id, "stellar_transaction_id");
+    update(&mut transfer.message, "message");
+    transfer.updated_at = ic_cdk::api::time();
+}
+
+fn transfer_server(toml: &StellarToml, protocol: &AnchorProtocol) -> Result<String, String> {
+    let key = match protocol {
+        AnchorProtocol::Sep6 => TRANSFER_SERVER,
+        AnchorProtocol::Sep24 => TRANSFER_SERVER_SEP0024,
+    };
+    Ok(toml.require(key)?.trim_end_matches('/').to_string())
+}
+
+fn store(transfer: &AnchorTransfer) {
+    ANCHOR_TRANSFERS.with(|transfers| {
+        let mut transfers = transfers.borrow_mut();
+        let key = (ic_cdk::caller(), transfer.id.clone());
+        // Never drop an in-flight claim or a recorded payment when saving a status update
+        let payment_result = transfers
+            .get(&key)
+            .and_then(|stored| stored.payment_result.clone())
+            .or_else(|| transfer.payment_result.clone());
+        let mut transfer = transfer.clone();
+        if transfer.payment_result.is_none() {
+            transfer.payment_result = payment_result;
+        }
+        transfers.insert(key, transfer);
+    });
+}
+
+fn bearer(token: &str) -> ic_cdk::api::management_canister::http_request::HttpHeader {
+    header("Authorization", &format!("Bearer {}", token))
+}
+
+// Anchors send amounts and ids as strings, but numbers show up too
+fn str_field(value: &serde_json::Value, key: &str) -> Option<String> {
+    match value.get(key)? {
+        serde_json::Value::String(s) if !s.is_empty() => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
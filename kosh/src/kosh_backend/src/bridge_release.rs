@@ -0,0 +1,161 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+
+use crate::require_controller;
+
+// Completed releases are forgotten after this long. Neither indexer reads a lock event that
+// old again (getEvents keeps at most 7 days, and the EVM indexer only moves forward) unless
+// a controller rewinds it that far. In-flight and failed releases are always kept.
+const RELEASE_RETENTION_NANOS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+// The lock event a release pays out
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockSource {
+    // Soroban event ID from getEvents
+    Stellar { event_id: String },
+    Evm { chain_id: u64, tx_hash: String, log_index: u64 },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ReleaseStatus {
+    // Claimed, release call not answered yet; also left behind when the call trapped
+    InFlight,
+    // Hash of the release transaction
+    Released(String),
+    Failed(String),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BridgeRelease {
+    pub source: LockSource,
+    pub status: ReleaseStatus,
+    // Nanoseconds since the epoch
+    pub claimed_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+thread_local! {
+    // Every lock event a release was started for, whatever came of it
+    static RELEASES: RefCell<BTreeMap<LockSource, BridgeRelease>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub fn snapshot() -> Vec<BridgeRelease> {
+    RELEASES.with(|releases| releases.borrow().values().cloned().collect())
+}
+
+pub fn restore(saved: Vec<BridgeRelease>) {
+    RELEASES.with(|releases| {
+        *releases.borrow_mut() = saved.into_iter().map(|release| (release.source.clone(), release)).collect()
+    });
+}
+
+// Record `source` as processed unless it already is. Runs without awaiting, so the check
+// and the insert happen in the same message and no other call can slip in between.
+fn claim(source: &LockSource) -> bool {
+    let now = ic_cdk::api::time();
+    RELEASES.with(|releases| {
+        let mut releases = releases.borrow_mut();
+        prune(&mut releases, now);
+        if releases.contains_key(source) {
+            return false;
+        }
+        releases.insert(
+            source.clone(),
+            BridgeRelease { source: source.clone(), status: ReleaseStatus::InFlight, claimed_at: now, finished_at: None },
+        );
+        true
+    })
+}
+
+fn prune(releases: &mut BTreeMap<LockSource, BridgeRelease>, now: u64) {
+    releases.retain(|_, release| {
+        !matches!(release.status, ReleaseStatus::Released(_))
+            || release.finished_at.is_none_or(|finished_at| now.saturating_sub(finished_at) < RELEASE_RETENTION_NANOS)
+    });
+}
+
+fn finish(source: &LockSource, status: ReleaseStatus) {
+    RELEASES.with(|releases| {
+        if let Some(release) = releases.borrow_mut().get_mut(source) {
+            release.status = status;
+            release.finished_at = Some(ic_cdk::api::time());
+        }
+    });
+}
+
+// Run `release` unless a release for `source` was started before. The claim is committed
+// before `release` makes its first call, so a retry after a failure or a trap never pays twice;
+// failed releases wait for a controller to clear them with clear_failed_bridge_release.
+pub async fn release_once<F>(source: LockSource, release: F) -> Result<String, String>
+where
+    F: Future<Output = Result<String, String>>,
+{
+    if !claim(&source) {
+        return Err(format!("Lock {:?} was already processed", source));
+    }
+
+    let result = release.await;
+    finish(&source, match &result {
+        Ok(hash) => ReleaseStatus::Released(hash.clone()),
+        Err(e) => ReleaseStatus::Failed(e.clone()),
+    });
+    result
+}
+
+// Forget a failed release so its lock is released again the next time an indexer reads it
+// (after rewinding the indexer if needed). A failure can be reported after the transaction
+// was broadcast, so check the destination chain before clearing.
+#[ic_cdk::update]
+fn clear_failed_bridge_release(source: LockSource) -> Result<BridgeRelease, String> {
+    require_controller("clear bridge releases")?;
+    RELEASES.with(|releases| {
+        let mut releases = releases.borrow_mut();
+        match releases.get(&source) {
+            Some(BridgeRelease { status: ReleaseStatus::Failed(_), .. }) => Ok(releases.remove(&source).unwrap()),
+            Some(release) => Err(format!("Release for {:?} is {:?}, not failed", source, release.status)),
+            None => Err(format!("No release for {:?}", source)),
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn get_bridge_release(source: LockSource) -> Option<BridgeRelease> {
+    RELEASES.with(|releases| releases.borrow().get(&source).cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    fn release(event_id: &str, status: ReleaseStatus, finished_day: u64) -> (LockSource, BridgeRelease) {
+        let source = LockSource::Stellar { event_id: event_id.to_string() };
+        let release = BridgeRelease { source: source.clone(), status, claimed_at: 0, finished_at: Some(finished_day * DAY_NANOS) };
+        (source, release)
+    }
+
+    #[test]
+    fn only_old_completed_releases_are_pruned() {
+        let mut releases: BTreeMap<_, _> = [
+            release("old", ReleaseStatus::Released("0xa".to_string()), 1),
+            release("recent", ReleaseStatus::Released("0xb".to_string()), 20),
+            release("failed", ReleaseStatus::Failed("nonce too low".to_string()), 1),
+        ]
+        .into_iter()
+        .collect();
+        prune(&mut releases, 40 * DAY_NANOS);
+
+        let kept: Vec<&LockSource> = releases.keys().collect();
+        assert_eq!(
+            kept,
+            vec![
+                &LockSource::Stellar { event_id: "failed".to_string() },
+                &LockSource::Stellar { event_id: "recent".to_string() },
+            ]
+        );
+    }
+}
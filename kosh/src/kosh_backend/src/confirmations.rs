@@ -0,0 +1,245 @@
+use candid::Principal;
+use ic_cdk_timers::TimerId;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+use stellar_xdr::curr::{FeeBumpTransactionInnerTx, Preconditions, TimeBounds, TimePoint, TransactionEnvelope};
+
+use crate::anchor;
+use crate::providers;
+use crate::soroban;
+use crate::transform::Transform;
+use crate::tx_ledger::{self, TxRecord, TxStatus};
+use crate::tx_result::{self, SubmittedTransaction, TxError};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+// How long transactions the canister builds stay valid
+const VALIDITY_SECS: u64 = 300;
+// Time past a transaction's max_time for its last possible ledger to reach Horizon
+const EXPIRY_GRACE_SECS: u64 = 60;
+
+thread_local! {
+    static POLL_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static POLLING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Submit through Horizon's /transactions_async, which returns as soon as the transaction
+// is queued instead of waiting for it to make it into a ledger.
+// Returns None when the Horizon instance does not offer the endpoint.
+pub async fn post_transaction_async(signed_xdr: &str, network: &str) -> Result<Option<SubmittedTransaction>, TxError> {
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("tx", signed_xdr.trim())
+        .finish();
+
+    let response = providers::horizon_post_form(network, "/transactions_async", body, 50_000, Transform::HorizonAsyncSubmission).await?;
+
+    ic_cdk::println!("Async submission response ({}): {}", response.status, response.body);
+
+    if response.status == 404 || response.status == 405 {
+        return Ok(None);
+    }
+
+    let json: serde_json::Value = serde_json::from_str(&response.body)
+        .map_err(|e| format!("Failed to parse async submission response: {}", e))?;
+    let hash = json.get("hash").and_then(|h| h.as_str()).unwrap_or_default().to_string();
+    let tx_status = json.get("tx_status").and_then(|s| s.as_str()).unwrap_or_default();
+
+    match tx_status {
+        "PENDING" | "DUPLICATE" => Ok(Some(SubmittedTransaction {
+            explorer_url: crate::explorer_tx_url(network, &hash),
+            hash,
+            network: network.to_string(),
+            status: TxStatus::Pending,
+        })),
+        "TRY_AGAIN_LATER" => Err(TxError::retry_later("Stellar network is busy; try again shortly".to_string())),
+        _ => {
+            let error_result_xdr = json.get("errorResultXdr").and_then(|x| x.as_str()).unwrap_or_default();
+            Err(failure(error_result_xdr)
+                .unwrap_or_else(|| TxError::unknown(format!("Transaction rejected ({})", tx_status))))
+        }
+    }
+}
+
+// Time bounds for a new transaction: once they pass, a transaction that is not in a ledger
+// never will be, so the poller can report it expired
+pub fn validity_window() -> Preconditions {
+    Preconditions::Time(TimeBounds {
+        min_time: TimePoint(0),
+        max_time: TimePoint(now_secs() + VALIDITY_SECS),
+    })
+}
+
+// max_time of the transaction's time bounds; None when it can land at any time
+fn valid_until(envelope_xdr: &str) -> Option<u64> {
+    let cond = match soroban::from_xdr::<TransactionEnvelope>(envelope_xdr).ok()? {
+        TransactionEnvelope::Tx(envelope) => envelope.tx.cond,
+        TransactionEnvelope::TxFeeBump(envelope) => match envelope.tx.inner_tx {
+            FeeBumpTransactionInnerTx::Tx(inner) => inner.tx.cond,
+        },
+        TransactionEnvelope::TxV0(envelope) => {
+            return envelope.tx.time_bounds.map(|bounds| bounds.max_time.0).filter(|max_time| *max_time != 0)
+        }
+    };
+    let bounds = match cond {
+        Preconditions::Time(bounds) => Some(bounds),
+        Preconditions::V2(conditions) => conditions.time_bounds,
+        Preconditions::None => None,
+    }?;
+    Some(bounds.max_time.0).filter(|max_time| *max_time != 0)
+}
+
+// Why a record not yet in a ledger stops being polled, once it is past its deadline.
+// Transactions without a max_time (e.g. signed for a SEP-7 request) could still land later,
+// but are given up on after VALIDITY_SECS like the canister's own so pending records can't
+// pile up and cost an outcall every tick.
+fn expiry(record: &TxRecord, now: u64) -> Option<String> {
+    match valid_until(&record.envelope_xdr) {
+        Some(max_time) => (now > max_time + EXPIRY_GRACE_SECS).then(|| "expired".to_string()),
+        None => (now > record.submitted_at / 1_000_000_000 + VALIDITY_SECS + EXPIRY_GRACE_SECS)
+            .then(|| format!("not in a ledger after {} seconds; without a max_time it may still land", VALIDITY_SECS)),
+    }
+}
+
+fn now_secs() -> u64 {
+    ic_cdk::api::time() / 1_000_000_000
+}
+
+// Make sure the poller is running while any recorded transaction is still pending
+pub fn track_pending() {
+    let running = POLL_TIMER.with(|timer| timer.borrow().is_some());
+    if running || tx_ledger::pending().is_empty() {
+        return;
+    }
+
+    let timer_id = ic_cdk_timers::set_timer_interval(POLL_INTERVAL, || ic_cdk::spawn(poll_pending()));
+    POLL_TIMER.with(|timer| *timer.borrow_mut() = Some(timer_id));
+}
+
+// Clears the in-flight flag even when the pass traps, since ic_cdk drops the future then
+struct PassGuard;
+
+impl Drop for PassGuard {
+    fn drop(&mut self) {
+        POLLING.with(|polling| polling.set(false));
+    }
+}
+
+async fn poll_pending() {
+    // Outcalls can take longer than POLL_INTERVAL; the next tick skips while a pass runs
+    if POLLING.with(|polling| polling.replace(true)) {
+        return;
+    }
+    let _guard = PassGuard;
+
+    let pending = tx_ledger::pending();
+
+    if pending.is_empty() {
+        if let Some(timer_id) = POLL_TIMER.with(|timer| timer.borrow_mut().take()) {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+        return;
+    }
+
+    for (owner, record) in pending {
+        match fetch_confirmation(&record.hash, &record.network).await {
+            Ok(Some(status)) => update(owner, &record.hash, status),
+            Ok(None) => {
+                if let Some(reason) = expiry(&record, now_secs()) {
+                    update(owner, &record.hash, TxStatus::Failed(reason))
+                }
+            }
+            Err(e) => ic_cdk::println!("Confirmation check for {} failed: {}", record.hash, e),
+        }
+    }
+}
+
+// Final status from /transactions/{hash}, or None while it is not in a ledger yet
+async fn fetch_confirmation(hash: &str, network: &str) -> Result<Option<TxStatus>, String> {
+    let response = providers::horizon_get(network, &format!("/transactions/{}", hash), 50_000, Transform::HorizonTransaction).await?;
+
+    if response.status == 404 {
+        return Ok(None);
+    }
+    if !response.is_success() {
+        return Err(format!("HTTP {} {}", response.status, response.body));
+    }
+
+    let json: serde_json::Value = serde_json::from_str(&response.body)
+        .map_err(|e| format!("Failed to parse transaction: {}", e))?;
+
+    Ok(Some(match json.get("successful").and_then(|s| s.as_bool()) {
+        Some(true) => TxStatus::Success,
+        _ => {
+            let result_xdr = json.get("result_xdr").and_then(|x| x.as_str()).unwrap_or_default();
+            TxStatus::Failed(failure(result_xdr).map_or_else(|| "failed in ledger".to_string(), |e| e.message))
+        }
+    }))
+}
+
+pub fn failure(result_xdr: &str) -> Option<TxError> {
+    if result_xdr.is_empty() {
+        return None;
+    }
+    tx_result::decode_result_xdr(result_xdr).unwrap_or_else(|e| {
+        ic_cdk::println!("Could not decode result XDR: {}", e);
+        None
+    })
+}
+
+fn update(owner: Principal, hash: &str, status: TxStatus) {
+    ic_cdk::println!("Transaction {} is now {:?}", hash, status);
+    anchor::payment_settled(owner, hash, &status);
+    tx_ledger::update_status(owner, hash, status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx_ledger::TxKind;
+    use stellar_xdr::curr::{Memo, MuxedAccount, SequenceNumber, Transaction, TransactionExt, TransactionV1Envelope, Uint256};
+
+    fn envelope(cond: Preconditions) -> String {
+        let tx = Transaction {
+            source_account: MuxedAccount::Ed25519(Uint256([0; 32])),
+            fee: 100,
+            seq_num: SequenceNumber(1),
+            cond,
+            memo: Memo::None,
+            operations: Default::default(),
+            ext: TransactionExt::V0,
+        };
+        soroban::to_xdr(&TransactionEnvelope::Tx(TransactionV1Envelope { tx, signatures: Default::default() })).unwrap()
+    }
+
+    #[test]
+    fn only_bounded_transactions_expire() {
+        let bounded = Preconditions::Time(TimeBounds { min_time: TimePoint(0), max_time: TimePoint(1_700_000_300) });
+        assert_eq!(valid_until(&envelope(bounded)), Some(1_700_000_300));
+
+        let open_ended = Preconditions::Time(TimeBounds { min_time: TimePoint(0), max_time: TimePoint(0) });
+        assert_eq!(valid_until(&envelope(open_ended)), None);
+        assert_eq!(valid_until(&envelope(Preconditions::None)), None);
+        assert_eq!(valid_until("not xdr"), None);
+    }
+
+    #[test]
+    fn unbounded_transactions_expire_by_age() {
+        let record = |envelope_xdr: String| TxRecord {
+            hash: String::new(),
+            network: "testnet".to_string(),
+            kind: TxKind::Payment,
+            params: "{}".to_string(),
+            envelope_xdr,
+            submitted_at: 1_700_000_000 * 1_000_000_000,
+            status: TxStatus::Pending,
+        };
+        let unbounded = record(envelope(Preconditions::None));
+        assert_eq!(expiry(&unbounded, 1_700_000_300), None);
+        assert!(expiry(&unbounded, 1_700_000_361).is_some());
+
+        // A max_time wins over the age, either way
+        let bounded = Preconditions::Time(TimeBounds { min_time: TimePoint(0), max_time: TimePoint(1_700_003_600) });
+        let bounded = record(envelope(bounded));
+        assert_eq!(expiry(&bounded, 1_700_000_361), None);
+        assert_eq!(expiry(&bounded, 1_700_003_661), Some("expired".to_string()));
+    }
+}
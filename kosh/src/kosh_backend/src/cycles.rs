@@ -0,0 +1,191 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use crate::require_controller;
+
+// Fees from https://internetcomputer.org/docs/current/developer-docs/gas-cost, which
+// scale with the number of nodes in the subnet paying for them
+const REFERENCE_SUBNET_SIZE: u128 = 13;
+const DEFAULT_SUBNET_SIZE: u32 = 13;
+
+const HTTP_REQUEST_BASE: u128 = 3_000_000;
+const HTTP_REQUEST_PER_NODE: u128 = 60_000;
+const HTTP_REQUEST_PER_BYTE: u128 = 400;
+const HTTP_RESPONSE_PER_BYTE: u128 = 800;
+
+const SIGN_WITH_SCHNORR: u128 = 10_000_000_000;
+
+// The EVM RPC canister runs on the 34-node fiduciary subnet and bills each provider
+// request on top of forwarding it; unused cycles are refunded.
+const EVM_RPC_SUBNET_SIZE: u128 = 34;
+const EVM_RPC_INGRESS_BASE: u128 = 1_200_000;
+const EVM_RPC_INGRESS_PER_BYTE: u128 = 2_000;
+const EVM_RPC_INGRESS_OVERHEAD_BYTES: u128 = 100;
+const EVM_RPC_URL_BYTES: u128 = 256;
+const EVM_RPC_CANISTER_OVERHEAD: u128 = 1_000_000;
+const EVM_RPC_COLLATERAL_PER_NODE: u128 = 10_000_000;
+// Added by the EVM RPC canister to every response size estimate
+const EVM_RPC_HEADER_BYTES: u64 = 2 * 1024;
+
+// Upper bound for a JSON-RPC request body without large parameters
+pub const EVM_RPC_REQUEST_BYTES: u64 = 512;
+
+// Principal and endpoint pairs tracked one by one. Beyond this the smallest spender is folded
+// into its endpoint's bucket under the anonymous principal, so anyone creating principals
+// can't grow the table (or the upgrade snapshot it is saved in) without bound.
+const MAX_USAGE_ENTRIES: usize = 10_000;
+
+thread_local! {
+    static SUBNET_SIZE: Cell<u32> = const { Cell::new(DEFAULT_SUBNET_SIZE) };
+    static USAGE: RefCell<BTreeMap<(Principal, String), Usage>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+#[derive(Clone, Copy, Default)]
+struct Usage {
+    calls: u64,
+    cycles: u128,
+}
+
+// Cycles one principal spent on one kind of call, e.g. `horizon_account` or `sign_with_schnorr`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CyclesUsage {
+    pub principal: Principal,
+    pub endpoint: String,
+    pub calls: u64,
+    pub cycles: u128,
+}
+
+pub fn subnet_size() -> u32 {
+    SUBNET_SIZE.with(|size| size.get())
+}
+
+pub fn set_subnet_size(size: u32) -> Result<(), String> {
+    if size == 0 {
+        return Err("Subnet size must be positive".to_string());
+    }
+    SUBNET_SIZE.with(|current| current.set(size));
+    Ok(())
+}
+
+fn http_request_cost(nodes: u128, request_bytes: u128, max_response_bytes: u128) -> u128 {
+    (HTTP_REQUEST_BASE + HTTP_REQUEST_PER_NODE * nodes) * nodes
+        + HTTP_REQUEST_PER_BYTE * nodes * request_bytes
+        + HTTP_RESPONSE_PER_BYTE * nodes * max_response_bytes
+}
+
+// `request_bytes` counts the URL, headers, body and transform (method name and context)
+pub fn http_request(request_bytes: u64, max_response_bytes: u64) -> u128 {
+    http_request_cost(subnet_size() as u128, request_bytes as u128, max_response_bytes as u128)
+}
+
+pub fn sign_with_schnorr() -> u128 {
+    SIGN_WITH_SCHNORR * subnet_size() as u128 / REFERENCE_SUBNET_SIZE
+}
+
+// Mirrors the EVM RPC canister's own pricing for a JSON-RPC request sent to `providers`
+pub fn evm_rpc_request(providers: usize, request_bytes: u64, max_response_bytes: u64) -> u128 {
+    let nodes = EVM_RPC_SUBNET_SIZE;
+    let ingress_bytes = request_bytes as u128 + EVM_RPC_URL_BYTES + EVM_RPC_INGRESS_OVERHEAD_BYTES;
+    let per_node = EVM_RPC_INGRESS_BASE
+        + EVM_RPC_INGRESS_PER_BYTE * ingress_bytes
+        + HTTP_REQUEST_BASE
+        + HTTP_REQUEST_PER_NODE * nodes
+        + HTTP_REQUEST_PER_BYTE * request_bytes as u128
+        + HTTP_RESPONSE_PER_BYTE * (max_response_bytes + EVM_RPC_HEADER_BYTES) as u128
+        + EVM_RPC_CANISTER_OVERHEAD
+        + EVM_RPC_COLLATERAL_PER_NODE;
+
+    providers as u128 * per_node * nodes
+}
+
+// Charge what the last call actually kept of `attached` to the caller of this message.
+// Must run right after the call returns, before anything else refunds cycles.
+pub fn record(endpoint: &str, attached: u128) {
+    let spent = attached.saturating_sub(ic_cdk::api::call::msg_cycles_refunded128());
+    let caller = ic_cdk::api::caller();
+
+    USAGE.with(|usage| add(&mut usage.borrow_mut(), (caller, endpoint.to_string()), spent, MAX_USAGE_ENTRIES));
+}
+
+fn add(usage: &mut BTreeMap<(Principal, String), Usage>, key: (Principal, String), spent: u128, max_entries: usize) {
+    if !usage.contains_key(&key) && usage.len() >= max_entries {
+        let smallest = usage
+            .iter()
+            .filter(|((principal, _), _)| *principal != Principal::anonymous())
+            .min_by_key(|(_, entry)| entry.cycles)
+            .map(|(key, _)| key.clone());
+        if let Some(smallest) = smallest {
+            let dropped = usage.remove(&smallest).unwrap_or_default();
+            let other = usage.entry((Principal::anonymous(), smallest.1)).or_default();
+            other.calls += dropped.calls;
+            other.cycles += dropped.cycles;
+        }
+    }
+
+    let entry = usage.entry(key).or_default();
+    entry.calls += 1;
+    entry.cycles += spent;
+}
+
+pub fn snapshot() -> Vec<CyclesUsage> {
+    USAGE.with(|usage| {
+        usage
+            .borrow()
+            .iter()
+            .map(|((principal, endpoint), usage)| CyclesUsage {
+                principal: *principal,
+                endpoint: endpoint.clone(),
+                calls: usage.calls,
+                cycles: usage.cycles,
+            })
+            .collect()
+    })
+}
+
+pub fn restore(saved: Vec<CyclesUsage>) {
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        for entry in saved {
+            usage.insert(
+                (entry.principal, entry.endpoint),
+                Usage { calls: entry.calls, cycles: entry.cycles },
+            );
+        }
+    });
+}
+
+// Heaviest spenders first
+#[ic_cdk::query]
+fn get_cycles_usage() -> Result<Vec<CyclesUsage>, String> {
+    require_controller("read cycles usage")?;
+
+    let mut usage = snapshot();
+    usage.sort_by_key(|entry| Reverse(entry.cycles));
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smallest_spenders_fold_into_the_anonymous_bucket() {
+        let caller = |id: u8| Principal::from_slice(&[id]);
+        let mut usage = BTreeMap::new();
+        add(&mut usage, (caller(1), "horizon_account".to_string()), 500, 2);
+        add(&mut usage, (caller(2), "horizon_account".to_string()), 100, 2);
+        add(&mut usage, (caller(3), "sign_with_schnorr".to_string()), 300, 2);
+
+        assert_eq!(usage.len(), 3);
+        assert!(!usage.contains_key(&(caller(2), "horizon_account".to_string())));
+        let other = usage[&(Principal::anonymous(), "horizon_account".to_string())];
+        assert_eq!((other.calls, other.cycles), (1, 100));
+
+        // Totals are kept whatever gets folded
+        add(&mut usage, (caller(4), "horizon_account".to_string()), 50, 2);
+        assert_eq!(usage.values().map(|entry| entry.cycles).sum::<u128>(), 950);
+    }
+}
@@ -9,16 +9,18 @@ use k256::PublicKey;
 use sha2::Digest;
 use std::str::FromStr;
 
+use crate::cycles;
 use crate::evm_indexer::ChainService;
 use crate::evm_rpc_bindings::{EthSepoliaService, GetTransactionCountArgs, MultiSendRawTransactionResult, RpcApi, SendRawTransactionStatus};
 use crate::evm_rpc_bindings::MultiGetTransactionCountResult;
-use crate::evm_rpc_bindings::{BlockTag, RpcServices};
+use crate::evm_rpc_bindings::{BlockTag, RpcConfig, RpcServices};
 use ic_cdk::api::management_canister::ecdsa::{ecdsa_public_key, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument};
 use crate::evm_rpc_bindings::GetTransactionCountResult;
 use ic_cdk::api::management_canister::ecdsa::SignWithEcdsaResponse;
 use ic_cdk::api::management_canister::ecdsa::EcdsaPublicKeyResponse;
 use ic_cdk::api::management_canister::ecdsa::sign_with_ecdsa;
 use crate::evm_rpc_bindings::SendRawTransactionResult;
+use crate::rate_limit::guard_generate_key_pair_evm;
 use num_traits::ToPrimitive;
 
 use std::sync::RwLock;
@@ -28,6 +30,8 @@ static TX_HASH: Lazy<RwLock<Option<String>>> = Lazy::new(|| RwLock::new(None));
 
 
 const CONTRACT_ADDRESS_HEX: &str = "0x99a79158A40E4BEF8Beb3AcFAE893e62C45034E8";
+// Nonce and submission responses are a single short JSON-RPC result
+const SMALL_RESPONSE_BYTES: u64 = 1024;
 const EIP1559_TX_ID: u8 = 2;
 
 impl ChainService {
@@ -56,14 +60,14 @@ impl ChainService {
             block: block_tag,
         };
 
-        // Prepare cycles amount to pay for the call (adjust as necessary)
-        let cycles: u128 = 200_000_000_000u128;
+        let config = RpcConfig { responseConsensus: None, responseSizeEstimate: Some(SMALL_RESPONSE_BYTES) };
+        let cycles = cycles::evm_rpc_request(1, cycles::EVM_RPC_REQUEST_BYTES, SMALL_RESPONSE_BYTES);
 
         // The principal (canister ID) of the EVM RPC canister
         let evm_canister_id = self.evm_rpc.0;
 
         // Make cross-canister call with cycles payment
-        let (transaction_result,) = call_with_payment128::<
+        let result = call_with_payment128::<
             (RpcServices, Option<crate::evm_rpc_bindings::RpcConfig>, GetTransactionCountArgs),
             (MultiGetTransactionCountResult,),
         >(
@@ -77,13 +81,15 @@ impl ChainService {
                         headers: None,
                     }],
                 },
-                None,
+                Some(config),
                 get_transaction_count_args.clone(),
             ),
             cycles,
         )
-        .await
-        .map_err(|e| format!("Failed to get transaction count: {:?}", e))?;
+        .await;
+        cycles::record("evm_rpc:eth_getTransactionCount", cycles);
+
+        let (transaction_result,) = result.map_err(|e| format!("Failed to get transaction count: {:?}", e))?;
 
         // Handle possible result variants
         let transaction_count = match transaction_result {
@@ -146,12 +152,13 @@ impl ChainService {
         // 6. Build the EIP-1559 transaction request
         let tx = Eip1559TransactionRequest {
             from: None,
-            to: Some(H160::from_str(&to).unwrap().into()),
+            to: Some(H160::from_str(&to).map_err(|e| format!("Invalid recipient {}: {}", to, e))?.into()),
             nonce: Some(U256::from(nonce.0.to_u64().unwrap())),
             gas: Some(U256::from(gas_limit)),
             max_fee_per_gas: Some(U256::from(max_fee_per_gas)),
             max_priority_fee_per_gas: Some(U256::from(max_priority_fee_per_gas)),
-            value: Some(U256::from_str(&amount).unwrap()),
+            // from_str would read the amount as hex
+            value: Some(U256::from_dec_str(&amount).map_err(|e| format!("Invalid amount {}: {}", amount, e))?),
             data: Default::default(),
             access_list: vec![].into(),
             chain_id: Some(U64::from(17000u64))
@@ -186,10 +193,12 @@ impl ChainService {
 
         ic_cdk::println!("Raw signed transaction hex: {}", raw_tx_hex);
 
-        let cycles_to_pay: u128 = 600_000_000_000;
+        let config = RpcConfig { responseConsensus: None, responseSizeEstimate: Some(SMALL_RESPONSE_BYTES) };
+        let request_bytes = cycles::EVM_RPC_REQUEST_BYTES + raw_tx_hex.len() as u64;
+        let cycles_to_pay = cycles::evm_rpc_request(1, request_bytes, SMALL_RESPONSE_BYTES);
 
         // 13. Send the raw transaction using your evm_rpc canister's eth_sendRawTransaction
-        let (send_result,) = call_with_payment128::<
+        let result = call_with_payment128::<
         (RpcServices, Option<crate::evm_rpc_bindings::RpcConfig>, String),
         (MultiSendRawTransactionResult,),
     >(
@@ -203,13 +212,15 @@ impl ChainService {
                     headers: None,
                 }],
             },
-            None,
+            Some(config),
             raw_tx_hex,
         ),
         cycles_to_pay, // send cycles for payment here
     )
-    .await
-    .map_err(|e| format!("Failed to send raw transaction: {:?}", e))?;
+    .await;
+        cycles::record("evm_rpc:eth_sendRawTransaction", cycles_to_pay);
+
+        let (send_result,) = result.map_err(|e| format!("Failed to send raw transaction: {:?}", e))?;
 
         // 14. Parse result from send
         match send_result {
@@ -250,7 +261,7 @@ impl ChainService {
 
 
 
-#[update]
+#[update(guard = "guard_generate_key_pair_evm")]
 pub async fn generate_key_pair_evm() -> Result<String, String> {
     use ic_cdk::id;
 
@@ -359,7 +370,7 @@ pub async fn get_ecdsa_public_key() -> Result<EcdsaPublicKeyResponse, String> {
 }
 
 // Wrapper function to call ChainService send_eth_evm method
-pub async fn send_eth_evm(to: String, amount: f64, dest_chain: String) -> Result<String, String> {
+pub async fn send_eth_evm(to: String, amount_wei: u128, dest_chain: String) -> Result<String, String> {
     use crate::evm_indexer::{ChainService, CHAIN_SERVICE};
     
 
@@ -373,10 +384,7 @@ pub async fn send_eth_evm(to: String, amount: f64, dest_chain: String) -> Result
     });
     
     if let Some(service) = chain_service {
-        // Convert amount from f64 to string (in wei)
-
-        
-        service.send_eth_evm(to, amount.to_string(), dest_chain).await
+        service.send_eth_evm(to, amount_wei.to_string(), dest_chain).await
     } else {
         Err("Failed to initialize chain service".to_string())
     }
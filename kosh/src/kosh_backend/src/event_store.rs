@@ -0,0 +1,303 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::soroban::ContractValue;
+use crate::stellar_indexer::CandidContractEvent;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+// Events looked at per query, so filters that match rarely cannot exhaust the instruction
+// limit; the page then ends early with a cursor to carry on from
+const MAX_SCANNED: usize = 10_000;
+// Oldest events are dropped beyond this many, which keeps the store (and the upgrade
+// snapshot it is saved in) bounded
+const MAX_EVENTS: usize = 50_000;
+
+// Every field narrows the result; leave them out to match all events
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct EventQuery {
+    pub contract_id: Option<String>,
+    // Leading topics the event must start with
+    pub topic_prefix: Option<Vec<ContractValue>>,
+    // Inclusive ledger range
+    pub from_ledger: Option<u32>,
+    pub to_ledger: Option<u32>,
+    // Recipient of bridge lock events
+    pub recipient: Option<String>,
+    // `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EventPage {
+    // Newest first
+    pub events: Vec<CandidContractEvent>,
+    // Set while older events may match
+    pub next_cursor: Option<String>,
+}
+
+// Events keyed by their ID, which sorts by ledger and position in the ledger, with the
+// IDs of each contract's and each recipient's events alongside
+#[derive(Default)]
+struct EventStore {
+    events: BTreeMap<String, CandidContractEvent>,
+    by_contract: BTreeSet<(String, String)>,
+    by_recipient: BTreeSet<(String, String)>,
+}
+
+thread_local! {
+    static STORE: RefCell<EventStore> = RefCell::new(EventStore::default());
+}
+
+// EVM addresses are checksummed in mixed case, so recipients are compared in lowercase
+fn recipient_key(recipient: &str) -> String {
+    recipient.to_lowercase()
+}
+
+// Lowest event ID in `ledger`: the ID is the 19 digit TOID (ledger << 32 | ...) and
+// an event index, and the bare TOID sorts before every ID that starts with it
+fn ledger_bound(ledger: u64) -> String {
+    format!("{:019}", ledger << 32)
+}
+
+impl EventStore {
+    fn insert(&mut self, event: CandidContractEvent) {
+        self.by_contract.insert((event.contract_id.clone(), event.id.clone()));
+        if let Some(lock) = &event.bridge_lock {
+            self.by_recipient.insert((recipient_key(&lock.recipient_address), event.id.clone()));
+        }
+        self.events.insert(event.id.clone(), event);
+        self.prune(MAX_EVENTS);
+    }
+
+    fn prune(&mut self, max_events: usize) {
+        while self.events.len() > max_events {
+            let Some((id, event)) = self.events.pop_first() else {
+                return;
+            };
+            self.by_contract.remove(&(event.contract_id, id.clone()));
+            if let Some(lock) = &event.bridge_lock {
+                self.by_recipient.remove(&(recipient_key(&lock.recipient_address), id));
+            }
+        }
+    }
+
+    fn query(&self, query: &EventQuery) -> Result<EventPage, String> {
+        if let (Some(from), Some(to)) = (query.from_ledger, query.to_ledger) {
+            if from > to {
+                return Err(format!("from_ledger {} is after to_ledger {}", from, to));
+            }
+        }
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+
+        let lower = ledger_bound(query.from_ledger.unwrap_or(0) as u64);
+        let mut upper = query
+            .to_ledger
+            .filter(|to| *to < u32::MAX)
+            .map_or_else(|| "9".repeat(20), |to| ledger_bound(to as u64 + 1));
+        if let Some(cursor) = &query.cursor {
+            upper = upper.min(cursor.clone());
+        }
+        if lower >= upper {
+            return Ok(EventPage { events: vec![], next_cursor: None });
+        }
+
+        let recipient = query.recipient.as_deref().map(recipient_key);
+        let mut ids: Box<dyn Iterator<Item = &String>> = match (&recipient, &query.contract_id) {
+            (Some(recipient), _) => Box::new(indexed(&self.by_recipient, recipient, &lower, &upper)),
+            (None, Some(contract_id)) => Box::new(indexed(&self.by_contract, contract_id, &lower, &upper)),
+            (None, None) => Box::new(self.events.range(lower.clone()..upper.clone()).rev().map(|(id, _)| id)),
+        };
+
+        let mut events = Vec::new();
+        let mut last_scanned = None;
+        for _ in 0..MAX_SCANNED {
+            let Some(id) = ids.next() else {
+                return Ok(EventPage { events, next_cursor: None });
+            };
+            last_scanned = Some(id);
+            let Some(event) = self.events.get(id) else {
+                continue;
+            };
+            if matches(event, query, recipient.as_deref()) {
+                events.push(event.clone());
+                if events.len() == limit {
+                    return Ok(EventPage { events, next_cursor: Some(id.clone()) });
+                }
+            }
+        }
+
+        // Scan limit reached; the next page carries on below the last event looked at
+        let next_cursor = ids.next().and(last_scanned).cloned();
+        Ok(EventPage { events, next_cursor })
+    }
+}
+
+// IDs under `key` in [lower, upper), newest first
+fn indexed<'a>(
+    index: &'a BTreeSet<(String, String)>,
+    key: &str,
+    lower: &str,
+    upper: &str,
+) -> impl Iterator<Item = &'a String> {
+    index
+        .range((key.to_string(), lower.to_string())..(key.to_string(), upper.to_string()))
+        .rev()
+        .map(|(_, id)| id)
+}
+
+fn matches(event: &CandidContractEvent, query: &EventQuery, recipient: Option<&str>) -> bool {
+    query.contract_id.as_ref().is_none_or(|contract_id| event.contract_id == *contract_id)
+        && query
+            .topic_prefix
+            .as_ref()
+            .is_none_or(|prefix| event.topic_values.starts_with(prefix))
+        && recipient.is_none_or(|recipient| {
+            event
+                .bridge_lock
+                .as_ref()
+                .is_some_and(|lock| recipient_key(&lock.recipient_address) == recipient)
+        })
+}
+
+pub fn insert(event: CandidContractEvent) {
+    STORE.with(|store| store.borrow_mut().insert(event));
+}
+
+pub fn snapshot() -> Vec<CandidContractEvent> {
+    STORE.with(|store| store.borrow().events.values().cloned().collect())
+}
+
+pub fn restore(events: Vec<CandidContractEvent>) {
+    STORE.with(|store| {
+        let mut store = store.borrow_mut();
+        *store = EventStore::default();
+        for event in events {
+            store.insert(event);
+        }
+    });
+}
+
+#[ic_cdk::query]
+fn get_events(query: EventQuery) -> Result<EventPage, String> {
+    STORE.with(|store| store.borrow().query(&query))
+}
+
+#[ic_cdk::query]
+fn get_event_by_id(id: String) -> Option<CandidContractEvent> {
+    STORE.with(|store| store.borrow().events.get(&id).cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stellar_indexer::BridgeLockEvent;
+
+    const BRIDGE: &str = "CDTA5IYGUGRI4PAGXJL7TPBEIC3EZY6V23ILF5EDVXFVLCGGMVOK4CRL";
+    const TOKEN: &str = "CDLZFC3SYJYDZT7K67VZ75HPJVIEUVNIXF47ZG2FB2RMQQVU2HHGCYSC";
+
+    fn event(contract_id: &str, ledger: u32, index: u32, topic: &str, recipient: Option<&str>) -> CandidContractEvent {
+        let id = format!("{:019}-{:010}", (ledger as u64) << 32 | 1 << 12, index);
+        CandidContractEvent {
+            contract_id: contract_id.to_string(),
+            id: id.clone(),
+            ledger,
+            tx_hash: "00".repeat(32),
+            topic: vec![],
+            xdr_value: String::new(),
+            paging_token: id,
+            topic_values: vec![ContractValue::Symbol(topic.to_string())],
+            value: ContractValue::Void,
+            bridge_lock: recipient.map(|recipient| BridgeLockEvent {
+                from_token: TOKEN.to_string(),
+                dest_token: "ETH".to_string(),
+                in_amount: 1,
+                dest_chain: 11_155_111,
+                recipient_address: recipient.to_string(),
+            }),
+        }
+    }
+
+    fn store() -> EventStore {
+        let mut store = EventStore::default();
+        store.insert(event(BRIDGE, 100, 0, "lock", Some("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed")));
+        store.insert(event(TOKEN, 100, 1, "transfer", None));
+        store.insert(event(BRIDGE, 105, 0, "release", None));
+        store.insert(event(BRIDGE, 110, 0, "lock", Some("0x0000000000000000000000000000000000000001")));
+        store.insert(event(BRIDGE, 120, 0, "lock", Some("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed")));
+        store
+    }
+
+    fn ledgers(page: &EventPage) -> Vec<u32> {
+        page.events.iter().map(|event| event.ledger).collect()
+    }
+
+    #[test]
+    fn pages_run_newest_first() {
+        let store = store();
+        let first = store.query(&EventQuery { limit: Some(2), ..Default::default() }).unwrap();
+        assert_eq!(ledgers(&first), vec![120, 110]);
+
+        let second = store
+            .query(&EventQuery { limit: Some(2), cursor: first.next_cursor, ..Default::default() })
+            .unwrap();
+        assert_eq!(ledgers(&second), vec![105, 100]);
+
+        let last = store
+            .query(&EventQuery { limit: Some(2), cursor: second.next_cursor, ..Default::default() })
+            .unwrap();
+        assert_eq!(ledgers(&last), vec![100]);
+        assert_eq!(last.next_cursor, None);
+    }
+
+    #[test]
+    fn ledger_range_is_inclusive() {
+        let page = store()
+            .query(&EventQuery { from_ledger: Some(100), to_ledger: Some(110), ..Default::default() })
+            .unwrap();
+        assert_eq!(ledgers(&page), vec![110, 105, 100, 100]);
+
+        let reversed = EventQuery { from_ledger: Some(110), to_ledger: Some(100), ..Default::default() };
+        assert!(store().query(&reversed).is_err());
+
+        let open_ended = store()
+            .query(&EventQuery { from_ledger: Some(110), to_ledger: Some(u32::MAX), ..Default::default() })
+            .unwrap();
+        assert_eq!(ledgers(&open_ended), vec![120, 110]);
+    }
+
+    #[test]
+    fn pruning_drops_the_oldest_events_and_their_index_entries() {
+        let mut store = store();
+        store.prune(2);
+        assert_eq!(ledgers(&store.query(&EventQuery::default()).unwrap()), vec![120, 110]);
+        assert_eq!(store.by_contract.len(), 2);
+        assert_eq!(store.by_recipient.len(), 2);
+    }
+
+    #[test]
+    fn filters_combine() {
+        let store = store();
+        let by_recipient = store
+            .query(&EventQuery {
+                recipient: Some("0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(ledgers(&by_recipient), vec![120, 100]);
+
+        let locks = store
+            .query(&EventQuery {
+                contract_id: Some(BRIDGE.to_string()),
+                topic_prefix: Some(vec![ContractValue::Symbol("lock".to_string())]),
+                to_ledger: Some(115),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(ledgers(&locks), vec![110, 100]);
+        assert!(locks.events.iter().all(|event| event.contract_id == BRIDGE));
+    }
+}
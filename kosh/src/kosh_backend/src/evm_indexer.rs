@@ -14,13 +14,16 @@ use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 
 
+use crate::bridge_release::{self, LockSource};
 use crate::build_stellar_transaction;
+use crate::cycles;
 use crate::eth::send_eth_evm;
 use crate::evm_rpc_bindings::{
     BlockTag,
     GetBlockByNumberResult,
     GetLogsArgs,
     GetLogsResult,
+    GetLogsRpcConfig,
     MultiGetBlockByNumberResult,
     MultiGetLogsResult,
     RpcApi,
@@ -28,6 +31,7 @@ use crate::evm_rpc_bindings::{
     RpcServices,
     Service as EvmRpcService, // This is your interface to the canister
 };
+use crate::rate_limit::guard_start_periodic_fetch;
 
 
 
@@ -57,6 +61,32 @@ thread_local! {
 
 
 
+// Holesky testnet, where TokenLocked is emitted
+const SOURCE_CHAIN_ID: u64 = 17000;
+
+// Stellar network a TokenLocked `destChain` releases on. The contract takes any string, so
+// only these names are honoured; anything else is refused rather than released on a default.
+fn stellar_network(dest_chain: &str) -> Result<&'static str, String> {
+    match dest_chain.trim().to_ascii_lowercase().as_str() {
+        "stellar" | "stellar-testnet" | "testnet" => Ok("testnet"),
+        "stellar-mainnet" | "stellar-public" | "mainnet" | "public" => Ok("mainnet"),
+        "stellar-futurenet" | "futurenet" => Ok("futurenet"),
+        _ => Err(format!("Destination chain {:?} is not a Stellar network", dest_chain)),
+    }
+}
+
+// Response size estimates the EVM RPC canister sizes (and bills) its outcalls by
+const LOGS_RESPONSE_BYTES: u64 = 256 * 1024;
+const BLOCK_RESPONSE_BYTES: u64 = 128 * 1024;
+
+fn logs_config() -> GetLogsRpcConfig {
+    GetLogsRpcConfig {
+        responseConsensus: None,
+        maxBlockRange: None,
+        responseSizeEstimate: Some(LOGS_RESPONSE_BYTES),
+    }
+}
+
 thread_local! {
     pub static CHAIN_SERVICE: RefCell<Option<ChainService>> = RefCell::new(None);
 }
@@ -132,27 +162,29 @@ impl ChainService {
         };
 
         let rpc_providers = RpcServices::Custom {
-            chainId: 17000, // Holesky testnet chain id
+            chainId: SOURCE_CHAIN_ID,
             services: vec![RpcApi {
                 url: "https://ethereum-holesky-rpc.publicnode.com".to_string(),
                 headers: None,
             }],
         };
 
-        let cycles = 100_000_000_000u128;
+        let cycles = cycles::evm_rpc_request(1, cycles::EVM_RPC_REQUEST_BYTES, LOGS_RESPONSE_BYTES);
 
         // Call eth_getLogs RPC with payment cycles
-        let (result,) = ic_cdk::api::call::call_with_payment128::<
-            (RpcServices, Option<RpcConfig>, GetLogsArgs),
+        let result = ic_cdk::api::call::call_with_payment128::<
+            (RpcServices, Option<GetLogsRpcConfig>, GetLogsArgs),
             (MultiGetLogsResult,),
         >(
             self.evm_rpc.0,
             "eth_getLogs",
-            (rpc_providers, None, get_logs_args),
+            (rpc_providers, Some(logs_config()), get_logs_args),
             cycles,
         )
-        .await
-        .map_err(|e| format!("🧨 Call failed: {:?}", e))?;
+        .await;
+        cycles::record("evm_rpc:eth_getLogs", cycles);
+
+        let (result,) = result.map_err(|e| format!("🧨 Call failed: {:?}", e))?;
 
         let logs = match result {
             MultiGetLogsResult::Consistent(GetLogsResult::Ok(logs)) => logs,
@@ -214,8 +246,22 @@ impl ChainService {
                
                // Pass the amount as-is (in wei)
             //    let txn_hash = send_eth_evm(to_address.clone(), amount as f64, dest_chain.clone()).await;
-               let txn_hash=build_stellar_transaction(to_address.clone(), amount, Some(dest_chain.clone())).await;
-               ic_cdk::println!("txn_hash: {:?}", txn_hash);
+               match log_entry.logIndex.clone().map(Self::nat_to_u64) {
+                   Some(log_index) if tx_hash != "N/A" => {
+                       let source = LockSource::Evm { chain_id: SOURCE_CHAIN_ID, tx_hash: tx_hash.clone(), log_index };
+                       let release = async {
+                           let network = stellar_network(&dest_chain)?;
+                           build_stellar_transaction(to_address.clone(), amount, Some(network.to_string()))
+                               .await
+                               .map(|transaction| transaction.hash)
+                               .map_err(|e| e.message)
+                       };
+                       let txn_hash = bridge_release::release_once(source, release).await;
+                       ic_cdk::println!("txn_hash: {:?}", txn_hash);
+                   }
+                   // Pending logs have no hash or index yet; they come back once mined
+                   _ => ic_cdk::println!("Skipping release for pending log in {}", tx_hash),
+               }
 
  
 
@@ -315,7 +361,7 @@ impl ChainService {
         ic_cdk::println!("🚀 fetch_logs started...");
 
         let rpc_providers = RpcServices::Custom {
-            chainId: 17000,
+            chainId: SOURCE_CHAIN_ID,
             services: vec![RpcApi {
                 url: "https://ethereum-holesky-rpc.publicnode.com".to_string(),
                 headers: None,
@@ -331,19 +377,21 @@ impl ChainService {
 
         ic_cdk::println!("📦 get_logs_args: {:?}", get_logs_args);
 
-        let cycles = 100_000_000_000u128;
+        let cycles = cycles::evm_rpc_request(1, cycles::EVM_RPC_REQUEST_BYTES, LOGS_RESPONSE_BYTES);
 
-        let (result,) = ic_cdk::api::call::call_with_payment128::<
-            (RpcServices, Option<RpcConfig>, GetLogsArgs),
+        let result = ic_cdk::api::call::call_with_payment128::<
+            (RpcServices, Option<GetLogsRpcConfig>, GetLogsArgs),
             (MultiGetLogsResult,),
         >(
             self.evm_rpc.0,
             "eth_getLogs",
-            (rpc_providers, None, get_logs_args),
+            (rpc_providers, Some(logs_config()), get_logs_args),
             cycles,
         )
-        .await
-        .map_err(|e| format!("🧨 Call failed: {:?}", e))?;
+        .await;
+        cycles::record("evm_rpc:eth_getLogs", cycles);
+
+        let (result,) = result.map_err(|e| format!("🧨 Call failed: {:?}", e))?;
 
         ic_cdk::println!("📨 LOGS_RESULT: {:?}", result);
 
@@ -460,24 +508,26 @@ impl ChainService {
         ic_cdk::println!("About to call eth_get_block_by_number");
 
         let rpc_services = RpcServices::Custom {
-            chainId: 17000,
+            chainId: SOURCE_CHAIN_ID,
             services: vec![RpcApi {
                 url: "https://ethereum-holesky-rpc.publicnode.com".to_string(),
                 headers: None,
             }],
         };
 
-        let cycles = 8_000_000_000_000u128;
+        let config = RpcConfig { responseConsensus: None, responseSizeEstimate: Some(BLOCK_RESPONSE_BYTES) };
+        let cycles = cycles::evm_rpc_request(1, cycles::EVM_RPC_REQUEST_BYTES, BLOCK_RESPONSE_BYTES);
 
         // Only call once
         let result: Result<(MultiGetBlockByNumberResult,), _> =
-            call_with_payment128::<(RpcServices, (), BlockTag), (MultiGetBlockByNumberResult,)>(
+            call_with_payment128::<(RpcServices, Option<RpcConfig>, BlockTag), (MultiGetBlockByNumberResult,)>(
                 self.evm_rpc.0,
                 "eth_getBlockByNumber",
-                (rpc_services, (), BlockTag::Latest),
+                (rpc_services, Some(config), BlockTag::Latest),
                 cycles,
             )
             .await;
+        cycles::record("evm_rpc:eth_getBlockByNumber", cycles);
 
         // Handle result in a single match
         // let highest_block_number: u64 = match result {
@@ -563,7 +613,7 @@ impl ChainService {
 
 
 
-#[update]
+#[update(guard = "guard_start_periodic_fetch")]
 async fn start_periodic_fetch(block_number: u64) {
     let service = CHAIN_SERVICE.with(|service_cell| {
         let mut service = service_cell.borrow_mut();
@@ -579,5 +629,22 @@ async fn start_periodic_fetch(block_number: u64) {
     }
 }
 
-
-
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dest_chain_maps_to_registered_networks() {
+        assert_eq!(stellar_network("stellar"), Ok("testnet"));
+        assert_eq!(stellar_network(" Stellar-Mainnet "), Ok("mainnet"));
+        assert_eq!(stellar_network("futurenet"), Ok("futurenet"));
+        assert!(stellar_network("17000").is_err());
+        assert!(stellar_network("").is_err());
+
+        // Every name it produces is one build_stellar_transaction accepts
+        let registered: Vec<String> = crate::network::snapshot().into_iter().map(|config| config.name).collect();
+        for dest_chain in ["stellar", "mainnet", "futurenet"] {
+            assert!(registered.contains(&stellar_network(dest_chain).unwrap().to_string()));
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use candid::CandidType;
+use serde::Deserialize;
+
+use crate::outcall;
+use crate::transform::Transform;
+use crate::stellar_toml::fetch_stellar_toml;
+use crate::{decode_stellar_address, parse_memo};
+use crate::rate_limit::guard_resolve_federation_address;
+
+const FEDERATION_SERVER: &str = "FEDERATION_SERVER";
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FederationRecord {
+    pub stellar_address: Option<String>,
+    pub account_id: String,
+    pub memo_type: Option<String>,
+    pub memo: Option<String>,
+}
+
+impl FederationRecord {
+    // Memo the federation server requires on payments to this address
+    pub fn memo(&self) -> Result<stellar_xdr::curr::Memo, String> {
+        parse_memo(self.memo_type.as_deref(), self.memo.as_deref())
+    }
+
+    pub fn has_memo(&self) -> bool {
+        self.memo.as_deref().is_some_and(|memo| !memo.is_empty())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct FederationResponse {
+    stellar_address: Option<String>,
+    account_id: String,
+    memo_type: Option<String>,
+    // Servers send ids as either strings or numbers
+    memo: Option<serde_json::Value>,
+}
+
+#[ic_cdk::update(guard = "guard_resolve_federation_address")]
+async fn resolve_federation_address(address: String) -> Result<FederationRecord, String> {
+    resolve_destination(&address).await
+}
+
+// Accepts a G... account or a `name*domain.com` federation address (SEP-2)
+pub async fn resolve_destination(destination: &str) -> Result<FederationRecord, String> {
+    let destination = destination.trim();
+
+    let Some((_, domain)) = destination.rsplit_once('*') else {
+        decode_stellar_address(destination)?;
+        return Ok(FederationRecord {
+            stellar_address: None,
+            account_id: destination.to_string(),
+            memo_type: None,
+            memo: None,
+        });
+    };
+
+    let toml = fetch_stellar_toml(domain).await?;
+    let server = toml.require(FEDERATION_SERVER)?;
+    let url = url::Url::parse_with_params(server, &[("q", destination), ("type", "name")])
+        .map_err(|e| format!("Invalid FEDERATION_SERVER {}: {}", server, e))?;
+
+    ic_cdk::println!("Resolving federation address {} via {}", destination, server);
+    let response = outcall::get(url.as_str(), vec![], 10_000, Transform::Headers).await?;
+    if response.status == 404 {
+        return Err(format!("Federation address not found: {}", destination));
+    }
+    if !response.is_success() {
+        return Err(format!("Federation lookup failed: HTTP {} {}", response.status, response.body));
+    }
+
+    let record: FederationResponse = serde_json::from_str(&response.body)
+        .map_err(|e| format!("Failed to parse federation response: {}", e))?;
+    decode_stellar_address(&record.account_id)
+        .map_err(|e| format!("Federation server returned an invalid account: {}", e))?;
+
+    let memo = record.memo.and_then(|memo| match memo {
+        serde_json::Value::String(memo) => Some(memo),
+        serde_json::Value::Number(memo) => Some(memo.to_string()),
+        _ => None,
+    });
+
+    let record = FederationRecord {
+        stellar_address: record.stellar_address.or_else(|| Some(destination.to_string())),
+        account_id: record.account_id,
+        memo_type: record.memo_type,
+        memo,
+    };
+    // Fail here rather than at payment time if the memo can't be used
+    record.memo()?;
+
+    ic_cdk::println!("{} resolved to {}", destination, record.account_id);
+    Ok(record)
+}
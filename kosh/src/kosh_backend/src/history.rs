@@ -0,0 +1,239 @@
+use candid::CandidType;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::network;
+use crate::providers;
+use crate::transform::Transform;
+use crate::public_key_stellar;
+use crate::rate_limit::guard_get_transaction_history;
+
+const DEFAULT_PAGE_SIZE: u32 = 20;
+// Horizon caps page size at 200
+const MAX_PAGE_SIZE: u32 = 200;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum HistoryKind {
+    Payment,
+    PathPayment,
+    CreateAccount,
+    ChangeTrust,
+    Offer,
+    AccountMerge,
+    Other(String),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum HistoryDirection {
+    Incoming,
+    Outgoing,
+    // Operations on the account itself: trustlines, offers, payments to self
+    Internal,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HistoryRecord {
+    pub id: String,
+    pub paging_token: String,
+    pub transaction_hash: String,
+    pub kind: HistoryKind,
+    pub direction: HistoryDirection,
+    pub counterparty: Option<String>,
+    // "XLM" or "CODE:ISSUER"
+    pub asset: Option<String>,
+    pub amount: Option<String>,
+    // Path payments: what was sent; offers: what is bought
+    pub counter_asset: Option<String>,
+    // Path payments only
+    pub counter_amount: Option<String>,
+    // Offers: the offer price, as Horizon reports it for the operation type
+    pub price: Option<String>,
+    pub memo: Option<String>,
+    pub memo_type: Option<String>,
+    pub created_at: String,
+    pub successful: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HistoryPage {
+    pub address: String,
+    pub records: Vec<HistoryRecord>,
+    // Pass back as `cursor` for the next page; None once the history is exhausted
+    pub next_cursor: Option<String>,
+}
+
+// Operation or payment history of the caller's account, newest first by default
+#[ic_cdk::update(guard = "guard_get_transaction_history")]
+async fn get_transaction_history(
+    cursor: Option<String>,
+    limit: Option<u32>,
+    payments_only: Option<bool>,
+    ascending: Option<bool>,
+    network: Option<String>,
+) -> Result<HistoryPage, String> {
+    let network = network::resolve(network)?;
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let endpoint = if payments_only.unwrap_or(false) { "payments" } else { "operations" };
+    let order = if ascending.unwrap_or(false) { "asc" } else { "desc" };
+
+    let address = public_key_stellar().await?;
+
+    let mut params = vec![
+        ("limit", limit.to_string()),
+        ("order", order.to_string()),
+        ("join", "transactions".to_string()),
+    ];
+    if let Some(cursor) = cursor {
+        params.push(("cursor", cursor));
+    }
+
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(&params)
+        .finish();
+    let path = format!("/accounts/{}/{}?{}", address, endpoint, query);
+
+    let response = providers::horizon_get(&network, &path, 400_000, Transform::HorizonHistory).await?;
+    if response.status == 404 {
+        return Ok(HistoryPage { address, records: vec![], next_cursor: None });
+    }
+    if !response.is_success() {
+        return Err(format!("Horizon history request failed: HTTP {} {}", response.status, response.body));
+    }
+
+    let body: Value = serde_json::from_str(&response.body)
+        .map_err(|e| format!("Failed to parse history response: {}", e))?;
+    let raw_records = body["_embedded"]["records"].as_array().cloned().unwrap_or_default();
+
+    let records: Vec<HistoryRecord> = raw_records
+        .iter()
+        .map(|record| normalize_record(record, &address))
+        .collect();
+
+    let next_cursor = if raw_records.len() as u32 == limit {
+        records.last().map(|record| record.paging_token.clone())
+    } else {
+        None
+    };
+
+    Ok(HistoryPage { address, records, next_cursor })
+}
+
+fn normalize_record(record: &Value, address: &str) -> HistoryRecord {
+    let field = |key: &str| record.get(key).and_then(|v| v.as_str()).map(|v| v.to_string());
+    let op_type = field("type").unwrap_or_default();
+
+    // Incoming if we are the receiving side, outgoing if we sent it to someone else
+    let transfer_direction = |from: Option<String>, to: Option<String>| {
+        let from_us = from.as_deref() == Some(address);
+        let to_us = to.as_deref() == Some(address);
+        match (from_us, to_us) {
+            (true, true) => (HistoryDirection::Internal, None),
+            (false, true) => (HistoryDirection::Incoming, from),
+            _ => (HistoryDirection::Outgoing, to),
+        }
+    };
+
+    let (kind, direction, counterparty, asset, amount, counter_asset, counter_amount) = match op_type.as_str() {
+        "payment" => {
+            let (direction, counterparty) = transfer_direction(field("from"), field("to"));
+            (HistoryKind::Payment, direction, counterparty, asset_label(record, ""), field("amount"), None, None)
+        }
+        "path_payment_strict_send" | "path_payment_strict_receive" => {
+            let (direction, counterparty) = transfer_direction(field("from"), field("to"));
+            (
+                HistoryKind::PathPayment,
+                direction,
+                counterparty,
+                asset_label(record, ""),
+                field("amount"),
+                asset_label(record, "source_"),
+                field("source_amount"),
+            )
+        }
+        "create_account" => {
+            let (direction, counterparty) = transfer_direction(field("funder"), field("account"));
+            (
+                HistoryKind::CreateAccount,
+                direction,
+                counterparty,
+                Some("XLM".to_string()),
+                field("starting_balance"),
+                None,
+                None,
+            )
+        }
+        "change_trust" => (
+            HistoryKind::ChangeTrust,
+            HistoryDirection::Internal,
+            field("asset_issuer"),
+            asset_label(record, ""),
+            field("limit"),
+            None,
+            None,
+        ),
+        "manage_sell_offer" | "manage_buy_offer" | "create_passive_sell_offer" => (
+            HistoryKind::Offer,
+            HistoryDirection::Internal,
+            None,
+            asset_label(record, "selling_"),
+            field("amount"),
+            asset_label(record, "buying_"),
+            None,
+        ),
+        "account_merge" => {
+            let (direction, counterparty) = transfer_direction(field("account"), field("into"));
+            (HistoryKind::AccountMerge, direction, counterparty, Some("XLM".to_string()), None, None, None)
+        }
+        other => (
+            HistoryKind::Other(other.to_string()),
+            HistoryDirection::Internal,
+            None,
+            None,
+            None,
+            None,
+            None,
+        ),
+    };
+
+    let price = if matches!(kind, HistoryKind::Offer) { field("price") } else { None };
+
+    let transaction = record.get("transaction");
+    let tx_field = |key: &str| {
+        transaction
+            .and_then(|tx| tx.get(key))
+            .and_then(|v| v.as_str())
+            .map(|v| v.to_string())
+    };
+    let memo_type = tx_field("memo_type").filter(|memo_type| memo_type != "none");
+
+    HistoryRecord {
+        id: field("id").unwrap_or_default(),
+        paging_token: field("paging_token").unwrap_or_default(),
+        transaction_hash: field("transaction_hash").unwrap_or_default(),
+        kind,
+        direction,
+        counterparty,
+        asset,
+        amount,
+        counter_asset,
+        counter_amount,
+        price,
+        memo: memo_type.as_ref().and_then(|_| tx_field("memo")),
+        memo_type,
+        created_at: field("created_at").unwrap_or_default(),
+        successful: record
+            .get("transaction_successful")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true),
+    }
+}
+
+// Horizon spreads assets over `{prefix}asset_type`, `{prefix}asset_code` and `{prefix}asset_issuer`
+fn asset_label(record: &Value, prefix: &str) -> Option<String> {
+    let get = |key: &str| record.get(format!("{}{}", prefix, key)).and_then(|v| v.as_str());
+
+    match get("asset_type")? {
+        "native" => Some("XLM".to_string()),
+        _ => Some(format!("{}:{}", get("asset_code")?, get("asset_issuer")?)),
+    }
+}
@@ -0,0 +1,151 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::outcall::OutcallResponse;
+use crate::token::TokenBalance;
+use crate::providers;
+use crate::transform::Transform;
+
+// One entry of the `balances` array on a Horizon account
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct HorizonBalance {
+    pub asset_type: String,
+    pub asset_code: Option<String>,
+    pub asset_issuer: Option<String>,
+    pub liquidity_pool_id: Option<String>,
+    pub balance: String,
+    pub limit: Option<String>,
+    #[serde(default = "zero_amount")]
+    pub buying_liabilities: String,
+    #[serde(default = "zero_amount")]
+    pub selling_liabilities: String,
+    #[serde(default = "default_true")]
+    pub is_authorized: bool,
+    #[serde(default = "default_true")]
+    pub is_authorized_to_maintain_liabilities: bool,
+    #[serde(default)]
+    pub is_clawback_enabled: bool,
+}
+
+impl HorizonBalance {
+    pub fn is_native(&self) -> bool {
+        self.asset_type == "native"
+    }
+
+    pub fn matches(&self, asset_code: &str, asset_issuer: &str) -> bool {
+        self.asset_code.as_deref() == Some(asset_code) && self.asset_issuer.as_deref() == Some(asset_issuer)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AssetId {
+    pub asset_code: String,
+    pub asset_issuer: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct HorizonAccount {
+    balances: Vec<HorizonBalance>,
+}
+
+fn zero_amount() -> String {
+    "0.0000000".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// Balances of an account, or None if the account does not exist (not yet funded).
+// `critical` reads back a transfer and are cross-checked when the network asks for it.
+pub async fn fetch_account_balances(
+    address: &str,
+    network: &str,
+    critical: bool,
+) -> Result<Option<Vec<HorizonBalance>>, String> {
+    let path = format!("/accounts/{}", address);
+    if critical {
+        return providers::horizon_get_verified(network, &path, 100_000, Transform::HorizonAccount, parse_account_balances).await;
+    }
+    let response = providers::horizon_get(network, &path, 100_000, Transform::HorizonAccount).await?;
+    parse_account_balances(&response)
+}
+
+fn parse_account_balances(response: &OutcallResponse) -> Result<Option<Vec<HorizonBalance>>, String> {
+    if response.status == 404 {
+        return Ok(None);
+    }
+    if !response.is_success() {
+        return Err(format!("Horizon account request failed: HTTP {} {}", response.status, response.body));
+    }
+
+    let account: HorizonAccount = serde_json::from_str(&response.body)
+        .map_err(|e| format!("Failed to parse JSON response: {}", e))?;
+    Ok(Some(account.balances))
+}
+
+// Candid view of a balance, shared by get_account_assets and the trustline checks
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AccountBalance {
+    pub asset_type: String,
+    // "XLM" for the native balance
+    pub asset_code: String,
+    pub asset_issuer: Option<String>,
+    pub liquidity_pool_id: Option<String>,
+    pub balance: String,
+    pub limit: Option<String>,
+    pub buying_liabilities: String,
+    pub selling_liabilities: String,
+    pub is_authorized: bool,
+    pub is_authorized_to_maintain_liabilities: bool,
+    pub is_clawback_enabled: bool,
+}
+
+impl From<&HorizonBalance> for AccountBalance {
+    fn from(balance: &HorizonBalance) -> Self {
+        AccountBalance {
+            asset_type: balance.asset_type.clone(),
+            asset_code: if balance.is_native() {
+                "XLM".to_string()
+            } else {
+                balance.asset_code.clone().unwrap_or_default()
+            },
+            asset_issuer: balance.asset_issuer.clone(),
+            liquidity_pool_id: balance.liquidity_pool_id.clone(),
+            balance: balance.balance.clone(),
+            limit: balance.limit.clone(),
+            buying_liabilities: balance.buying_liabilities.clone(),
+            selling_liabilities: balance.selling_liabilities.clone(),
+            is_authorized: balance.is_authorized,
+            is_authorized_to_maintain_liabilities: balance.is_authorized_to_maintain_liabilities,
+            is_clawback_enabled: balance.is_clawback_enabled,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AccountAssets {
+    pub address: String,
+    pub network: String,
+    // False until the account has been funded; `assets` is empty then
+    pub account_exists: bool,
+    pub assets: Vec<AccountBalance>,
+    // SEP-41 token contracts the caller follows (see add_token_contract)
+    pub contract_tokens: Vec<TokenBalance>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TrustlineStatus {
+    pub asset_code: String,
+    pub asset_issuer: String,
+    pub exists: bool,
+    pub trustline: Option<AccountBalance>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TrustlineReport {
+    pub address: String,
+    pub network: String,
+    pub account_exists: bool,
+    pub trustlines: Vec<TrustlineStatus>,
+}
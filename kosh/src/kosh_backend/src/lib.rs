@@ -5,24 +5,67 @@ use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
 
 use base64::{engine::general_purpose::STANDARD, Engine as _};
-use ic_cdk::api::management_canister::http_request::{
-    CanisterHttpRequestArgument, HttpMethod, HttpResponse, TransformArgs,
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use crate::{evm_indexer::CHAIN_SERVICE, stellar_indexer::{CandidContractEvent, IndexerStatus}};
+use crate::anchor::{AnchorTransfer, AnchorTransferCall, AnchorTransferKind, AnchorTransferRequest};
+use crate::sep7::{Sep7Outcome, Sep7Request};
+use crate::sep10::Sep10Challenge;
+use crate::network::NetworkConfig;
+use crate::stable_state::InitArgs;
+use crate::cycles::CyclesUsage;
+use crate::federation::FederationRecord;
+use crate::horizon::{AccountAssets, AccountBalance, AssetId, TrustlineReport, TrustlineStatus};
+use crate::history::HistoryPage;
+use crate::portfolio::{Portfolio, ReferenceAsset};
+use crate::soroban::{ContractCall, ContractValue};
+use crate::token::{TokenBalance, TokenInfo};
+use crate::event_store::{EventPage, EventQuery};
+use crate::subscriptions::{Subscription, SubscriptionRequest};
+use crate::bridge_release::{BridgeRelease, LockSource};
+use crate::tx_ledger::{TxKind, TxRecord, TxRequest, TxStatus};
+use crate::tx_result::{SubmittedTransaction, TxError, TxErrorCode};
+use crate::transform::Transform;
+use crate::rate_limit::{
+    guard_build_stellar_transaction, guard_check_trustline, guard_check_trustlines, guard_create_trustline,
+    guard_evm_block_fetch, guard_execute_bridge_lock, guard_execute_token_swap, guard_get_account_assets,
+    guard_public_key_stellar, guard_remove_trustline, guard_sign_stellar_swap, EndpointUsage, RateLimits,
 };
-use crate::{evm_indexer::CHAIN_SERVICE, stellar_indexer::CandidContractEvent};
-use candid::Func;
 use serde_json;
 
 use sha2::{Digest, Sha256};
 use stellar_xdr::curr::{
-    DecoratedSignature, Hash, Limited, Limits, ReadXdr, Signature,
+    DecoratedSignature, Hash, Limited, Limits, ReadXdr, Signature, Transaction,
     TransactionEnvelope, TransactionSignaturePayload, TransactionSignaturePayloadTaggedTransaction,
-    WriteXdr,
+    TransactionV1Envelope, WriteXdr,
 };
 
 pub mod stellar_indexer; 
 pub mod evm_indexer;
 pub mod eth;
 pub mod evm_rpc_bindings;
+pub mod outcall;
+pub mod stellar_toml;
+pub mod sep10;
+pub mod anchor;
+pub mod sep7;
+pub mod federation;
+pub mod horizon;
+pub mod network;
+pub mod history;
+pub mod tx_ledger;
+pub mod confirmations;
+pub mod tx_result;
+pub mod stable_state;
+pub mod providers;
+pub mod transform;
+pub mod cycles;
+pub mod rate_limit;
+pub mod portfolio;
+pub mod soroban;
+pub mod token;
+pub mod event_store;
+pub mod subscriptions;
+pub mod bridge_release;
 type CanisterId = Principal;
 
 #[derive(CandidType, Serialize, Deserialize, Debug, Copy, Clone)]
@@ -82,28 +125,13 @@ struct AccountResponse {
     sequence: String,
 }
 
-// Transform function to make HTTP responses deterministic
-#[ic_cdk::query]
-fn transform_http_response(raw: TransformArgs) -> HttpResponse {
-    let mut sanitized = raw.response.clone();
-    
-    // Remove non-deterministic headers
-    sanitized.headers.clear();
-    
-    HttpResponse {
-        status: sanitized.status,
-        body: sanitized.body,
-        headers: vec![], // Remove all headers for determinism
-    }
-}
-
 // Keep the original greet function for testing
 #[ic_cdk::query]
 fn greet(name: String) -> String {
     format!("Hello, {}!", name)
 }
 
-#[ic_cdk::update]
+#[ic_cdk::update(guard = "guard_public_key_stellar")]
 async fn public_key_stellar() -> Result<String, String> {
     let request = ManagementCanisterSchnorrPublicKeyRequest {
         canister_id: None,
@@ -130,21 +158,9 @@ async fn public_key_stellar() -> Result<String, String> {
         return Err("Invalid public key length; expected 32 bytes".to_string());
     }
 
-    let version_byte = 0x30;
-    let mut data_with_version = vec![version_byte];
-    data_with_version.extend_from_slice(&public_key_bytes);
-
-    let stellar_pub = hex::encode(data_with_version.clone());
-    ic_cdk::println!("stellar_pub {:?}", stellar_pub);
-
-    // Step 2: Calculate CRC16-XModem checksum
-    let mut state = State::<XMODEM>::new();
-    state.update(&data_with_version);
-    let checksum = state.get().to_le_bytes();
-    data_with_version.extend_from_slice(&checksum);
-
-    // Step 3: Encode the resulting data in Base32
-    let stellar_address = base32::encode(Alphabet::RFC4648 { padding: false }, &data_with_version);
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&public_key_bytes);
+    let stellar_address = encode_stellar_address(&key);
     ic_cdk::println!("Stellar address: {}", stellar_address);
 
     if !stellar_address.starts_with('G') {
@@ -154,8 +170,8 @@ async fn public_key_stellar() -> Result<String, String> {
     Ok(stellar_address)
 }
 
-async fn sign_transaction_stellar(xdr_base64: String, network: &str) -> Result<String, String> {
-    let xdr_bytes = STANDARD.decode(&xdr_base64)
+fn decode_envelope(xdr_base64: &str) -> Result<TransactionEnvelope, String> {
+    let xdr_bytes = STANDARD.decode(xdr_base64.trim())
         .map_err(|e| format!("Failed to decode XDR: {}", e))?;
 
     let limits = Limits {
@@ -163,156 +179,164 @@ async fn sign_transaction_stellar(xdr_base64: String, network: &str) -> Result<S
         len: 10000,
     };
 
-    let mut limited_reader = Limited::new(xdr_bytes.as_slice(), limits.clone());
-    let envelope = TransactionEnvelope::read_xdr(&mut limited_reader)
-        .map_err(|e| format!("Failed to parse XDR: {}", e))?;
+    let mut limited_reader = Limited::new(xdr_bytes.as_slice(), limits);
+    TransactionEnvelope::read_xdr(&mut limited_reader)
+        .map_err(|e| format!("Failed to parse XDR: {}", e))
+}
 
-    if let TransactionEnvelope::Tx(tx_envelope) = envelope {
-        // Use the appropriate network passphrase
-        let network_passphrase = match network {
-            "mainnet" => "Public Global Stellar Network ; September 2015",
-            "testnet" | _ => "Test SDF Network ; September 2015",
-        };
-        let network_id = Sha256::digest(network_passphrase.as_bytes());
-        ic_cdk::println!("Network ID: {}", hex::encode(&network_id));
-        
-        let tagged_transaction = TransactionSignaturePayloadTaggedTransaction::Tx(tx_envelope.tx.clone());
-        let payload = TransactionSignaturePayload {
-            network_id: Hash(network_id.try_into().map_err(|_| "Hash conversion failed")?),
-            tagged_transaction,
-        };
+fn encode_envelope(envelope: &TransactionEnvelope) -> Result<String, String> {
+    let limits = Limits {
+        depth: 100,
+        len: 10000,
+    };
 
-        // Serialize the payload to get the by
-        let mut payload_bytes = Vec::new();
-        let mut limited_writer = Limited::new(&mut payload_bytes, limits.clone());
-        payload.write_xdr(&mut limited_writer)
-            .map_err(|e| format!("Failed to serialize payload: {}", e))?;
-
-        // Calculate the hash of the payload
-        let hash = Sha256::digest(&payload_bytes);
-        ic_cdk::println!("Transaction hash to sign: {}", hex::encode(&hash));
-
-        // Get the public key first
-        let pubkey_request = ManagementCanisterSchnorrPublicKeyRequest {
-            canister_id: None,
-            derivation_path: vec![ic_cdk::api::caller().as_slice().to_vec()],
-            key_id: SchnorrKeyId {
-                algorithm: SchnorrAlgorithm::Ed25519,
-                name: String::from("test_key_1"),
-            },
-        };
+    let mut xdr_out = Vec::new();
+    let mut limited_writer = Limited::new(&mut xdr_out, limits);
+    envelope
+        .write_xdr(&mut limited_writer)
+        .map_err(|e| format!("Failed to serialize transaction envelope: {}", e))?;
 
-        let (pubkey_reply,): (ManagementCanisterSchnorrPublicKeyReply,) = ic_cdk::call(
-            Principal::management_canister(),
-            "schnorr_public_key",
-            (pubkey_request,),
-        )
-        .await
-        .map_err(|e| format!("schnorr_public_key failed {}", e.1))?;
-
-        ic_cdk::println!("Public key: {}", hex::encode(&pubkey_reply.public_key));
-
-        // Sign the hash
-        let internal_request = ManagementCanisterSignatureRequest {
-            message: hash.to_vec(),
-            derivation_path: vec![ic_cdk::api::caller().as_slice().to_vec()],
-            key_id: SchnorrKeyId {
-                algorithm: SchnorrAlgorithm::Ed25519,
-                name: String::from("test_key_1"),
-            },
-        };
+    Ok(STANDARD.encode(xdr_out))
+}
 
-        let (internal_reply,): (ManagementCanisterSignatureReply,) =
-            ic_cdk::api::call::call_with_payment(
-                Principal::management_canister(),
-                "sign_with_schnorr",
-                (internal_request,),
-                26_153_846_153,
-            )
-            .await
-            .map_err(|e| format!("sign_with_schnorr failed {e:?}"))?;
-
-        ic_cdk::println!("Signature: {}", hex::encode(&internal_reply.signature));
-
-        // Create hint from public key
-        let mut hint = [0u8; 4];
-        hint.copy_from_slice(&pubkey_reply.public_key[28..32]);
-        ic_cdk::println!("Signature hint: {}", hex::encode(&hint));
-
-        // Create decorated signature
-        let decorated_sig = DecoratedSignature {
-            hint: stellar_xdr::curr::SignatureHint(hint),
-            signature: Signature(internal_reply.signature.try_into()
-                .map_err(|_| "Invalid signature length")?),
-        };
+// Hash the network signs over: sha256 of the network id plus the tagged transaction
+fn transaction_hash(tx: &Transaction, network_passphrase: &str) -> Result<[u8; 32], String> {
+    let network_id = Sha256::digest(network_passphrase.as_bytes());
 
-        // Create new envelope with the signature
-        let mut signed_envelope = tx_envelope;
-        signed_envelope.signatures = vec![decorated_sig].try_into()
-            .map_err(|_| "Failed to add signature")?;
-
-        // Serialize the signed envelope
-        let mut signed_xdr = Vec::new();
-        let mut limited_writer = Limited::new(&mut signed_xdr, limits);
-        TransactionEnvelope::Tx(signed_envelope)
-            .write_xdr(&mut limited_writer)
-            .map_err(|e| format!("Failed to serialize signed envelope: {}", e))?;
-
-        let signed_xdr_base64 = STANDARD.encode(signed_xdr);
-        ic_cdk::println!("Signed XDR: {}", signed_xdr_base64);
-        let result = submit_transaction(signed_xdr_base64.clone(), network).await?;
-        ic_cdk::println!("Transaction submission result: {}", result);
-        // Return the actual submission result (with hash) instead of the XDR
-        Ok(result)
-    } else {
-        Err("Invalid transaction envelope type".to_string())
-    }
-}
+    let payload = TransactionSignaturePayload {
+        network_id: Hash(network_id.try_into().map_err(|_| "Hash conversion failed")?),
+        tagged_transaction: TransactionSignaturePayloadTaggedTransaction::Tx(tx.clone()),
+    };
 
-async fn get_sequence_number(public_key: &str, network: &str) -> Result<i64, String> {
-    let base_url = match network {
-        "mainnet" => "https://horizon.stellar.org",
-        "testnet" | _ => "https://horizon-testnet.stellar.org", // Default to testnet
+    let limits = Limits {
+        depth: 100,
+        len: 10000,
     };
-    
-    let url = format!("{}/accounts/{}", base_url, public_key);
-
-    let response = ic_cdk::api::management_canister::http_request::http_request(
-        CanisterHttpRequestArgument {
-            url: url.clone(),
-            method: HttpMethod::GET,
-            body: None,
-            max_response_bytes: Some(50_000), // Increased for larger responses
-            transform: Some(ic_cdk::api::management_canister::http_request::TransformContext {
-                function: ic_cdk::api::management_canister::http_request::TransformFunc(
-                    Func {
-                        principal: ic_cdk::id(),
-                        method: "transform_http_response".to_string(),
-                    }
-                ),
-                context: vec![],
-            }),
-            headers: vec![],
+
+    let mut payload_bytes = Vec::new();
+    let mut limited_writer = Limited::new(&mut payload_bytes, limits);
+    payload.write_xdr(&mut limited_writer)
+        .map_err(|e| format!("Failed to serialize payload: {}", e))?;
+
+    Ok(Sha256::digest(&payload_bytes).into())
+}
+
+// Sign a V1 envelope with the caller's derived key, keeping any signatures already on it
+async fn sign_envelope(
+    mut tx_envelope: TransactionV1Envelope,
+    network_passphrase: &str,
+) -> Result<TransactionV1Envelope, String> {
+    let hash = transaction_hash(&tx_envelope.tx, network_passphrase)?;
+    ic_cdk::println!("Transaction hash to sign: {}", hex::encode(hash));
+
+    // Get the public key first
+    let pubkey_request = ManagementCanisterSchnorrPublicKeyRequest {
+        canister_id: None,
+        derivation_path: vec![ic_cdk::api::caller().as_slice().to_vec()],
+        key_id: SchnorrKeyId {
+            algorithm: SchnorrAlgorithm::Ed25519,
+            name: String::from("test_key_1"),
         },
-        100_000_000_000, // Increased cycles for HTTPS requests
+    };
+
+    let (pubkey_reply,): (ManagementCanisterSchnorrPublicKeyReply,) = ic_cdk::call(
+        Principal::management_canister(),
+        "schnorr_public_key",
+        (pubkey_request,),
     )
     .await
-    .map_err(|(code, msg)| format!("HTTP request failed: code = {:?}, message = {}", code, msg))?;
+    .map_err(|e| format!("schnorr_public_key failed {}", e.1))?;
+
+    ic_cdk::println!("Public key: {}", hex::encode(&pubkey_reply.public_key));
+
+    // Sign the hash
+    let internal_request = ManagementCanisterSignatureRequest {
+        message: hash.to_vec(),
+        derivation_path: vec![ic_cdk::api::caller().as_slice().to_vec()],
+        key_id: SchnorrKeyId {
+            algorithm: SchnorrAlgorithm::Ed25519,
+            name: String::from("test_key_1"),
+        },
+    };
+
+    let cycles = cycles::sign_with_schnorr();
+    let result = ic_cdk::api::call::call_with_payment128(
+        Principal::management_canister(),
+        "sign_with_schnorr",
+        (internal_request,),
+        cycles,
+    )
+    .await;
+    cycles::record("sign_with_schnorr", cycles);
+
+    let (internal_reply,): (ManagementCanisterSignatureReply,) =
+        result.map_err(|e| format!("sign_with_schnorr failed {e:?}"))?;
+
+    ic_cdk::println!("Signature: {}", hex::encode(&internal_reply.signature));
+
+    // Create hint from public key
+    let mut hint = [0u8; 4];
+    hint.copy_from_slice(&pubkey_reply.public_key[28..32]);
+    ic_cdk::println!("Signature hint: {}", hex::encode(hint));
+
+    // Create decorated signature
+    let decorated_sig = DecoratedSignature {
+        hint: stellar_xdr::curr::SignatureHint(hint),
+        signature: Signature(internal_reply.signature.try_into()
+            .map_err(|_| "Invalid signature length")?),
+    };
+
+    let mut signatures = tx_envelope.signatures.to_vec();
+    signatures.push(decorated_sig);
+    tx_envelope.signatures = signatures.try_into()
+        .map_err(|_| "Failed to add signature")?;
+
+    Ok(tx_envelope)
+}
+
+// Add the caller's signature to a base64 V1 envelope
+async fn sign_transaction_xdr(xdr_base64: &str, network: &str) -> Result<String, String> {
+    let TransactionEnvelope::Tx(tx_envelope) = decode_envelope(xdr_base64)? else {
+        return Err("Invalid transaction envelope type".to_string());
+    };
 
-    let response_body = String::from_utf8(response.0.body)
-        .map_err(|e| format!("Failed to decode response body: {}", e))?;
+    // Use the appropriate network passphrase
+    let signed_envelope = sign_envelope(tx_envelope, &network::passphrase(network)?).await?;
 
-    let account: AccountResponse = serde_json::from_str(&response_body)
-        .map_err(|e| format!("Failed to parse JSON response: {}", e))?;
+    let signed_xdr_base64 = encode_envelope(&TransactionEnvelope::Tx(signed_envelope))?;
+    ic_cdk::println!("Signed XDR: {}", signed_xdr_base64);
+    Ok(signed_xdr_base64)
+}
 
-    account
-        .sequence
-        .parse::<i64>()
-        .map_err(|e| format!("Failed to parse sequence number: {}", e))
+async fn sign_transaction_stellar(xdr_base64: String, network: &str, request: TxRequest) -> Result<SubmittedTransaction, TxError> {
+    let signed_xdr_base64 = sign_transaction_xdr(&xdr_base64, network).await?;
+    let result = submit_transaction(signed_xdr_base64, network, &request).await?;
+    ic_cdk::println!("Transaction submission result: {:?}", result);
+    // Return the actual submission result (with hash) instead of the XDR
+    Ok(result)
 }
 
+async fn get_sequence_number(public_key: &str, network: &str) -> Result<i64, String> {
+    // The next transaction is built on this value, so it is a critical read
+    let path = format!("/accounts/{}", public_key);
+    providers::horizon_get_verified(network, &path, 50_000, Transform::HorizonAccount, |response| {
+        if !response.is_success() {
+            return Err(format!("Horizon account request failed: HTTP {} {}", response.status, response.body));
+        }
+
+        let account: AccountResponse = serde_json::from_str(&response.body)
+            .map_err(|e| format!("Failed to parse JSON response: {}", e))?;
+
+        account
+            .sequence
+            .parse::<i64>()
+            .map_err(|e| format!("Failed to parse sequence number: {}", e))
+    })
+    .await
+}
 
-#[ic_cdk::update]
+
+#[ic_cdk::update(guard = "guard_evm_block_fetch")]
 async fn evm_block_fetch(block_number: u64) {
     let service = CHAIN_SERVICE.with(|service_cell| {
         let mut service = service_cell.borrow_mut();
@@ -328,14 +352,14 @@ async fn evm_block_fetch(block_number: u64) {
     }
 }
 
-#[ic_cdk::update]
+#[ic_cdk::update(guard = "guard_build_stellar_transaction")]
 async fn build_stellar_transaction(
     destination_address: String,
     amount: u64,
     network: Option<String>,
-) -> Result<String, String> {
+) -> Result<SubmittedTransaction, TxError> {
     // Check if this is a Base chain transaction
-    let network_type = network.as_deref().unwrap_or("testnet");
+    let network_type = &network::resolve(network.clone())?;
     
     // if network_type == "base" {
     //     // Handle Base chain (EVM) transaction
@@ -370,8 +394,8 @@ async fn build_stellar_transaction(
     
     // Continue with Stellar transaction logic for non-base networks
     use stellar_xdr::curr::{
-        Asset, Memo, MuxedAccount, Operation, OperationBody, PaymentOp, Preconditions,
-        SequenceNumber, TimeBounds, TimePoint, Transaction, TransactionExt, TransactionV1Envelope,
+        Asset, MuxedAccount, Operation, OperationBody, PaymentOp,
+        SequenceNumber, Transaction, TransactionExt, TransactionV1Envelope,
         Uint256,
     };
 
@@ -387,13 +411,13 @@ async fn build_stellar_transaction(
 
     // Remove version byte (1 byte) and checksum (2 bytes)
     if decoded.len() <= 3 {
-        return Err("Invalid source address encoding".to_string());
+        return Err("Invalid source address encoding".into());
     }
 
     let key_bytes = &decoded[1..decoded.len() - 2];
 
     if key_bytes.len() != 32 {
-        return Err(format!("Invalid key length: {}", key_bytes.len()).to_string());
+        return Err(format!("Invalid key length: {}", key_bytes.len()).into());
     }
 
     let source_key = Uint256(
@@ -406,23 +430,26 @@ async fn build_stellar_transaction(
     let source_account = MuxedAccount::Ed25519(source_key);
 
     // Get sequence number from network
-    let network_type = network.as_deref().unwrap_or("testnet");
+    let network_type = &network::resolve(network.clone())?;
     let sequence = get_sequence_number(&source_address, network_type).await?;
     ic_cdk::println!("Current sequence: {}", sequence);
 
+    // Resolve federation addresses (name*domain.com) to an account and required memo
+    let destination = federation::resolve_destination(&destination_address).await?;
+
     // Convert destination address to AccountId format
-    let decoded_dest = base32::decode(Alphabet::RFC4648 { padding: false }, &destination_address)
+    let decoded_dest = base32::decode(Alphabet::RFC4648 { padding: false }, &destination.account_id)
         .ok_or("Failed to decode base32 destination address")?;
 
     if decoded_dest.len() <= 3 {
-        return Err("Invalid destination address encoding".to_string());
+        return Err("Invalid destination address encoding".into());
     }
 
     let dest_key_bytes = &decoded_dest[1..decoded_dest.len() - 2];
 
     if dest_key_bytes.len() != 32 {
         return Err(
-            format!("Invalid destination key length: {}", dest_key_bytes.len()).to_string(),
+            format!("Invalid destination key length: {}", dest_key_bytes.len()).into(),
         );
     }
 
@@ -452,19 +479,13 @@ async fn build_stellar_transaction(
         body: OperationBody::Payment(payment_op),
     };
 
-    // Create time bounds (valid for 5 minutes)
-    let time_bounds = TimeBounds {
-        min_time: TimePoint(0),
-        max_time: TimePoint(0), // 5 minutes validity window
-    };
-
     // Create transaction with proper sequence number and time bounds
     let transaction = Transaction {
         source_account,
         fee: 100, // 100 stroops (0.00001 XLM)
         seq_num: SequenceNumber(sequence + 1),
-        cond: Preconditions::Time(time_bounds),
-        memo: Memo::None,
+        cond: confirmations::validity_window(),
+        memo: destination.memo()?,
         operations: vec![operation]
             .try_into()
             .map_err(|_| "Too many operations")?,
@@ -495,195 +516,134 @@ async fn build_stellar_transaction(
 
     // Log the XDR for debugging
     ic_cdk::println!("Generated transaction XDR: {}", xdr_base64);
-    let result = sign_transaction_stellar(xdr_base64.clone(), network_type).await?;
-    ic_cdk::println!("Transaction submission result: {}", result);
+    let request = TxRequest::new(TxKind::Payment, serde_json::json!({
+        "destination_address": destination_address,
+        "amount": amount,
+        "network": network_type,
+    }));
+    let result = sign_transaction_stellar(xdr_base64.clone(), network_type, request).await?;
+    ic_cdk::println!("Transaction submission result: {:?}", result);
     // Return the actual submission result (with hash) instead of the XDR
     Ok(result)
 }
 
-async fn submit_transaction(signed_xdr: String, network: &str) -> Result<String, String> {
-    use ic_cdk::api::management_canister::http_request::{HttpHeader};
-    use url::form_urlencoded;
+// Submit to Horizon and record the outcome in the caller's transaction ledger
+async fn submit_transaction(signed_xdr: String, network: &str, request: &TxRequest) -> Result<SubmittedTransaction, TxError> {
+    // Queue the transaction and let the confirmation poller follow it into a ledger, so a
+    // slow close cannot time out the outcall
+    let result = match confirmations::post_transaction_async(&signed_xdr, network).await {
+        Ok(Some(submitted)) => Ok(submitted),
+        Ok(None) => post_transaction(signed_xdr.clone(), network).await,
+        Err(e) => Err(e),
+    };
 
+    // Requests that never reached Stellar have nothing to record
+    let status = match &result {
+        Ok(submitted) => Some(submitted.status.clone()),
+        Err(e) if e.code == TxErrorCode::Request => None,
+        Err(e) => Some(TxStatus::Failed(e.message.clone())),
+    };
+    if let Some(status) = status {
+        if let Err(e) = tx_ledger::record(&signed_xdr, network, request, status) {
+            ic_cdk::println!("Failed to record transaction: {}", e);
+        }
+        confirmations::track_pending();
+    }
+
+    result
+}
+
+// Empty for networks without an explorer (futurenet, standalone)
+fn explorer_tx_url(network: &str, hash: &str) -> String {
+    network::get(network)
+        .ok()
+        .and_then(|config| config.explorer_url)
+        .map(|explorer| format!("{}/tx/{}", explorer.trim_end_matches('/'), hash))
+        .unwrap_or_default()
+}
+
+// 0x followed by 20 bytes of hex; the checksum of mixed-case addresses is not checked
+fn is_evm_address(address: &str) -> bool {
+    address
+        .strip_prefix("0x")
+        .is_some_and(|hex| hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+// Err unless the caller controls the canister; `action` completes "Only controllers can ..."
+fn require_controller(action: &str) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&ic_cdk::api::caller()) {
+        return Err(format!("Only controllers can {}", action));
+    }
+    Ok(())
+}
+
+// Blocking submission through /transactions, for Horizon instances without /transactions_async
+async fn post_transaction(signed_xdr: String, network: &str) -> Result<SubmittedTransaction, TxError> {
     // Clean the XDR and URL encode it
     let clean_xdr = signed_xdr.trim().replace(" ", "");
-    let encoded_xdr = form_urlencoded::byte_serialize(clean_xdr.as_bytes()).collect::<String>();
-    
     ic_cdk::println!("Clean XDR before submission: {}", clean_xdr);
 
-    // Prepare the request body in the format Horizon expects
-    let request_body = format!("tx={}", encoded_xdr);
-    
-    ic_cdk::println!("Request body: {}", request_body);
+    let request_body = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("tx", &clean_xdr)
+        .finish();
 
-    let base_url = match network {
-        "mainnet" => "https://horizon.stellar.org",
-        "testnet" | _ => "https://horizon-testnet.stellar.org", // Default to testnet
-    };
-    
-    // Make HTTP request to Stellar network
-    let response = ic_cdk::api::management_canister::http_request::http_request(
-        CanisterHttpRequestArgument {
-            url: format!("{}/transactions", base_url),
-            method: HttpMethod::POST,
-            body: Some(request_body.into_bytes()),
-            max_response_bytes: Some(50_000), // Increased for larger responses
-            transform: Some(ic_cdk::api::management_canister::http_request::TransformContext {
-                function: ic_cdk::api::management_canister::http_request::TransformFunc(
-                    Func {
-                        principal: ic_cdk::id(),
-                        method: "transform_http_response".to_string(),
-                    }
-                ),
-                context: vec![],
-            }),
-            headers: vec![HttpHeader {
-                name: "Content-Type".to_string(),
-                value: "application/x-www-form-urlencoded".to_string(),
-            }],
-        },
-        100_000_000_000, // Increased cycles for HTTPS requests
-    )
-    .await
-    .map_err(|(code, msg)| format!("HTTP request failed: code = {:?}, message = {}", code, msg))?;
+    let response = providers::horizon_post_form(network, "/transactions", request_body, 50_000, Transform::HorizonSubmission).await?;
 
-    // Parse and return the response
-    let response_body = String::from_utf8(response.0.body)
-        .map_err(|e| format!("Failed to decode response body: {}", e))?;
+    ic_cdk::println!("Transaction submission response ({}): {}", response.status, response.body);
 
-    ic_cdk::println!("Transaction submission response: {}", response_body);
-    ic_cdk::println!("Response status code: {:?}", response.0.status);
-    ic_cdk::println!("Response headers: {:?}", response.0.headers);
-    
-    // Check if response is successful (2xx status code)  
-    let status_code = response.0.status.0.to_string().parse::<u16>().unwrap_or(500);
-    
     // For Stellar API, 400 errors are expected for transaction failures and contain useful JSON
-    // Only treat non-400 errors as HTTP errors that we can't parse
-    if status_code < 200 || (status_code >= 300 && status_code != 400) {
-        ic_cdk::println!("HTTP error status: {}", status_code);
-        let error_response = serde_json::json!({
-            "success": false,
-            "error": format!("HTTP error {}: {}", status_code, response_body),
-            "raw_response": response_body
-        });
-        return Ok(error_response.to_string());
+    // Anything else we can't parse; a 504 may still make it into a ledger later
+    if !response.is_success() && response.status != 400 {
+        return Err(TxError::retry_later(format!("HTTP error {}: {}", response.status, response.body)));
     }
-    
-    // Parse the JSON response to extract transaction hash
-    match serde_json::from_str::<serde_json::Value>(&response_body) {
-        Ok(json_response) => {
-            ic_cdk::println!("Parsed JSON response: {}", json_response);
-            
-            // Log all keys to understand the response structure
-            ic_cdk::println!("JSON response keys: {:?}", json_response.as_object().map(|obj| obj.keys().collect::<Vec<_>>()));
-            
-            // For successful transactions, Stellar returns a 'hash' field
-            let hash = json_response.get("hash").and_then(|h| h.as_str());
-                
-            // Check if transaction was successful - for errors, status will be 400 and successful field won't exist
-            let is_successful = json_response.get("successful")
-                .and_then(|s| s.as_bool())
-                .unwrap_or(false);
-                
-            // Check if this is an error response from Stellar
-            let is_error_response = json_response.get("type").is_some() && 
-                                  json_response.get("title").is_some() &&
-                                  json_response.get("status").is_some();
-                
-            ic_cdk::println!("Extracted hash: {:?}, Is successful: {}", hash, is_successful);
-            
-            if let Some(hash_value) = hash {
-                let network_path = match network {
-                    "mainnet" => "public",
-                    "testnet" | _ => "testnet",
-                };
-                let explorer_url = format!("https://stellar.expert/explorer/{}/tx/{}", network_path, hash_value);
-                let success_response = serde_json::json!({
-                    "success": true,
-                    "hash": hash_value,
-                    "explorer_url": explorer_url,
-                    "raw_response": response_body
-                });
-                ic_cdk::println!("Returning success response with hash: {}", hash_value);
-                Ok(success_response.to_string())
-            } else if is_error_response {
-                // Handle Stellar API error responses
-                let title = json_response.get("title").and_then(|t| t.as_str()).unwrap_or("Transaction Failed");
-                let detail = json_response.get("detail").and_then(|d| d.as_str()).unwrap_or("Unknown error");
-                
-                // Extract operation error codes for better error messages
-                let operation_errors = json_response.get("extras")
-                    .and_then(|extras| extras.get("result_codes"))
-                    .and_then(|codes| codes.get("operations"))
-                    .and_then(|ops| ops.as_array())
-                    .map(|arr| arr.iter().filter_map(|v| v.as_str()).collect::<Vec<_>>().join(", "))
-                    .unwrap_or_else(|| "unknown".to_string());
-                
-                let user_friendly_error = match operation_errors.as_str() {
-                    "op_no_destination" => "Destination account does not exist. The recipient needs to be funded with at least 1 XLM first.",
-                    "op_underfunded" => "Insufficient balance to complete this transaction.",
-                    "op_low_reserve" => "This transaction would leave your account below the minimum reserve.",
-                    _ => &detail
-                };
-                
-                ic_cdk::println!("Stellar API error: {} - {}", title, detail);
-                ic_cdk::println!("Operation errors: {}", operation_errors);
-                
-                let error_response = serde_json::json!({
-                    "success": false,
-                    "error": user_friendly_error,
-                    "stellar_error_code": operation_errors,
-                    "raw_response": response_body
-                });
-                Ok(error_response.to_string())
-            } else if is_successful {
-                // Transaction successful but no hash found (unusual case)
-                let success_response = serde_json::json!({
-                    "success": true,
-                    "message": "Transaction successful but hash not available",
-                    "raw_response": response_body
-                });
-                Ok(success_response.to_string())
-            } else {
-                // Unexpected response format
-                ic_cdk::println!("Unexpected response format. Full response: {}", json_response);
-                
-                let error_response = serde_json::json!({
-                    "success": false,
-                    "error": "Unexpected response format from Stellar network",
-                    "raw_response": response_body,
-                    "debug_info": "Check canister logs for full response details"
-                });
-                Ok(error_response.to_string())
-            }
-        }
-        Err(parse_error) => {
-            ic_cdk::println!("Failed to parse JSON response: {}", parse_error);
-            
-            // Check if this looks like our own XDR (base64) - this would indicate an issue
-            if response_body.starts_with("AAAA") && response_body.len() > 100 {
-                ic_cdk::println!("ERROR: Response looks like XDR instead of Stellar API response!");
-                let error_response = serde_json::json!({
-                    "success": false,
-                    "error": "Internal error: Got XDR instead of API response",
-                    "raw_response": "XDR data (hidden for clarity)",
-                    "debug_info": "Transaction may have been submitted but hash extraction failed"
-                });
-                return Ok(error_response.to_string());
-            }
-            
-            // If we can't parse as JSON, return the raw response
-            let fallback_response = serde_json::json!({
-                "success": false,
-                "error": format!("Could not parse response as JSON: {}", parse_error),
-                "raw_response": response_body
-            });
-            Ok(fallback_response.to_string())
-        }
+
+    let json_response: serde_json::Value = serde_json::from_str(&response.body)
+        .map_err(|e| TxError::unknown(format!("Could not parse response as JSON: {}", e)))?;
+
+    if response.is_success() {
+        let hash = json_response
+            .get("hash")
+            .and_then(|h| h.as_str())
+            .ok_or_else(|| TxError::unknown("Transaction successful but hash not available".to_string()))?;
+        ic_cdk::println!("Transaction included with hash: {}", hash);
+
+        return Ok(SubmittedTransaction {
+            hash: hash.to_string(),
+            network: network.to_string(),
+            status: TxStatus::Success,
+            explorer_url: explorer_tx_url(network, hash),
+        });
     }
+
+    let detail = json_response.get("detail").and_then(|d| d.as_str()).unwrap_or("Unknown error");
+    ic_cdk::println!("Stellar API error: {}", detail);
+
+    let result_xdr = json_response
+        .get("extras")
+        .and_then(|extras| extras.get("result_xdr"))
+        .and_then(|xdr| xdr.as_str())
+        .unwrap_or_default();
+    Err(confirmations::failure(result_xdr).unwrap_or_else(|| TxError::unknown(detail.to_string())))
+}
+
+
+// StrKey encoding: version byte, payload, CRC16-XModem checksum, then unpadded base32
+fn encode_strkey(version_byte: u8, payload: &[u8]) -> String {
+    let mut data = vec![version_byte];
+    data.extend_from_slice(payload);
+
+    let mut state = State::<XMODEM>::new();
+    state.update(&data);
+    let checksum = state.get().to_le_bytes();
+    data.extend_from_slice(&checksum);
+
+    base32::encode(Alphabet::RFC4648 { padding: false }, &data)
 }
 
+fn encode_stellar_address(key: &[u8; 32]) -> String {
+    // 6 << 3 is the ed25519 public key version byte ('G...')
+    encode_strkey(0x30, key)
+}
 
 fn decode_stellar_address(address: &str) -> Result<[u8; 32], String> {
     let decoded = base32::decode(Alphabet::RFC4648 { padding: false }, address)
@@ -704,7 +664,153 @@ fn decode_stellar_address(address: &str) -> Result<[u8; 32], String> {
     Ok(result)
 }
 
-#[ic_cdk::update]
+fn muxed_account_key(account: &stellar_xdr::curr::MuxedAccount) -> [u8; 32] {
+    match account {
+        stellar_xdr::curr::MuxedAccount::Ed25519(key) => key.0,
+        stellar_xdr::curr::MuxedAccount::MuxedEd25519(muxed) => muxed.ed25519.0,
+    }
+}
+
+fn credit_asset(asset_code: &str, asset_issuer: &str) -> Result<stellar_xdr::curr::Asset, String> {
+    use stellar_xdr::curr::{AccountId, AlphaNum12, AlphaNum4, Asset, AssetCode12, AssetCode4, PublicKey, Uint256};
+
+    if asset_code.is_empty() || asset_code.len() > 12 || !asset_code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("Invalid asset code: {}", asset_code));
+    }
+
+    let issuer = AccountId(PublicKey::PublicKeyTypeEd25519(Uint256(decode_stellar_address(asset_issuer)?)));
+    let bytes = asset_code.as_bytes();
+
+    if bytes.len() <= 4 {
+        let mut code = [0u8; 4];
+        code[..bytes.len()].copy_from_slice(bytes);
+        Ok(Asset::CreditAlphanum4(AlphaNum4 { asset_code: AssetCode4(code), issuer }))
+    } else {
+        let mut code = [0u8; 12];
+        code[..bytes.len()].copy_from_slice(bytes);
+        Ok(Asset::CreditAlphanum12(AlphaNum12 { asset_code: AssetCode12(code), issuer }))
+    }
+}
+
+// "XLM" / "native" or a code with an issuer
+fn parse_asset(asset_code: &str, asset_issuer: Option<&str>) -> Result<stellar_xdr::curr::Asset, String> {
+    match (asset_code, asset_issuer) {
+        ("XLM" | "native", None) => Ok(stellar_xdr::curr::Asset::Native),
+        (code, Some(issuer)) => credit_asset(code, issuer),
+        (code, None) => Err(format!("Asset {} requires an issuer", code)),
+    }
+}
+
+// Decimal amount string ("12.5") to stroops, rejecting more than 7 decimal places
+fn parse_stellar_amount(amount: &str) -> Result<i64, String> {
+    let amount = amount.trim();
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+
+    if whole.is_empty() && fraction.is_empty()
+        || !whole.chars().all(|c| c.is_ascii_digit())
+        || !fraction.chars().all(|c| c.is_ascii_digit())
+        || fraction.len() > 7
+    {
+        return Err(format!("Invalid amount: {}", amount));
+    }
+
+    let whole: i64 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| format!("Amount too large: {}", amount))? };
+    let fraction: i64 = format!("{:0<7}", fraction).parse().unwrap_or(0);
+
+    whole
+        .checked_mul(10_000_000)
+        .and_then(|stroops| stroops.checked_add(fraction))
+        .ok_or_else(|| format!("Amount too large: {}", amount))
+}
+
+// Memo as given by anchors, federation servers and SEP-7 URIs: type "text", "id" or "hash"/"return" (base64)
+fn parse_memo(memo_type: Option<&str>, memo: Option<&str>) -> Result<stellar_xdr::curr::Memo, String> {
+    use stellar_xdr::curr::{Memo, StringM};
+
+    let Some(memo) = memo.filter(|m| !m.is_empty()) else {
+        return Ok(Memo::None);
+    };
+
+    match memo_type.unwrap_or("text") {
+        "text" | "MEMO_TEXT" => Ok(Memo::Text(
+            StringM::try_from(memo.to_string()).map_err(|_| "Memo text longer than 28 bytes")?,
+        )),
+        "id" | "MEMO_ID" => Ok(Memo::Id(memo.parse().map_err(|_| format!("Invalid memo id: {}", memo))?)),
+        kind @ ("hash" | "return" | "MEMO_HASH" | "MEMO_RETURN") => {
+            let bytes = STANDARD
+                .decode(memo)
+                .or_else(|_| hex::decode(memo))
+                .map_err(|_| format!("Invalid {} memo: {}", kind, memo))?;
+            let hash = Hash(bytes.try_into().map_err(|_| format!("{} memo must be 32 bytes", kind))?);
+            if kind.ends_with("return") || kind.ends_with("RETURN") {
+                Ok(Memo::Return(hash))
+            } else {
+                Ok(Memo::Hash(hash))
+            }
+        }
+        other => Err(format!("Unsupported memo type: {}", other)),
+    }
+}
+
+// Unsigned V1 envelope from the caller's account with the given operations and the next sequence number
+async fn build_transaction_xdr(
+    operations: Vec<stellar_xdr::curr::Operation>,
+    memo: stellar_xdr::curr::Memo,
+    network: &str,
+) -> Result<String, String> {
+    use stellar_xdr::curr::{MuxedAccount, SequenceNumber, TransactionExt, Uint256, VecM};
+
+    let source_address = public_key_stellar().await?;
+    let sequence_number = get_sequence_number(&source_address, network).await?;
+    let source_key_bytes = decode_stellar_address(&source_address)?;
+
+    let fee = 10000 * operations.len() as u32;
+    let transaction = Transaction {
+        source_account: MuxedAccount::Ed25519(Uint256(source_key_bytes)),
+        fee,
+        seq_num: SequenceNumber(sequence_number + 1),
+        cond: confirmations::validity_window(),
+        memo,
+        operations: VecM::try_from(operations).map_err(|_| "Too many operations")?,
+        ext: TransactionExt::V0,
+    };
+
+    encode_envelope(&TransactionEnvelope::Tx(TransactionV1Envelope {
+        tx: transaction,
+        signatures: VecM::default(),
+    }))
+}
+
+// Pay `amount` stroops of `asset` from the caller's account, then sign and submit
+async fn send_payment(
+    destination_address: &str,
+    asset: stellar_xdr::curr::Asset,
+    amount: i64,
+    memo: stellar_xdr::curr::Memo,
+    network: &str,
+    request: TxRequest,
+) -> Result<SubmittedTransaction, TxError> {
+    use stellar_xdr::curr::{MuxedAccount, Operation, OperationBody, PaymentOp, Uint256};
+
+    if amount <= 0 {
+        return Err("Payment amount must be positive".into());
+    }
+
+    let operation = Operation {
+        source_account: None,
+        body: OperationBody::Payment(PaymentOp {
+            destination: MuxedAccount::Ed25519(Uint256(decode_stellar_address(destination_address)?)),
+            asset,
+            amount,
+        }),
+    };
+
+    let tx_xdr = build_transaction_xdr(vec![operation], memo, network).await?;
+    ic_cdk::println!("Built payment XDR: {}", tx_xdr);
+    sign_transaction_stellar(tx_xdr, network, request).await
+}
+
+#[ic_cdk::update(guard = "guard_execute_token_swap")]
 async fn execute_token_swap(
     destination_address: String,
     destination_asset_code: String,
@@ -712,16 +818,16 @@ async fn execute_token_swap(
     send_amount: u64,
     dest_min: String,
     network: Option<String>,
-) -> Result<String, String> {
+) -> Result<SubmittedTransaction, TxError> {
     use stellar_xdr::curr::{
         Asset, Memo, MuxedAccount, Operation, OperationBody, PaymentOp,
-        PathPaymentStrictSendOp, Preconditions, SequenceNumber, StringM, 
+        PathPaymentStrictSendOp, SequenceNumber, StringM, 
         Transaction, TransactionExt, TransactionV1Envelope,
         Uint256, VecM, Limited, Limits, WriteXdr, AlphaNum4, AlphaNum12, 
         AssetCode4, AssetCode12, AccountId, PublicKey
     };
 
-    let network = network.unwrap_or_else(|| "testnet".to_string());
+    let network = network::resolve(network)?;
     
     ic_cdk::println!("Executing REAL token swap on Stellar: {} XLM → {} {}", 
         send_amount as f64 / 10_000_000.0, dest_min, destination_asset_code);
@@ -735,7 +841,8 @@ async fn execute_token_swap(
     
     // Decode addresses
     let source_key_bytes = decode_stellar_address(&source_address)?;
-    let dest_key_bytes = decode_stellar_address(&destination_address)?;
+    let destination = federation::resolve_destination(&destination_address).await?;
+    let dest_key_bytes = decode_stellar_address(&destination.account_id)?;
     let issuer_key_bytes = decode_stellar_address(&destination_asset_issuer)?;
     
     // Create destination asset
@@ -776,13 +883,16 @@ async fn execute_token_swap(
         body: OperationBody::PathPaymentStrictSend(path_payment_op),
     };
 
-    // Create memo
-    let memo = Memo::Text(
-        StringM::try_from("KOSH Swap".to_string()).map_err(|_| "Memo too long")?
-    );
+    // Create memo, unless the federation server requires its own
+    let memo = if destination.has_memo() {
+        destination.memo()?
+    } else {
+        Memo::Text(
+            StringM::try_from("KOSH Swap".to_string()).map_err(|_| "Memo too long")?
+        )
+    };
 
-    // Use simpler preconditions - no time bounds for now to avoid time sync issues
-    let preconditions = Preconditions::None;
+    let preconditions = confirmations::validity_window();
 
     // Build transaction
     let transaction = Transaction {
@@ -813,124 +923,57 @@ async fn execute_token_swap(
     ic_cdk::println!("Submitting transaction to Stellar {} network...", network);
 
     // Sign and submit the transaction - same as build_stellar_transaction
-    let result = sign_transaction_stellar(tx_xdr, &network).await?;
+    let request = TxRequest::new(TxKind::Swap, serde_json::json!({
+        "destination_address": destination_address,
+        "destination_asset_code": destination_asset_code,
+        "destination_asset_issuer": destination_asset_issuer,
+        "send_amount": send_amount,
+        "dest_min": dest_min,
+        "network": network,
+    }));
+    let result = sign_transaction_stellar(tx_xdr, &network, request).await?;
     
-    ic_cdk::println!("Swap transaction result: {}", result);
+    ic_cdk::println!("Swap transaction result: {:?}", result);
     
     // Return the result directly, just like build_stellar_transaction does
     Ok(result)
 }
 
 
-#[ic_cdk::update]
-async fn get_account_assets(network: Option<String>) -> Result<String, String> {
-    let network = network.unwrap_or_else(|| "testnet".to_string());
-    let base_url = match network.as_str() {
-        "mainnet" => "https://horizon.stellar.org",
-        _ => "https://horizon-testnet.stellar.org",
-    };
-    
-    // Get our public key
-    let public_key_result = public_key_stellar().await?;
-    let address = public_key_result;
-
-    let url = format!("{}/accounts/{}", base_url, address);
-
-    let response = ic_cdk::api::management_canister::http_request::http_request(
-        CanisterHttpRequestArgument {
-            url: url.clone(),
-            method: HttpMethod::GET,
-            body: None,
-            max_response_bytes: Some(100_000),
-            transform: Some(ic_cdk::api::management_canister::http_request::TransformContext {
-                function: ic_cdk::api::management_canister::http_request::TransformFunc(
-                    Func {
-                        principal: ic_cdk::id(),
-                        method: "transform_http_response".to_string(),
-                    }
-                ),
-                context: vec![],
-            }),
-            headers: vec![],
-        },
-        200_000_000_000,
-    )
-    .await
-    .map_err(|(code, msg)| format!("HTTP request failed: code = {:?}, message = {}", code, msg))?;
-
-    let response_body = String::from_utf8(response.0.body)
-        .map_err(|e| format!("Failed to decode response body: {}", e))?;
-
-    if response.0.status.to_string() == "404" {
-        return Ok(serde_json::json!({
-            "success": false,
-            "error": "Account not found",
-            "assets": []
-        }).to_string());
-    }
-
-    let account: serde_json::Value = serde_json::from_str(&response_body)
-        .map_err(|e| format!("Failed to parse JSON response: {}", e))?;
+#[ic_cdk::update(guard = "guard_get_account_assets")]
+async fn get_account_assets(network: Option<String>) -> Result<AccountAssets, String> {
+    let network = network::resolve(network)?;
 
-    // Extract all balances (assets)
-    let mut assets = Vec::new();
-    
-    if let Some(balances) = account["balances"].as_array() {
-        for balance in balances {
-            let asset_info = if balance["asset_type"].as_str() == Some("native") {
-                serde_json::json!({
-                    "asset_type": "native",
-                    "asset_code": "XLM",
-                    "asset_issuer": null,
-                    "balance": balance["balance"],
-                    "limit": null,
-                    "is_authorized": true,
-                    "is_authorized_to_maintain_liabilities": true,
-                    "buying_liabilities": balance.get("buying_liabilities").unwrap_or(&serde_json::Value::String("0.0000000".to_string())),
-                    "selling_liabilities": balance.get("selling_liabilities").unwrap_or(&serde_json::Value::String("0.0000000".to_string()))
-                })
-            } else {
-                serde_json::json!({
-                    "asset_type": balance["asset_type"],
-                    "asset_code": balance["asset_code"],
-                    "asset_issuer": balance["asset_issuer"],
-                    "balance": balance["balance"],
-                    "limit": balance["limit"],
-                    "is_authorized": balance.get("is_authorized").unwrap_or(&serde_json::Value::Bool(true)),
-                    "is_authorized_to_maintain_liabilities": balance.get("is_authorized_to_maintain_liabilities").unwrap_or(&serde_json::Value::Bool(true)),
-                    "buying_liabilities": balance.get("buying_liabilities").unwrap_or(&serde_json::Value::String("0.0000000".to_string())),
-                    "selling_liabilities": balance.get("selling_liabilities").unwrap_or(&serde_json::Value::String("0.0000000".to_string()))
-                })
-            };
-            assets.push(asset_info);
-        }
-    }
-
-    let response = serde_json::json!({
-        "success": true,
-        "address": address,
-        "assets": assets,
-        "network": network
-    });
-
-    Ok(response.to_string())
+    // Get our public key
+    let address = public_key_stellar().await?;
+
+    let balances = horizon::fetch_account_balances(&address, &network, false).await?;
+    let contract_tokens = token::tracked_balances(&address, &network).await;
+
+    Ok(AccountAssets {
+        address,
+        network,
+        account_exists: balances.is_some(),
+        assets: balances.iter().flatten().map(AccountBalance::from).collect(),
+        contract_tokens,
+    })
 }
 
-#[ic_cdk::update]
+#[ic_cdk::update(guard = "guard_create_trustline")]
 async fn create_trustline(
     asset_code: String,
     asset_issuer: String,
     network: Option<String>,
     limit: Option<String>,
-) -> Result<String, String> {
+) -> Result<SubmittedTransaction, TxError> {
     use stellar_xdr::curr::{
         Asset, Memo, MuxedAccount, Operation, OperationBody, ChangeTrustOp,
-        Preconditions, SequenceNumber, Transaction, TransactionExt, TransactionV1Envelope,
+        SequenceNumber, Transaction, TransactionExt, TransactionV1Envelope,
         Uint256, VecM, Limited, Limits, WriteXdr, AlphaNum4, AlphaNum12,
         AssetCode4, AssetCode12, AccountId, PublicKey, ChangeTrustAsset
     };
 
-    let network = network.unwrap_or_else(|| "testnet".to_string());
+    let network = network::resolve(network)?;
     let trust_limit = limit.unwrap_or_else(|| "922337203685.4775807".to_string());
     
     ic_cdk::println!("Creating trustline for {} from issuer {} on {} with limit {}", 
@@ -988,7 +1031,7 @@ async fn create_trustline(
         source_account: MuxedAccount::Ed25519(Uint256(source_key_bytes)),
         fee: 10000, // Higher fee for trustline operations
         seq_num: SequenceNumber(sequence_number + 1),
-        cond: Preconditions::None,
+        cond: confirmations::validity_window(),
         memo,
         operations: VecM::try_from(vec![operation]).map_err(|_| "Too many operations")?,
         ext: TransactionExt::V0,
@@ -1012,64 +1055,203 @@ async fn create_trustline(
     ic_cdk::println!("Submitting trustline transaction to Stellar {} network...", network);
 
     // Sign and submit the transaction - same as build_stellar_transaction
-    let result = sign_transaction_stellar(tx_xdr, &network).await?;
+    let request = TxRequest::new(TxKind::Trustline, serde_json::json!({
+        "asset_code": asset_code,
+        "asset_issuer": asset_issuer,
+        "limit": trust_limit,
+        "network": network,
+    }));
+    let result = sign_transaction_stellar(tx_xdr, &network, request).await?;
     
-    ic_cdk::println!("Trustline transaction result: {}", result);
+    ic_cdk::println!("Trustline transaction result: {:?}", result);
     
     // Return the result directly, just like build_stellar_transaction does
     Ok(result)
 }
 
-#[ic_cdk::update]
+// What to do with a non-zero balance when removing its trustline
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TrustlineBalanceDisposal {
+    // Path-pay the whole balance to XLM in the same transaction, receiving at least `min_xlm`;
+    // required, since without a floor any order book price would be accepted
+    SwapToXlm { min_xlm: String },
+    // Pay the whole balance back to the issuer
+    ReturnToIssuer,
+}
+
+#[ic_cdk::update(guard = "guard_remove_trustline")]
+async fn remove_trustline(
+    asset_code: String,
+    asset_issuer: String,
+    disposal: Option<TrustlineBalanceDisposal>,
+    network: Option<String>,
+) -> Result<SubmittedTransaction, TxError> {
+    use stellar_xdr::curr::{
+        Asset, ChangeTrustAsset, ChangeTrustOp, Memo, MuxedAccount, Operation, OperationBody, PathPaymentStrictSendOp,
+        PaymentOp, StringM, Uint256, VecM,
+    };
+
+    let network = network::resolve(network)?;
+
+    ic_cdk::println!("Removing trustline for {} from issuer {} on {}", asset_code, asset_issuer, network);
+
+    let source_address = public_key_stellar().await?;
+    let balances = horizon::fetch_account_balances(&source_address, &network, true)
+        .await?
+        .ok_or("Account not found")?;
+    let trustline = balances
+        .iter()
+        .find(|balance| balance.matches(&asset_code, &asset_issuer))
+        .ok_or_else(|| format!("No trustline found for {} issued by {}", asset_code, asset_issuer))?;
+
+    // Open offers hold liabilities against the trustline; they have to be cancelled first
+    if parse_stellar_amount(&trustline.buying_liabilities)? != 0 || parse_stellar_amount(&trustline.selling_liabilities)? != 0 {
+        return Err(format!(
+            "Trustline has open offers (buying {}, selling {}); cancel them before removing it",
+            trustline.buying_liabilities, trustline.selling_liabilities
+        ).into());
+    }
+
+    let asset = credit_asset(&asset_code, &asset_issuer)?;
+    let balance = parse_stellar_amount(&trustline.balance)?;
+    let mut operations = Vec::new();
+
+    if balance > 0 {
+        let source_account = MuxedAccount::Ed25519(Uint256(decode_stellar_address(&source_address)?));
+        let body = match disposal {
+            None => {
+                return Err(format!(
+                    "Trustline still holds {} {}; remove it with SwapToXlm or ReturnToIssuer to clear the balance first",
+                    trustline.balance, asset_code
+                ).into());
+            }
+            Some(TrustlineBalanceDisposal::SwapToXlm { min_xlm }) => {
+                let dest_min = parse_stellar_amount(&min_xlm)?;
+                if dest_min <= 0 {
+                    return Err("min_xlm must be greater than zero".to_string().into());
+                }
+                OperationBody::PathPaymentStrictSend(PathPaymentStrictSendOp {
+                    send_asset: asset.clone(),
+                    send_amount: balance,
+                    destination: source_account,
+                    dest_asset: Asset::Native,
+                    dest_min,
+                    path: VecM::default(),
+                })
+            }
+            Some(TrustlineBalanceDisposal::ReturnToIssuer) => OperationBody::Payment(PaymentOp {
+                destination: MuxedAccount::Ed25519(Uint256(decode_stellar_address(&asset_issuer)?)),
+                asset: asset.clone(),
+                amount: balance,
+            }),
+        };
+        operations.push(Operation { source_account: None, body });
+    }
+
+    // A zero limit deletes the trustline and releases its base reserve
+    let line = match asset {
+        Asset::CreditAlphanum4(asset) => ChangeTrustAsset::CreditAlphanum4(asset),
+        Asset::CreditAlphanum12(asset) => ChangeTrustAsset::CreditAlphanum12(asset),
+        Asset::Native => return Err("XLM has no trustline".into()),
+    };
+    operations.push(Operation {
+        source_account: None,
+        body: OperationBody::ChangeTrust(ChangeTrustOp { line, limit: 0 }),
+    });
+
+    let memo = Memo::Text(StringM::try_from("KOSH Trustline".to_string()).map_err(|_| "Memo too long")?);
+    let tx_xdr = build_transaction_xdr(operations, memo, &network).await?;
+    ic_cdk::println!("Built trustline removal XDR: {}", tx_xdr);
+
+    let request = TxRequest::new(TxKind::Trustline, serde_json::json!({
+        "asset_code": asset_code,
+        "asset_issuer": asset_issuer,
+        "limit": "0",
+        "network": network,
+    }));
+    let result = sign_transaction_stellar(tx_xdr, &network, request).await?;
+    ic_cdk::println!("Trustline removal result: {:?}", result);
+
+    Ok(result)
+}
+
+#[ic_cdk::update(guard = "guard_check_trustline")]
 async fn check_trustline(
     asset_code: String,
     asset_issuer: String,
     network: Option<String>,
-) -> Result<String, String> {
-    let network = network.unwrap_or_else(|| "testnet".to_string());
-    
+) -> Result<TrustlineStatus, String> {
+    let network = network::resolve(network)?;
+
     ic_cdk::println!("Checking trustline for {} from issuer {} on {}", asset_code, asset_issuer, network);
-    
-    // TODO: Move this function to frontend - temporarily return popular assets only
-    // Simplified version for now
-    
-    // If not found in actual account data, check popular assets as demo
-    let popular_assets = vec![
-        ("USDC", "GA5ZSEJYB37JRC5AVCIA5MOP4RHTM335X2KGX3IHOJAPP5RE34K4KZVN"),
-        ("USDT", "GCQTGZQQ5G4PTM2GL7CDIFKUBIPEC52BROAQIAPW53XBRJVN6ZJVTG6V"),
-        ("AQUA", "GBNZILSTVQZ4R7IKQDGHYGY2QXL5QOFJYQMXPKWRRM5PAV7Y4M67AQUA"),
-        ("yXLM", "GARDNV3Q7YGT4AKSDF25LT32YSCCW67G2P2OBKQP5PMPOUF2FIKW7SSP"),
-        ("SRT", "GCDNJUBQSX7AJWLJACMJ7I4BC3Z47BQUTMHEICZLE6MU4KQBRYG5JY6B"),
-    ];
-    
-    // For demo purposes, simulate finding popular assets
-    if popular_assets.iter().any(|(code, issuer)| *code == asset_code && *issuer == asset_issuer) {
-        // Simulate that trustline exists for demo
-        return Ok(serde_json::json!({
-            "success": true,
-            "exists": true,
-            "trustline": {
-                "asset_code": asset_code,
-                "asset_issuer": asset_issuer,
-                "balance": "0.0000000",
-                "limit": "922337203685.4775807",
-                "is_authorized": true,
-                "is_authorized_to_maintain_liabilities": true
-            },
-            "note": "Simulated trustline for demo purposes"
-        }).to_string());
+
+    let address = public_key_stellar().await?;
+    let balances = horizon::fetch_account_balances(&address, &network, false).await?;
+
+    Ok(trustline_status(balances.as_deref(), asset_code, asset_issuer))
+}
+
+// Check many trustlines against a single Horizon account lookup
+#[ic_cdk::update(guard = "guard_check_trustlines")]
+async fn check_trustlines(
+    assets: Vec<AssetId>,
+    network: Option<String>,
+) -> Result<TrustlineReport, String> {
+    let network = network::resolve(network)?;
+
+    let address = public_key_stellar().await?;
+    let balances = horizon::fetch_account_balances(&address, &network, false).await?;
+
+    let trustlines = assets
+        .into_iter()
+        .map(|asset| trustline_status(balances.as_deref(), asset.asset_code, asset.asset_issuer))
+        .collect();
+
+    Ok(TrustlineReport {
+        address,
+        network,
+        account_exists: balances.is_some(),
+        trustlines,
+    })
+}
+
+// An account that does not exist yet has no trustlines
+fn trustline_status(
+    balances: Option<&[horizon::HorizonBalance]>,
+    asset_code: String,
+    asset_issuer: String,
+) -> TrustlineStatus {
+    let trustline = balances
+        .unwrap_or_default()
+        .iter()
+        .find(|balance| balance.matches(&asset_code, &asset_issuer))
+        .map(AccountBalance::from);
+
+    TrustlineStatus {
+        asset_code,
+        asset_issuer,
+        exists: trustline.is_some(),
+        trustline,
     }
+}
 
-    // Not found
-    Ok(serde_json::json!({
-        "success": true,
-        "exists": false,
-        "message": format!("No trustline found for {} issued by {}", asset_code, asset_issuer)
-    }).to_string())
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BridgeLockResponse {
+    pub user_address: String,
+    pub from_token: String,
+    pub dest_token: String,
+    pub amount: u64,
+    pub dest_chain: String,
+    pub recipient: String,
+    pub network: String,
+    pub contract_id: String,
+    pub transaction: SubmittedTransaction,
+    // ID of the contract's `lock` event; None while the transaction is still pending
+    pub event_id: Option<String>,
 }
 
-// Core function: Execute bridge lock transaction using Soroban smart contracts
-#[ic_cdk::update]
+// Lock `amount` of the `from_token` contract token in the bridge contract for release on `dest_chain`
+#[ic_cdk::update(guard = "guard_execute_bridge_lock")]
 async fn execute_bridge_lock(
     from_token_address: String,
     dest_token: String,
@@ -1077,51 +1259,90 @@ async fn execute_bridge_lock(
     dest_chain: String,
     recipient_address: String,
     network: Option<String>,
-) -> Result<String, String> {
-    let network = network.as_deref().unwrap_or("testnet");
-    
+) -> Result<BridgeLockResponse, TxError> {
+    use soroban::ContractValue;
+    use stellar_indexer::{CONTRACT_ID, INDEXER_NETWORK};
+
+    let network = network::resolve(network)?;
+    if network != INDEXER_NETWORK {
+        return Err(format!("The bridge contract is deployed on {}", INDEXER_NETWORK).into());
+    }
+
     ic_cdk::println!("🔒 Bridge lock request: {} {} to {} on chain {}", 
         amount, from_token_address, dest_token, dest_chain);
-    
+
+    // The bridge releases on an EVM chain, so anything else would stay locked for good
+    if !is_evm_address(&recipient_address) {
+        return Err(format!("Recipient must be a 0x EVM address, not {}", recipient_address).into());
+    }
+
     // Get user's Stellar address for the lock transaction
     let user_address = public_key_stellar().await?;
-    
-    // In a production implementation, this would:
-    // 1. Build a Soroban contract call for the lock function
-    // 2. Create the transaction envelope with contract.call('lock', ...)
-    // 3. Sign it with threshold cryptography using sign_transaction_stellar
-    // 4. Submit to Stellar network using submit_transaction
-    // 5. Monitor for confirmation and return transaction hash
-    
-    // For now, return a demo response showing the structure
-    let response = serde_json::json!({
-        "success": true,
-        "user_address": user_address,
+
+    // The indexer reads dest_chain back as the hex of these bytes: the chain ID, big-endian
+    // without leading zeros
+    let chain_id: u64 = dest_chain
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid destination chain ID: {}", dest_chain))?;
+    let chain_id_bytes: Vec<u8> = chain_id.to_be_bytes().into_iter().skip_while(|byte| *byte == 0).collect();
+
+    let args = [
+        ContractValue::Address(user_address.clone()),
+        ContractValue::Address(from_token_address.clone()),
+        ContractValue::String(dest_token.clone()),
+        ContractValue::I128(amount as i128),
+        ContractValue::Bytes(chain_id_bytes),
+        ContractValue::String(recipient_address.clone()),
+    ];
+    let request = TxRequest::new(TxKind::Bridge, serde_json::json!({
         "from_token": from_token_address,
         "dest_token": dest_token,
         "amount": amount,
         "dest_chain": dest_chain,
-        "recipient": recipient_address,
+        "recipient_address": recipient_address,
         "network": network,
-        "contract_id": "CDTA5IYGUGRI4PAGXJL7TPBEIC3EZY6V23ILF5EDVXFXLCGGMVOK4CRL",
-        "message": "Bridge lock transaction built and ready for submission",
-        "note": "In production, this would execute the actual Soroban contract call"
+    }));
+    let (transaction, receipt) = soroban::call_contract(CONTRACT_ID, "lock", &args, &network, request).await?;
+
+    let lock_topic = ContractValue::Symbol("lock".to_string()).to_sc_val()?;
+    let stellar_xdr::curr::ScAddress::Contract(bridge) = soroban::parse_address(CONTRACT_ID)? else {
+        return Err(format!("{} is not a contract address", CONTRACT_ID).into());
+    };
+    // Tokens the bridge calls emit events of their own; only the bridge's lock event counts
+    let event_id = receipt.and_then(|receipt| {
+        receipt.events.into_iter().find_map(|(id, event)| {
+            let stellar_xdr::curr::ContractEventBody::V0(body) = &event.body;
+            (event.contract_id.as_ref() == Some(&bridge) && body.topics.first() == Some(&lock_topic)).then_some(id)
+        })
     });
-    
-    Ok(response.to_string())
+
+    Ok(BridgeLockResponse {
+        user_address,
+        from_token: from_token_address,
+        dest_token,
+        amount,
+        dest_chain,
+        recipient: recipient_address,
+        network,
+        contract_id: CONTRACT_ID.to_string(),
+        transaction,
+        event_id,
+    })
 }
 
-#[ic_cdk::update]
+#[ic_cdk::update(guard = "guard_sign_stellar_swap")]
 async fn sign_stellar_swap(
     xdr: String,
     network: Option<String>,
-) -> Result<String, String> {
-    let network = network.unwrap_or_else(|| "mainnet".to_string());
+) -> Result<SubmittedTransaction, TxError> {
+    let network = network::resolve(network)?;
     
     ic_cdk::println!("🔐 Signing swap XDR on {}", network);
     
     // Use existing sign_transaction_stellar - it does everything we need
-    sign_transaction_stellar(xdr, &network).await
+    let request = TxRequest::new(TxKind::Swap, serde_json::json!({ "network": network }));
+    sign_transaction_stellar(xdr, &network, request).await
 }
 
 ic_cdk::export_candid!();
\ No newline at end of file
@@ -0,0 +1,177 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+// Used by every endpoint called without an explicit network
+pub const DEFAULT_NETWORK: &str = "testnet";
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct NetworkConfig {
+    // Name callers pass as `network`, e.g. "mainnet"
+    pub name: String,
+    pub passphrase: String,
+    pub horizon_url: String,
+    pub soroban_rpc_url: String,
+    // Base of transaction links: `{explorer_url}/tx/{hash}`
+    pub explorer_url: Option<String>,
+    pub friendbot_url: Option<String>,
+    // Tried in order when the primary provider errors, times out or rate limits us
+    pub horizon_fallback_urls: Option<Vec<String>>,
+    pub soroban_rpc_fallback_urls: Option<Vec<String>>,
+    // Require two Horizon providers to agree on sequence numbers and balances used for transfers
+    pub verify_critical_reads: Option<bool>,
+    // Issuer of the USDC that portfolios are valued in
+    pub usdc_issuer: Option<String>,
+}
+
+impl NetworkConfig {
+    pub fn horizon_urls(&self) -> Vec<String> {
+        let mut urls = vec![self.horizon_url.clone()];
+        urls.extend(self.horizon_fallback_urls.iter().flatten().cloned());
+        urls
+    }
+
+    pub fn soroban_rpc_urls(&self) -> Vec<String> {
+        let mut urls = vec![self.soroban_rpc_url.clone()];
+        urls.extend(self.soroban_rpc_fallback_urls.iter().flatten().cloned());
+        urls
+    }
+}
+
+thread_local! {
+    static NETWORKS: RefCell<BTreeMap<String, NetworkConfig>> = RefCell::new(built_in_networks());
+}
+
+fn built_in_networks() -> BTreeMap<String, NetworkConfig> {
+    let network = |name: &str, passphrase: &str, horizon: &str, rpc: &str, explorer: Option<&str>, friendbot: Option<&str>| {
+        NetworkConfig {
+            name: name.to_string(),
+            passphrase: passphrase.to_string(),
+            horizon_url: horizon.to_string(),
+            soroban_rpc_url: rpc.to_string(),
+            explorer_url: explorer.map(|url| url.to_string()),
+            friendbot_url: friendbot.map(|url| url.to_string()),
+            horizon_fallback_urls: None,
+            soroban_rpc_fallback_urls: None,
+            verify_critical_reads: None,
+            usdc_issuer: None,
+        }
+    };
+
+    [
+        NetworkConfig {
+            horizon_fallback_urls: Some(vec!["https://horizon.stellar.lobstr.co".to_string()]),
+            soroban_rpc_fallback_urls: Some(vec!["https://mainnet.sorobanrpc.com".to_string()]),
+            usdc_issuer: Some("GA5ZSEJYB37JRC5AVCIA5MOP4RHTM335X2KGX3IHOJAPP5RE34K4KZVN".to_string()),
+            ..network(
+                "mainnet",
+                "Public Global Stellar Network ; September 2015",
+                "https://horizon.stellar.org",
+                "https://soroban-rpc.mainnet.stellar.gateway.fm",
+                Some("https://stellar.expert/explorer/public"),
+                None,
+            )
+        },
+        NetworkConfig {
+            usdc_issuer: Some("GBBD47IF6LWK7P7MDEVSCWR7DPUWV3NY3DTQEVFL4NAT4AQH3ZLLFLA5".to_string()),
+            ..network(
+                "testnet",
+                "Test SDF Network ; September 2015",
+                "https://horizon-testnet.stellar.org",
+                "https://soroban-testnet.stellar.org",
+                Some("https://stellar.expert/explorer/testnet"),
+                Some("https://friendbot.stellar.org"),
+            )
+        },
+        network(
+            "futurenet",
+            "Test SDF Future Network ; October 2022",
+            "https://horizon-futurenet.stellar.org",
+            "https://rpc-futurenet.stellar.org",
+            None,
+            Some("https://friendbot-futurenet.stellar.org"),
+        ),
+        // stellar/quickstart in --local mode
+        network(
+            "standalone",
+            "Standalone Network ; February 2017",
+            "http://localhost:8000",
+            "http://localhost:8000/soroban/rpc",
+            None,
+            Some("http://localhost:8000/friendbot"),
+        ),
+    ]
+    .into_iter()
+    .map(|config| (config.name.clone(), config))
+    .collect()
+}
+
+// Networks given at install or upgrade replace built-in or saved entries of the same name
+pub fn configure(configs: Vec<NetworkConfig>) -> Result<(), String> {
+    for config in &configs {
+        validate(config)?;
+    }
+    NETWORKS.with(|networks| {
+        let mut networks = networks.borrow_mut();
+        for config in configs {
+            ic_cdk::println!("Configured Stellar network {}: {}", config.name, config.horizon_url);
+            networks.insert(config.name.clone(), config);
+        }
+    });
+    Ok(())
+}
+
+fn validate(config: &NetworkConfig) -> Result<(), String> {
+    if config.name.trim().is_empty() || config.passphrase.trim().is_empty() {
+        return Err("Network name and passphrase are required".to_string());
+    }
+    if config.verify_critical_reads.unwrap_or(false) && config.horizon_urls().len() < 2 {
+        return Err(format!("Network {} needs a Horizon fallback URL to verify critical reads", config.name));
+    }
+    let urls = config
+        .horizon_urls()
+        .into_iter()
+        .chain(config.soroban_rpc_urls())
+        .chain(config.explorer_url.clone())
+        .chain(config.friendbot_url.clone());
+    for url in urls {
+        url::Url::parse(&url).map_err(|e| format!("Invalid URL {} for network {}: {}", url, config.name, e))?;
+    }
+    Ok(())
+}
+
+pub fn snapshot() -> Vec<NetworkConfig> {
+    NETWORKS.with(|networks| networks.borrow().values().cloned().collect())
+}
+
+pub fn restore(saved: Vec<NetworkConfig>) {
+    NETWORKS.with(|networks| {
+        let mut networks = networks.borrow_mut();
+        for config in saved {
+            networks.insert(config.name.clone(), config);
+        }
+    });
+}
+
+pub fn get(name: &str) -> Result<NetworkConfig, String> {
+    NETWORKS
+        .with(|networks| networks.borrow().get(name).cloned())
+        .ok_or_else(|| format!("Unknown Stellar network: {}", name))
+}
+
+// Validated network name from an endpoint's optional `network` argument
+pub fn resolve(network: Option<String>) -> Result<String, String> {
+    let network = network.unwrap_or_else(|| DEFAULT_NETWORK.to_string());
+    get(&network)?;
+    Ok(network)
+}
+
+pub fn passphrase(name: &str) -> Result<String, String> {
+    Ok(get(name)?.passphrase)
+}
+
+#[ic_cdk::query]
+fn get_networks() -> Vec<NetworkConfig> {
+    snapshot()
+}
@@ -0,0 +1,91 @@
+use ic_cdk::api::management_canister::http_request::{CanisterHttpRequestArgument, HttpHeader, HttpMethod};
+use num_traits::ToPrimitive;
+
+use crate::cycles;
+use crate::transform::Transform;
+
+#[derive(Debug, Clone)]
+pub struct OutcallResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl OutcallResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+pub fn header(name: &str, value: &str) -> HttpHeader {
+    HttpHeader {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+pub async fn get(
+    url: &str,
+    headers: Vec<HttpHeader>,
+    max_response_bytes: u64,
+    transform: Transform,
+) -> Result<OutcallResponse, String> {
+    send(HttpMethod::GET, url, headers, None, max_response_bytes, transform).await
+}
+
+pub async fn post(
+    url: &str,
+    headers: Vec<HttpHeader>,
+    body: Vec<u8>,
+    max_response_bytes: u64,
+    transform: Transform,
+) -> Result<OutcallResponse, String> {
+    send(HttpMethod::POST, url, headers, Some(body), max_response_bytes, transform).await
+}
+
+async fn send(
+    method: HttpMethod,
+    url: &str,
+    headers: Vec<HttpHeader>,
+    body: Option<Vec<u8>>,
+    max_response_bytes: u64,
+    transform: Transform,
+) -> Result<OutcallResponse, String> {
+    let transform_context = transform.context();
+    let request_bytes = url.len()
+        + headers.iter().map(|h| h.name.len() + h.value.len()).sum::<usize>()
+        + body.as_ref().map_or(0, |body| body.len())
+        + transform_context.function.0.method.len()
+        + transform_context.context.len();
+    let cycles = cycles::http_request(request_bytes as u64, max_response_bytes);
+
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        method,
+        body,
+        max_response_bytes: Some(max_response_bytes),
+        transform: Some(transform_context),
+        headers,
+    };
+
+    let result = ic_cdk::api::management_canister::http_request::http_request(request, cycles).await;
+    cycles::record(&metering_endpoint(url, transform), cycles);
+
+    let (response,) = result.map_err(|(code, msg)| format!("HTTP request failed: code = {:?}, message = {}", code, msg))?;
+
+    let status = response.status.0.to_u16().unwrap_or(500);
+    let body = String::from_utf8(response.body)
+        .map_err(|e| format!("Failed to decode response body: {}", e))?;
+
+    Ok(OutcallResponse { status, body })
+}
+
+// Horizon and RPC calls are metered by what they fetch, everything else by host
+fn metering_endpoint(url: &str, transform: Transform) -> String {
+    match transform {
+        Transform::Headers => url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_string()))
+            .unwrap_or_else(|| transform.name().to_string()),
+        _ => transform.name().to_string(),
+    }
+}
@@ -0,0 +1,323 @@
+use candid::CandidType;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::horizon::{self, AccountBalance};
+use crate::network;
+use crate::providers;
+use crate::public_key_stellar;
+use crate::rate_limit::guard_get_portfolio;
+use crate::transform::Transform;
+
+// Hourly buckets over the last day give the 24h change
+const HOUR_MILLIS: u64 = 3_600_000;
+const DAY_MILLIS: u64 = 24 * HOUR_MILLIS;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ReferenceAsset {
+    Usdc,
+    Xlm,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PortfolioHolding {
+    pub balance: AccountBalance,
+    // Price of one unit in the reference asset; None when no market was found
+    pub price: Option<String>,
+    pub value: Option<String>,
+    pub change_24h_percent: Option<f64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Portfolio {
+    pub address: String,
+    pub network: String,
+    pub reference: ReferenceAsset,
+    pub account_exists: bool,
+    pub holdings: Vec<PortfolioHolding>,
+    // Sum over the holdings that have a price
+    pub total_value: String,
+    pub total_change_24h_percent: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Asset {
+    Native,
+    Credit { code: String, issuer: String },
+}
+
+impl Asset {
+    fn of(balance: &AccountBalance) -> Option<Asset> {
+        match balance.asset_type.as_str() {
+            "native" => Some(Asset::Native),
+            "credit_alphanum4" | "credit_alphanum12" => Some(Asset::Credit {
+                code: balance.asset_code.clone(),
+                issuer: balance.asset_issuer.clone()?,
+            }),
+            // Liquidity pool shares are not traded on the DEX
+            _ => None,
+        }
+    }
+
+    // Horizon spells assets as `{prefix}asset_type`, `{prefix}asset_code` and `{prefix}asset_issuer`
+    fn params(&self, prefix: &str) -> Vec<(String, String)> {
+        match self {
+            Asset::Native => vec![(format!("{}asset_type", prefix), "native".to_string())],
+            Asset::Credit { code, issuer } => {
+                let asset_type = if code.len() <= 4 { "credit_alphanum4" } else { "credit_alphanum12" };
+                vec![
+                    (format!("{}asset_type", prefix), asset_type.to_string()),
+                    (format!("{}asset_code", prefix), code.clone()),
+                    (format!("{}asset_issuer", prefix), issuer.clone()),
+                ]
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Quote {
+    price: f64,
+    change_24h_percent: Option<f64>,
+}
+
+impl Quote {
+    // Price of A in C from A in B and B in C
+    fn then(self, next: Quote) -> Quote {
+        let change = match (self.change_24h_percent, next.change_24h_percent) {
+            (Some(a), Some(b)) => Some(((1.0 + a / 100.0) * (1.0 + b / 100.0) - 1.0) * 100.0),
+            _ => None,
+        };
+        Quote { price: self.price * next.price, change_24h_percent: change }
+    }
+}
+
+struct Pricer {
+    network: String,
+    reference: Asset,
+    // XLM in the reference asset, shared by every holding priced through XLM
+    xlm_quote: Option<Option<Quote>>,
+}
+
+impl Pricer {
+    async fn quote(&mut self, asset: &Asset) -> Result<Option<Quote>, String> {
+        if *asset == self.reference {
+            return Ok(Some(Quote { price: 1.0, change_24h_percent: Some(0.0) }));
+        }
+        if let Some(quote) = market_quote(&self.network, asset, &self.reference).await? {
+            return Ok(Some(quote));
+        }
+        if *asset == Asset::Native || self.reference == Asset::Native {
+            return Ok(None);
+        }
+
+        // Most assets only trade against XLM
+        let Some(in_xlm) = market_quote(&self.network, asset, &Asset::Native).await? else {
+            return Ok(None);
+        };
+        if self.xlm_quote.is_none() {
+            self.xlm_quote = Some(market_quote(&self.network, &Asset::Native, &self.reference).await?);
+        }
+        Ok(self.xlm_quote.flatten().map(|xlm| in_xlm.then(xlm)))
+    }
+}
+
+// Price from the close of the last closed hour; the 24h change compares it with the open of
+// the first hour of the day with trades.
+async fn market_quote(network: &str, base: &Asset, counter: &Asset) -> Result<Option<Quote>, String> {
+    let (start_time, end_time) = closed_hours(ic_cdk::api::time() / 1_000_000);
+
+    let mut params = base.params("base_");
+    params.extend(counter.params("counter_"));
+    params.extend([
+        ("start_time".to_string(), start_time.to_string()),
+        ("end_time".to_string(), end_time.to_string()),
+        ("resolution".to_string(), HOUR_MILLIS.to_string()),
+        ("order".to_string(), "asc".to_string()),
+        ("limit".to_string(), "200".to_string()),
+    ]);
+
+    let aggregations = horizon_json(network, "/trade_aggregations", &params, Transform::HorizonTradeAggregations).await?;
+    Ok(aggregation_quote(&aggregations))
+}
+
+// The day of hourly buckets ending at the start of the current hour. Every replica fetches the
+// aggregations on its own, so the bucket still taking trades (like the live order book) would
+// differ between them and fail consensus.
+fn closed_hours(now_millis: u64) -> (u64, u64) {
+    let end_time = now_millis / HOUR_MILLIS * HOUR_MILLIS;
+    (end_time - DAY_MILLIS, end_time)
+}
+
+fn aggregation_quote(aggregations: &Value) -> Option<Quote> {
+    let records = aggregations["_embedded"]["records"].as_array()?;
+    let price = |record: Option<&Value>, key: &str| {
+        record
+            .and_then(|record| record[key].as_str())
+            .and_then(|price| price.parse::<f64>().ok())
+    };
+
+    let close = price(records.last(), "close")?;
+    let change_24h_percent = price(records.first(), "open")
+        .filter(|open| *open > 0.0)
+        .map(|open| (close - open) / open * 100.0);
+    Some(Quote { price: close, change_24h_percent })
+}
+
+async fn horizon_json(
+    network: &str,
+    path: &str,
+    params: &[(String, String)],
+    transform: Transform,
+) -> Result<Value, String> {
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    let response = providers::horizon_get(network, &format!("{}?{}", path, query), 20_000, transform).await?;
+
+    if !response.is_success() {
+        return Err(format!("Horizon {} request failed: HTTP {} {}", path, response.status, response.body));
+    }
+    serde_json::from_str(&response.body).map_err(|e| format!("Failed to parse {} response: {}", path, e))
+}
+
+// Holdings with their value, the total value and the total 24h change
+fn valuation(priced: Vec<(AccountBalance, Option<Quote>)>) -> (Vec<PortfolioHolding>, f64, Option<f64>) {
+    let mut holdings = Vec::new();
+    let mut total_value = 0.0;
+    // Value of the holdings with a known change, now and 24h ago
+    let (mut changed_now, mut changed_before) = (0.0, 0.0);
+
+    for (balance, quote) in priced {
+        let amount = balance.balance.parse::<f64>().unwrap_or(0.0);
+        let value = quote.map(|quote| quote.price * amount);
+        if let Some(value) = value {
+            total_value += value;
+        }
+        if let (Some(value), Some(change)) = (value, quote.and_then(|quote| quote.change_24h_percent)) {
+            changed_now += value;
+            changed_before += value / (1.0 + change / 100.0);
+        }
+
+        holdings.push(PortfolioHolding {
+            balance,
+            price: quote.map(|quote| format_amount(quote.price)),
+            value: value.map(format_amount),
+            change_24h_percent: quote.and_then(|quote| quote.change_24h_percent),
+        });
+    }
+
+    let total_change_24h_percent =
+        (changed_before > 0.0).then(|| (changed_now - changed_before) / changed_before * 100.0);
+    (holdings, total_value, total_change_24h_percent)
+}
+
+fn format_amount(amount: f64) -> String {
+    format!("{:.7}", amount)
+}
+
+#[ic_cdk::update(guard = "guard_get_portfolio")]
+async fn get_portfolio(reference: Option<ReferenceAsset>, network: Option<String>) -> Result<Portfolio, String> {
+    let network = network::resolve(network)?;
+    let reference = reference.unwrap_or(ReferenceAsset::Usdc);
+
+    let reference_asset = match reference {
+        ReferenceAsset::Xlm => Asset::Native,
+        ReferenceAsset::Usdc => Asset::Credit {
+            code: "USDC".to_string(),
+            issuer: network::get(&network)?
+                .usdc_issuer
+                .ok_or_else(|| format!("No USDC issuer configured for {}", network))?,
+        },
+    };
+
+    let address = public_key_stellar().await?;
+    let balances = horizon::fetch_account_balances(&address, &network, false).await?;
+
+    let mut pricer = Pricer { network: network.clone(), reference: reference_asset, xlm_quote: None };
+    let mut priced = Vec::new();
+    for balance in balances.iter().flatten().map(AccountBalance::from) {
+        let quote = match Asset::of(&balance) {
+            Some(asset) => pricer.quote(&asset).await.unwrap_or_else(|e| {
+                ic_cdk::println!("Could not price {}: {}", balance.asset_code, e);
+                None
+            }),
+            None => None,
+        };
+        priced.push((balance, quote));
+    }
+    let (holdings, total_value, total_change_24h_percent) = valuation(priced);
+
+    Ok(Portfolio {
+        address,
+        network,
+        reference,
+        account_exists: balances.is_some(),
+        holdings,
+        total_value: format_amount(total_value),
+        total_change_24h_percent,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HORIZON_TRADE_AGGREGATIONS: &str = include_str!("../testdata/horizon_trade_aggregations.json");
+
+    fn balance(asset_code: &str, amount: &str) -> AccountBalance {
+        AccountBalance {
+            asset_type: if asset_code == "XLM" { "native" } else { "credit_alphanum4" }.to_string(),
+            asset_code: asset_code.to_string(),
+            asset_issuer: None,
+            liquidity_pool_id: None,
+            balance: amount.to_string(),
+            limit: None,
+            buying_liabilities: "0.0000000".to_string(),
+            selling_liabilities: "0.0000000".to_string(),
+            is_authorized: true,
+            is_authorized_to_maintain_liabilities: true,
+            is_clawback_enabled: false,
+        }
+    }
+
+    #[test]
+    fn only_closed_hours_are_queried() {
+        // 2025-06-02 11:14:39 UTC; the fixture was fetched for the day up to 11:00
+        let (start_time, end_time) = closed_hours(1_748_862_879_000);
+        assert_eq!((start_time, end_time), (1_748_775_600_000, 1_748_862_000_000));
+        assert_eq!(closed_hours(end_time), (start_time, end_time));
+    }
+
+    #[test]
+    fn quote_takes_the_last_close_and_the_first_open() {
+        let aggregations = serde_json::from_str(HORIZON_TRADE_AGGREGATIONS).unwrap();
+        let quote = aggregation_quote(&aggregations).unwrap();
+
+        assert_eq!(quote.price, 0.2647);
+        let change = quote.change_24h_percent.unwrap();
+        assert!((change - (0.2647 - 0.2598) / 0.2598 * 100.0).abs() < 1e-9);
+
+        let quiet = serde_json::json!({ "_embedded": { "records": [] } });
+        assert!(aggregation_quote(&quiet).is_none());
+    }
+
+    #[test]
+    fn valuation_sums_priced_holdings() {
+        let (holdings, total_value, total_change) = valuation(vec![
+            (balance("XLM", "1000.0000000"), Some(Quote { price: 0.25, change_24h_percent: Some(25.0) })),
+            (balance("USDC", "50.0000000"), Some(Quote { price: 1.0, change_24h_percent: Some(0.0) })),
+            // Priced, but without a day of trades to compare with
+            (balance("AQUA", "10.0000000"), Some(Quote { price: 2.0, change_24h_percent: None })),
+            (balance("NOPE", "99.0000000"), None),
+        ]);
+
+        assert_eq!(holdings.len(), 4);
+        assert_eq!(holdings[0].price.as_deref(), Some("0.2500000"));
+        assert_eq!(holdings[0].value.as_deref(), Some("250.0000000"));
+        assert_eq!(holdings[3].value, None);
+        assert_eq!(format_amount(total_value), "320.0000000");
+        // 300 now against 200 + 50 a day ago; AQUA has no change and is left out
+        assert!((total_change.unwrap() - 20.0).abs() < 1e-9);
+    }
+}
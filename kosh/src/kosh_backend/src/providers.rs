@@ -0,0 +1,148 @@
+use ic_cdk::api::management_canister::http_request::HttpHeader;
+use std::fmt::Debug;
+
+use crate::network;
+use crate::outcall::{self, header, OutcallResponse};
+use crate::transform::Transform;
+
+struct Request<'a> {
+    // Appended to the provider's base URL, e.g. "/accounts/G..."
+    path: &'a str,
+    headers: Vec<HttpHeader>,
+    body: Option<Vec<u8>>,
+    max_response_bytes: u64,
+    transform: Transform,
+}
+
+// Server errors and rate limiting are the provider's problem, not the request's
+fn should_fail_over(status: u16) -> bool {
+    status >= 500 || status == 429
+}
+
+// Try each provider in order until one answers. Returns the index of the provider that
+// answered; when every provider fails with an HTTP error, the last response is returned
+// so callers can report it as usual.
+async fn send(bases: &[String], request: &Request<'_>) -> Result<(usize, OutcallResponse), String> {
+    let mut last_response = None;
+    let mut last_error = "no providers configured".to_string();
+
+    for (index, base) in bases.iter().enumerate() {
+        let url = format!("{}{}", base.trim_end_matches('/'), request.path);
+        let result = match &request.body {
+            Some(body) => {
+                outcall::post(&url, request.headers.clone(), body.clone(), request.max_response_bytes, request.transform)
+                    .await
+            }
+            None => outcall::get(&url, request.headers.clone(), request.max_response_bytes, request.transform).await,
+        };
+
+        match result {
+            Ok(response) if !should_fail_over(response.status) => return Ok((index, response)),
+            Ok(response) => {
+                ic_cdk::println!("{} answered HTTP {}, trying the next provider", base, response.status);
+                last_error = format!("HTTP {} from {}", response.status, base);
+                last_response = Some((index, response));
+            }
+            // Timeouts and unreachable hosts
+            Err(e) => {
+                ic_cdk::println!("{} failed: {}, trying the next provider", base, e);
+                last_error = e;
+            }
+        }
+    }
+
+    last_response.ok_or_else(|| format!("All providers failed: {}", last_error))
+}
+
+pub async fn horizon_get(
+    network: &str,
+    path: &str,
+    max_response_bytes: u64,
+    transform: Transform,
+) -> Result<OutcallResponse, String> {
+    let bases = network::get(network)?.horizon_urls();
+    let request = Request { path, headers: vec![], body: None, max_response_bytes, transform };
+    Ok(send(&bases, &request).await?.1)
+}
+
+pub async fn horizon_post_form(
+    network: &str,
+    path: &str,
+    body: String,
+    max_response_bytes: u64,
+    transform: Transform,
+) -> Result<OutcallResponse, String> {
+    let bases = network::get(network)?.horizon_urls();
+    let request = Request {
+        path,
+        headers: vec![header("Content-Type", "application/x-www-form-urlencoded")],
+        body: Some(body.into_bytes()),
+        max_response_bytes,
+        transform,
+    };
+    Ok(send(&bases, &request).await?.1)
+}
+
+pub async fn rpc_post(
+    network: &str,
+    body: String,
+    max_response_bytes: u64,
+    transform: Transform,
+) -> Result<OutcallResponse, String> {
+    let bases = network::get(network)?.soroban_rpc_urls();
+    let request = Request {
+        path: "",
+        headers: vec![header("Content-Type", "application/json")],
+        body: Some(body.into_bytes()),
+        max_response_bytes,
+        transform,
+    };
+    Ok(send(&bases, &request).await?.1)
+}
+
+// Horizon read whose result is used to move funds. With `verify_critical_reads` set on the
+// network, a second provider has to return the same value; Horizon responses embed
+// provider-specific links, so the extracted value is compared rather than the raw body.
+pub async fn horizon_get_verified<T, F>(
+    network: &str,
+    path: &str,
+    max_response_bytes: u64,
+    transform: Transform,
+    extract: F,
+) -> Result<T, String>
+where
+    T: PartialEq + Debug,
+    F: Fn(&OutcallResponse) -> Result<T, String>,
+{
+    let config = network::get(network)?;
+    let bases = config.horizon_urls();
+    let verify = config.verify_critical_reads.unwrap_or(false);
+    // Refuse rather than silently skip the check; network::configure rejects this too
+    if verify && bases.len() < 2 {
+        return Err(format!("Cannot verify {}: network {} has a single Horizon provider", path, network));
+    }
+    let request = Request { path, headers: vec![], body: None, max_response_bytes, transform };
+
+    let (first_index, response) = send(&bases, &request).await?;
+    let first = extract(&response)?;
+
+    if !verify {
+        return Ok(first);
+    }
+
+    let others: Vec<String> = bases
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != first_index)
+        .map(|(_, base)| base.clone())
+        .collect();
+    let (_, response) = send(&others, &request)
+        .await
+        .map_err(|e| format!("Could not verify {} with a second provider: {}", path, e))?;
+    let second = extract(&response)?;
+
+    if first != second {
+        return Err(format!("Horizon providers disagree on {}: {:?} vs {:?}", path, first, second));
+    }
+    Ok(first)
+}
@@ -0,0 +1,286 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use crate::require_controller;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const NANOS_PER_DAY: u64 = 86_400 * NANOS_PER_SECOND;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    // Calls one principal may make to one endpoint within `window_seconds`
+    pub max_calls: u32,
+    pub window_seconds: u64,
+    // Calls one principal may make to one endpoint per UTC day
+    pub daily_quota: u32,
+}
+
+// `default` applies to every guarded endpoint without its own entry
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct RateLimits {
+    pub default: RateLimit,
+    pub endpoints: Vec<(String, RateLimit)>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        // The indexers make many outcalls per call
+        let indexer = RateLimit { max_calls: 2, window_seconds: 60, daily_quota: 50 };
+        RateLimits {
+            default: RateLimit { max_calls: 10, window_seconds: 60, daily_quota: 500 },
+            endpoints: vec![
+                ("evm_block_fetch".to_string(), indexer),
+                ("start_periodic_fetch".to_string(), indexer),
+            ],
+        }
+    }
+}
+
+impl RateLimits {
+    fn for_endpoint(&self, endpoint: &str) -> RateLimit {
+        self.endpoints
+            .iter()
+            .find(|(name, _)| name == endpoint)
+            .map_or(self.default, |(_, limit)| *limit)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Usage {
+    window_start: u64,
+    window_calls: u32,
+    day: u64,
+    day_calls: u32,
+    total_calls: u64,
+    last_call_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EndpointUsage {
+    pub principal: Principal,
+    pub endpoint: String,
+    pub calls_today: u32,
+    pub total_calls: u64,
+    // Nanoseconds since the epoch
+    pub last_call_at: u64,
+}
+
+thread_local! {
+    static LIMITS: RefCell<RateLimits> = RefCell::new(RateLimits::default());
+    static USAGE: RefCell<BTreeMap<(Principal, String), Usage>> = const { RefCell::new(BTreeMap::new()) };
+    // Day USAGE was last swept of finished entries
+    static SWEPT_DAY: Cell<u64> = const { Cell::new(0) };
+}
+
+enum Access {
+    // Signs with the caller's derived key, so the anonymous principal (shared by everyone) is refused
+    Signing,
+    Metered,
+}
+
+fn check(endpoint: &str, access: Access) -> Result<(), String> {
+    let caller = ic_cdk::api::caller();
+
+    if matches!(access, Access::Signing) && caller == Principal::anonymous() {
+        return Err(format!("{} requires an authenticated principal", endpoint));
+    }
+    // Controllers operate the canister; timers run as the canister itself
+    if caller == ic_cdk::id() || ic_cdk::api::is_controller(&caller) {
+        return Ok(());
+    }
+
+    let limit = LIMITS.with(|limits| limits.borrow().for_endpoint(endpoint));
+    let now = ic_cdk::api::time();
+
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        if SWEPT_DAY.with(|day| day.replace(now / NANOS_PER_DAY)) != now / NANOS_PER_DAY {
+            LIMITS.with(|limits| sweep(&mut usage, &limits.borrow(), now));
+        }
+        let entry = usage.entry((caller, endpoint.to_string())).or_default();
+
+        if now.saturating_sub(entry.window_start) >= limit.window_seconds * NANOS_PER_SECOND {
+            entry.window_start = now;
+            entry.window_calls = 0;
+        }
+        if entry.day != now / NANOS_PER_DAY {
+            entry.day = now / NANOS_PER_DAY;
+            entry.day_calls = 0;
+        }
+
+        if entry.window_calls >= limit.max_calls {
+            return Err(format!(
+                "Rate limit exceeded for {}: at most {} calls per {} seconds",
+                endpoint, limit.max_calls, limit.window_seconds
+            ));
+        }
+        if entry.day_calls >= limit.daily_quota {
+            return Err(format!(
+                "Daily quota exceeded for {}: at most {} calls per day",
+                endpoint, limit.daily_quota
+            ));
+        }
+
+        entry.window_calls += 1;
+        entry.day_calls += 1;
+        entry.total_calls += 1;
+        entry.last_call_at = now;
+        Ok(())
+    })
+}
+
+// Drop entries that no longer limit anything: last used before today, with their window
+// closed. Principals cost nothing to create, so otherwise the table only ever grows.
+fn sweep(usage: &mut BTreeMap<(Principal, String), Usage>, limits: &RateLimits, now: u64) {
+    usage.retain(|(_, endpoint), entry| {
+        let window = limits.for_endpoint(endpoint).window_seconds * NANOS_PER_SECOND;
+        entry.day == now / NANOS_PER_DAY || now.saturating_sub(entry.window_start) < window
+    });
+}
+
+// `#[ic_cdk::update(guard = "...")]` only takes a function name and an update call cannot
+// ask for its own method name, so every guarded endpoint gets a `guard_{endpoint}` function.
+macro_rules! guards {
+    ($($access:ident: $($guard:ident),+;)+) => {
+        $($(
+            pub fn $guard() -> Result<(), String> {
+                check(stringify!($guard).trim_start_matches("guard_"), Access::$access)
+            }
+        )+)+
+    };
+}
+
+guards! {
+    Signing:
+        guard_build_stellar_transaction,
+        guard_execute_token_swap,
+        guard_create_trustline,
+        guard_remove_trustline,
+        guard_sign_stellar_swap,
+        guard_execute_bridge_lock,
+        guard_invoke_contract,
+        guard_transfer_token,
+        guard_approve_token,
+        guard_confirm_sep7_request,
+        guard_sep10_sign_challenge;
+    Metered:
+        guard_public_key_stellar,
+        guard_generate_key_pair_evm,
+        guard_get_account_assets,
+        guard_get_portfolio,
+        guard_get_token_info,
+        guard_get_token_balance,
+        guard_add_token_contract,
+        guard_check_trustline,
+        guard_check_trustlines,
+        guard_get_transaction_history,
+        guard_resolve_federation_address,
+        guard_prepare_sep7_uri,
+        guard_sep10_register_token,
+        guard_start_anchor_deposit,
+        guard_start_anchor_withdrawal,
+        guard_record_anchor_transfer,
+        guard_refresh_anchor_transfer,
+        guard_evm_block_fetch,
+        guard_start_periodic_fetch;
+}
+
+pub fn snapshot_limits() -> RateLimits {
+    LIMITS.with(|limits| limits.borrow().clone())
+}
+
+pub fn restore_limits(saved: RateLimits) {
+    LIMITS.with(|limits| *limits.borrow_mut() = saved);
+}
+
+// Today's counters only; earlier ones no longer limit anything
+pub fn snapshot_usage() -> Vec<EndpointUsage> {
+    let today = ic_cdk::api::time() / NANOS_PER_DAY;
+    USAGE.with(|usage| {
+        usage
+            .borrow()
+            .iter()
+            .filter(|(_, usage)| usage.day == today)
+            .map(|((principal, endpoint), usage)| EndpointUsage {
+                principal: *principal,
+                endpoint: endpoint.clone(),
+                calls_today: usage.day_calls,
+                total_calls: usage.total_calls,
+                last_call_at: usage.last_call_at,
+            })
+            .collect()
+    })
+}
+
+pub fn restore_usage(saved: Vec<EndpointUsage>) {
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        for entry in saved {
+            usage.insert(
+                (entry.principal, entry.endpoint),
+                Usage {
+                    day: entry.last_call_at / NANOS_PER_DAY,
+                    day_calls: entry.calls_today,
+                    total_calls: entry.total_calls,
+                    last_call_at: entry.last_call_at,
+                    ..Default::default()
+                },
+            );
+        }
+    });
+}
+
+#[ic_cdk::query]
+fn get_rate_limits() -> RateLimits {
+    snapshot_limits()
+}
+
+#[ic_cdk::update]
+fn set_rate_limits(limits: RateLimits) -> Result<(), String> {
+    require_controller("manage rate limits")?;
+
+    for limit in std::iter::once(&limits.default).chain(limits.endpoints.iter().map(|(_, limit)| limit)) {
+        if limit.max_calls == 0 || limit.window_seconds == 0 {
+            return Err("Rate limits need at least one call per window and a window of at least a second".to_string());
+        }
+    }
+    restore_limits(limits);
+    Ok(())
+}
+
+// Busiest principals first; pass a principal to see only its usage
+#[ic_cdk::query]
+fn get_rate_limit_usage(principal: Option<Principal>) -> Result<Vec<EndpointUsage>, String> {
+    require_controller("manage rate limits")?;
+
+    let mut usage: Vec<EndpointUsage> = snapshot_usage()
+        .into_iter()
+        .filter(|entry| principal.is_none_or(|principal| entry.principal == principal))
+        .collect();
+    usage.sort_by_key(|entry| std::cmp::Reverse(entry.calls_today));
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_keeps_only_entries_still_limiting() {
+        let now = 20_000 * NANOS_PER_DAY + 30 * NANOS_PER_SECOND;
+        let key = |id: u8, endpoint: &str| (Principal::from_slice(&[id]), endpoint.to_string());
+        let used = |at: u64| Usage { window_start: at, window_calls: 1, day: at / NANOS_PER_DAY, day_calls: 1, total_calls: 1, last_call_at: at };
+        let mut usage = BTreeMap::from([
+            (key(1, "get_portfolio"), used(now - 10 * NANOS_PER_SECOND)),
+            // Yesterday, but its 60 second window is still open
+            (key(2, "get_portfolio"), used(now - 40 * NANOS_PER_SECOND)),
+            (key(3, "get_portfolio"), used(now - NANOS_PER_DAY)),
+        ]);
+        sweep(&mut usage, &RateLimits::default(), now);
+
+        let kept: Vec<_> = usage.keys().cloned().collect();
+        assert_eq!(kept, vec![key(1, "get_portfolio"), key(2, "get_portfolio")]);
+    }
+}
@@ -0,0 +1,422 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use candid::{CandidType, Principal};
+use ed25519_dalek::{Signature as Ed25519Signature, VerifyingKey};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use stellar_xdr::curr::{Memo, OperationBody, Preconditions, TransactionEnvelope, TransactionV1Envelope};
+
+use crate::network;
+use crate::stellar_toml::{fetch_stellar_toml, NETWORK_PASSPHRASE, SIGNING_KEY, WEB_AUTH_ENDPOINT};
+use crate::{
+    decode_envelope, decode_stellar_address, encode_envelope, muxed_account_key,
+    public_key_stellar, sign_envelope, transaction_hash,
+};
+use crate::rate_limit::{guard_sep10_register_token, guard_sep10_sign_challenge};
+
+// Allowed clock drift between the canister and the anchor when checking time bounds
+const TIME_BOUNDS_GRACE_SECS: u64 = 300;
+// Tokens this close to expiry are not used for new requests
+const TOKEN_REFRESH_MARGIN_SECS: u64 = 60;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CachedToken {
+    pub token: String,
+    // Seconds since the epoch
+    pub expires_at: u64,
+}
+
+// (caller, home domain, network)
+pub type TokenKey = (Principal, String, String);
+
+thread_local! {
+    static SEP10_TOKENS: RefCell<HashMap<TokenKey, CachedToken>> = RefCell::new(HashMap::new());
+}
+
+pub fn snapshot() -> Vec<(TokenKey, CachedToken)> {
+    let now = now_secs();
+    SEP10_TOKENS.with(|tokens| {
+        tokens
+            .borrow()
+            .iter()
+            .filter(|(_, cached)| cached.expires_at > now)
+            .map(|(key, cached)| (key.clone(), cached.clone()))
+            .collect()
+    })
+}
+
+pub fn restore(entries: Vec<(TokenKey, CachedToken)>) {
+    SEP10_TOKENS.with(|tokens| *tokens.borrow_mut() = entries.into_iter().collect());
+}
+
+// The anchor hands every caller a fresh random nonce and JWT, so replicas fetching the
+// challenge or posting it back would each see a different response and the outcall could
+// never reach consensus. The client does both round trips with the anchor itself: the
+// canister checks and signs the challenge, then keeps the JWT the anchor returned for it.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Sep10Challenge {
+    // Challenge with the caller's signature added, to POST to `web_auth_endpoint`
+    pub signed_transaction: String,
+    pub web_auth_endpoint: String,
+}
+
+// Check a challenge the client fetched from the anchor's WEB_AUTH_ENDPOINT and sign it
+// with the caller's key
+#[ic_cdk::update(guard = "guard_sep10_sign_challenge")]
+async fn sep10_sign_challenge(
+    home_domain: String,
+    challenge_xdr: String,
+    network: Option<String>,
+) -> Result<Sep10Challenge, String> {
+    let network = network::resolve(network)?;
+    let toml = fetch_stellar_toml(&home_domain).await?;
+    let auth_endpoint = toml.require(WEB_AUTH_ENDPOINT)?.to_string();
+    let server_key = decode_stellar_address(toml.require(SIGNING_KEY)?)?;
+    let passphrase = &network::passphrase(&network)?;
+
+    if let Some(toml_passphrase) = toml.get(NETWORK_PASSPHRASE) {
+        if toml_passphrase != passphrase {
+            return Err(format!("{} does not serve the {} network", home_domain, network));
+        }
+    }
+
+    let web_auth_domain = url::Url::parse(&auth_endpoint)
+        .map_err(|e| format!("Invalid WEB_AUTH_ENDPOINT {}: {}", auth_endpoint, e))?
+        .host_str()
+        .ok_or("WEB_AUTH_ENDPOINT has no host")?
+        .to_string();
+    let client_key = decode_stellar_address(&public_key_stellar().await?)?;
+
+    let tx_envelope = validate_challenge(
+        decode_envelope(&challenge_xdr)?,
+        &server_key,
+        &client_key,
+        &home_domain,
+        &web_auth_domain,
+        passphrase,
+        now_secs(),
+    )?;
+
+    let signed_envelope = sign_envelope(tx_envelope, passphrase).await?;
+    Ok(Sep10Challenge {
+        signed_transaction: encode_envelope(&TransactionEnvelope::Tx(signed_envelope))?,
+        web_auth_endpoint: auth_endpoint,
+    })
+}
+
+// Keep the JWT the anchor issued for the signed challenge, for the anchor transfer endpoints.
+// Returns when it expires, in seconds since the epoch.
+#[ic_cdk::update(guard = "guard_sep10_register_token")]
+async fn sep10_register_token(home_domain: String, token: String, network: Option<String>) -> Result<u64, String> {
+    let network = network::resolve(network)?;
+    let toml = fetch_stellar_toml(&home_domain).await?;
+    let auth_endpoint = toml.require(WEB_AUTH_ENDPOINT)?.to_string();
+    let account = public_key_stellar().await?;
+    let expires_at = check_token(&token, &account, &auth_endpoint, &home_domain, now_secs())?;
+
+    SEP10_TOKENS.with(|tokens| {
+        tokens
+            .borrow_mut()
+            .insert((ic_cdk::caller(), home_domain, network), CachedToken { token, expires_at })
+    });
+    Ok(expires_at)
+}
+
+// Expiry of a JWT the anchor behind `auth_endpoint` issued to `account`
+fn check_token(token: &str, account: &str, auth_endpoint: &str, home_domain: &str, now: u64) -> Result<u64, String> {
+    let payload = token.split('.').nth(1).ok_or("Token is not a JWT")?;
+    let claims: serde_json::Value = URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()
+        .and_then(|payload| serde_json::from_slice(&payload).ok())
+        .ok_or("Token is not a JWT")?;
+
+    // `sub` is the account, as `G...:memo` when the challenge carried a memo
+    let subject = claims["sub"].as_str().unwrap_or_default();
+    if subject.split(':').next() != Some(account) {
+        return Err(format!("Token was issued to {}, not {}", subject, account));
+    }
+    // SEP-10 has `iss` name the web auth endpoint; some anchors give just their origin
+    let issuer = claims["iss"].as_str().ok_or("Token has no issuer")?;
+    let auth_host = url::Url::parse(auth_endpoint).ok().and_then(|url| url.host_str().map(str::to_string));
+    let issuer_host = url::Url::parse(issuer).ok().and_then(|url| url.host_str().map(str::to_string));
+    let issued_by_anchor = issuer.trim_end_matches('/') == auth_endpoint.trim_end_matches('/')
+        || issuer_host.is_some_and(|host| host == home_domain || Some(&host) == auth_host.as_ref());
+    if !issued_by_anchor {
+        return Err(format!("Token was issued by {}, not {}", issuer, auth_endpoint));
+    }
+    let expires_at = claims["exp"].as_u64().ok_or("Token has no expiry")?;
+    if expires_at <= now {
+        return Err("Token has expired".to_string());
+    }
+    Ok(expires_at)
+}
+
+// The caller's registered JWT for the anchor, while it is still valid
+pub fn sep10_token(home_domain: &str, network: &str) -> Result<String, String> {
+    let key = (ic_cdk::caller(), home_domain.to_string(), network.to_string());
+    let cached = SEP10_TOKENS.with(|tokens| tokens.borrow().get(&key).cloned());
+
+    match cached {
+        Some(cached) if cached.expires_at > now_secs() + TOKEN_REFRESH_MARGIN_SECS => Ok(cached.token),
+        _ => Err(format!(
+            "No valid SEP-10 token for {}; sign a challenge with sep10_sign_challenge and register the anchor's token",
+            home_domain
+        )),
+    }
+}
+
+// Checks a challenge against SEP-10: ManageData operations only, sequence 0, valid time
+// bounds, the expected home and web auth domains, and a signature from the anchor's key
+fn validate_challenge(
+    envelope: TransactionEnvelope,
+    server_key: &[u8; 32],
+    client_key: &[u8; 32],
+    home_domain: &str,
+    web_auth_domain: &str,
+    network_passphrase: &str,
+    now: u64,
+) -> Result<TransactionV1Envelope, String> {
+    let TransactionEnvelope::Tx(tx_envelope) = envelope else {
+        return Err("Challenge must be a V1 transaction envelope".to_string());
+    };
+    let tx = &tx_envelope.tx;
+
+    if muxed_account_key(&tx.source_account) != *server_key {
+        return Err("Challenge source account is not the anchor's SIGNING_KEY".to_string());
+    }
+
+    if tx.seq_num.0 != 0 {
+        return Err("Challenge sequence number must be 0".to_string());
+    }
+
+    let time_bounds = match &tx.cond {
+        Preconditions::Time(time_bounds) => Some(time_bounds),
+        Preconditions::V2(conditions) => conditions.time_bounds.as_ref(),
+        Preconditions::None => None,
+    }
+    .ok_or("Challenge has no time bounds")?;
+
+    let (min_time, max_time) = (time_bounds.min_time.0, time_bounds.max_time.0);
+    if max_time == 0 {
+        return Err("Challenge has no expiry".to_string());
+    }
+    if now + TIME_BOUNDS_GRACE_SECS < min_time || now > max_time + TIME_BOUNDS_GRACE_SECS {
+        return Err(format!(
+            "Challenge is outside its time bounds ({} - {}, now {})",
+            min_time, max_time, now
+        ));
+    }
+
+    if !matches!(tx.memo, Memo::None) {
+        return Err("Challenge must not carry a memo".to_string());
+    }
+
+    if tx.operations.is_empty() {
+        return Err("Challenge has no operations".to_string());
+    }
+
+    for (index, operation) in tx.operations.iter().enumerate() {
+        let OperationBody::ManageData(data) = &operation.body else {
+            return Err(format!("Challenge operation {} is not ManageData", index));
+        };
+
+        let source = operation
+            .source_account
+            .as_ref()
+            .map(muxed_account_key)
+            .ok_or_else(|| format!("Challenge operation {} has no source account", index))?;
+        let name = data.data_name.0.to_utf8_string_lossy();
+        let value = data.data_value.as_ref().map(|v| v.0.as_slice()).unwrap_or_default();
+
+        if index == 0 {
+            if source != *client_key {
+                return Err("First challenge operation is not sourced from the client account".to_string());
+            }
+            if name != format!("{} auth", home_domain) {
+                return Err(format!("Challenge is for '{}', expected '{} auth'", name, home_domain));
+            }
+            if value.len() != 64 {
+                return Err("Challenge nonce must be 64 bytes".to_string());
+            }
+        } else if name == "web_auth_domain" {
+            if source != *server_key || value != web_auth_domain.as_bytes() {
+                return Err("Challenge web_auth_domain does not match WEB_AUTH_ENDPOINT".to_string());
+            }
+        } else if name != "client_domain" && source != *server_key {
+            return Err(format!("Challenge operation {} must be sourced from the anchor account", index));
+        }
+    }
+
+    let hash = transaction_hash(tx, network_passphrase)?;
+    let verifying_key = VerifyingKey::from_bytes(server_key)
+        .map_err(|e| format!("Invalid anchor SIGNING_KEY: {}", e))?;
+
+    let signed_by_server = tx_envelope.signatures.iter().any(|decorated| {
+        decorated.hint.0 == server_key[28..32]
+            && Ed25519Signature::from_slice(decorated.signature.0.as_slice())
+                .map(|signature| verifying_key.verify_strict(&hash, &signature).is_ok())
+                .unwrap_or(false)
+    });
+    if !signed_by_server {
+        return Err("Challenge is not signed by the anchor's SIGNING_KEY".to_string());
+    }
+
+    Ok(tx_envelope)
+}
+
+fn now_secs() -> u64 {
+    ic_cdk::api::time() / 1_000_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use stellar_xdr::curr::{
+        DataValue, DecoratedSignature, ManageDataOp, MuxedAccount, Operation, SequenceNumber, Signature,
+        SignatureHint, TimeBounds, TimePoint, Transaction, TransactionExt, Uint256,
+    };
+
+    const PASSPHRASE: &str = "Test SDF Network ; September 2015";
+    const HOME_DOMAIN: &str = "testanchor.stellar.org";
+    const NOW: u64 = 1_700_000_000;
+    const CLIENT: [u8; 32] = [7; 32];
+
+    fn server() -> SigningKey {
+        SigningKey::from_bytes(&[42; 32])
+    }
+
+    fn manage_data(source: [u8; 32], name: &str, value: &[u8]) -> Operation {
+        Operation {
+            source_account: Some(MuxedAccount::Ed25519(Uint256(source))),
+            body: OperationBody::ManageData(ManageDataOp {
+                data_name: name.as_bytes().to_vec().try_into().unwrap(),
+                data_value: Some(DataValue(value.to_vec().try_into().unwrap())),
+            }),
+        }
+    }
+
+    // A challenge as the anchor issues it, after `change` and signed by `signer`
+    fn challenge(signer: &SigningKey, change: impl FnOnce(&mut Transaction)) -> TransactionEnvelope {
+        let server_key = server().verifying_key().to_bytes();
+        let mut tx = Transaction {
+            source_account: MuxedAccount::Ed25519(Uint256(server_key)),
+            fee: 200,
+            seq_num: SequenceNumber(0),
+            cond: Preconditions::Time(TimeBounds { min_time: TimePoint(NOW - 10), max_time: TimePoint(NOW + 900) }),
+            memo: Memo::None,
+            operations: vec![
+                manage_data(CLIENT, &format!("{} auth", HOME_DOMAIN), &[b'n'; 64]),
+                manage_data(server_key, "web_auth_domain", HOME_DOMAIN.as_bytes()),
+            ]
+            .try_into()
+            .unwrap(),
+            ext: TransactionExt::V0,
+        };
+        change(&mut tx);
+
+        let hash = transaction_hash(&tx, PASSPHRASE).unwrap();
+        let public_key = signer.verifying_key().to_bytes();
+        let signature = DecoratedSignature {
+            hint: SignatureHint(public_key[28..32].try_into().unwrap()),
+            signature: Signature(signer.sign(&hash).to_bytes().to_vec().try_into().unwrap()),
+        };
+        TransactionEnvelope::Tx(TransactionV1Envelope { tx, signatures: vec![signature].try_into().unwrap() })
+    }
+
+    fn validate(envelope: TransactionEnvelope) -> Result<TransactionV1Envelope, String> {
+        let server_key = server().verifying_key().to_bytes();
+        validate_challenge(envelope, &server_key, &CLIENT, HOME_DOMAIN, HOME_DOMAIN, PASSPHRASE, NOW)
+    }
+
+    #[test]
+    fn anchor_challenge_is_accepted() {
+        assert!(validate(challenge(&server(), |_| {})).is_ok());
+    }
+
+    #[test]
+    fn challenge_signed_by_another_key_is_rejected() {
+        let error = validate(challenge(&SigningKey::from_bytes(&[1; 32]), |_| {})).unwrap_err();
+        assert!(error.contains("not signed"), "{}", error);
+    }
+
+    #[test]
+    fn challenge_with_a_sequence_number_is_rejected() {
+        assert!(validate(challenge(&server(), |tx| tx.seq_num = SequenceNumber(1))).is_err());
+    }
+
+    #[test]
+    fn expired_challenge_is_rejected() {
+        let expired = challenge(&server(), |tx| {
+            tx.cond = Preconditions::Time(TimeBounds {
+                min_time: TimePoint(NOW - 2000),
+                max_time: TimePoint(NOW - TIME_BOUNDS_GRACE_SECS - 1),
+            })
+        });
+        assert!(validate(expired).is_err());
+    }
+
+    #[test]
+    fn challenge_for_another_domain_is_rejected() {
+        let other = challenge(&server(), |tx| {
+            let mut operations = tx.operations.to_vec();
+            operations[0] = manage_data(CLIENT, "evil.example auth", &[b'n'; 64]);
+            tx.operations = operations.try_into().unwrap();
+        });
+        assert!(validate(other).is_err());
+    }
+
+    #[test]
+    fn challenge_for_another_client_is_rejected() {
+        let other = challenge(&server(), |tx| {
+            let mut operations = tx.operations.to_vec();
+            operations[0] = manage_data([8; 32], &format!("{} auth", HOME_DOMAIN), &[b'n'; 64]);
+            tx.operations = operations.try_into().unwrap();
+        });
+        assert!(validate(other).is_err());
+    }
+
+    fn jwt(claims: serde_json::Value) -> String {
+        format!("e30.{}.c2ln", URL_SAFE_NO_PAD.encode(claims.to_string()))
+    }
+
+    const AUTH_ENDPOINT: &str = "https://testanchor.stellar.org/auth";
+
+    fn check(token: &str, account: &str) -> Result<u64, String> {
+        check_token(token, account, AUTH_ENDPOINT, HOME_DOMAIN, NOW)
+    }
+
+    #[test]
+    fn token_must_belong_to_the_account() {
+        let account = "GAYOLLLUIZE4DZMBB2ZBKGBUBZLIOYU6XFLW37GBP2VZD3ABNXCW4BVA";
+        let token = jwt(serde_json::json!({ "iss": AUTH_ENDPOINT, "sub": format!("{}:42", account), "exp": NOW + 600 }));
+        assert_eq!(check(&token, account), Ok(NOW + 600));
+
+        let other = jwt(serde_json::json!({ "iss": AUTH_ENDPOINT, "sub": "GBOTHER", "exp": NOW + 600 }));
+        assert!(check(&other, account).is_err());
+
+        let expired = jwt(serde_json::json!({ "iss": AUTH_ENDPOINT, "sub": account, "exp": NOW - 1 }));
+        assert!(check(&expired, account).is_err());
+        let no_expiry = jwt(serde_json::json!({ "iss": AUTH_ENDPOINT, "sub": account }));
+        assert!(check(&no_expiry, account).is_err());
+        assert!(check("not-a-jwt", account).is_err());
+    }
+
+    #[test]
+    fn token_must_come_from_the_anchor() {
+        let account = "GAYOLLLUIZE4DZMBB2ZBKGBUBZLIOYU6XFLW37GBP2VZD3ABNXCW4BVA";
+        let token = |issuer: Option<&str>| {
+            let mut claims = serde_json::json!({ "sub": account, "exp": NOW + 600 });
+            if let Some(issuer) = issuer {
+                claims["iss"] = issuer.into();
+            }
+            jwt(claims)
+        };
+        assert!(check(&token(Some("https://testanchor.stellar.org/auth/")), account).is_ok());
+        assert!(check(&token(Some("https://testanchor.stellar.org")), account).is_ok());
+        assert!(check(&token(Some("https://evil.example.com/auth")), account).is_err());
+        assert!(check(&token(Some("testanchor.stellar.org")), account).is_err());
+        assert!(check(&token(None), account).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use candid::{CandidType, Principal};
+use ed25519_dalek::{Signature as Ed25519Signature, VerifyingKey};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use stellar_xdr::curr::{MuxedAccount, Operation, OperationBody, PaymentOp, SequenceNumber, TransactionEnvelope, Uint256};
+
+use crate::network;
+use crate::outcall::{self, header};
+use crate::transform::Transform;
+use crate::stellar_toml::fetch_stellar_toml;
+use crate::tx_ledger::{TxKind, TxRequest};
+use crate::tx_result::{SubmittedTransaction, TxError};
+use crate::{
+    build_transaction_xdr, decode_envelope, decode_stellar_address, encode_envelope, get_sequence_number,
+    muxed_account_key, parse_asset, parse_memo, parse_stellar_amount, public_key_stellar,
+    sign_envelope, sign_transaction_stellar,
+};
+use crate::rate_limit::{guard_confirm_sep7_request, guard_prepare_sep7_uri};
+
+const URI_SCHEME: &str = "web+stellar:";
+const URI_REQUEST_SIGNING_KEY: &str = "URI_REQUEST_SIGNING_KEY";
+// Prepared requests the user never confirms are dropped after an hour
+const PENDING_TTL_NANOS: u64 = 3_600_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Sep7Request {
+    pub id: u64,
+    // "pay" or "tx"
+    pub operation: String,
+    pub network: String,
+    // Unsigned transaction the user is asked to confirm
+    pub xdr: String,
+    pub destination: Option<String>,
+    pub amount: Option<String>,
+    pub asset_code: Option<String>,
+    pub asset_issuer: Option<String>,
+    pub memo: Option<String>,
+    pub memo_type: Option<String>,
+    pub msg: Option<String>,
+    // Set only when the URI's signature was verified against the domain's URI_REQUEST_SIGNING_KEY
+    pub origin_domain: Option<String>,
+    pub callback: Option<String>,
+    pub created_at: u64,
+}
+
+// What confirm_sep7_request did with the signed transaction
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Sep7Outcome {
+    Submitted(SubmittedTransaction),
+    // Posted to the URI's `callback` instead of the network
+    Callback { url: String, response: String },
+}
+
+thread_local! {
+    static PENDING_SEP7: RefCell<HashMap<(Principal, u64), Sep7Request>> = RefCell::new(HashMap::new());
+    static NEXT_SEP7_ID: RefCell<u64> = const { RefCell::new(1) };
+}
+
+// A pending request keyed by (caller, request ID)
+pub type PendingSep7 = ((Principal, u64), Sep7Request);
+
+pub fn snapshot() -> (Vec<PendingSep7>, u64) {
+    let pending = PENDING_SEP7.with(|pending| pending.borrow().iter().map(|(key, r)| (*key, r.clone())).collect());
+    (pending, NEXT_SEP7_ID.with(|id| *id.borrow()))
+}
+
+pub fn restore(entries: Vec<PendingSep7>, next_id: u64) {
+    PENDING_SEP7.with(|pending| *pending.borrow_mut() = entries.into_iter().collect());
+    NEXT_SEP7_ID.with(|id| *id.borrow_mut() = next_id);
+}
+
+// Parse a web+stellar: URI into an unsigned transaction awaiting the user's confirmation
+#[ic_cdk::update(guard = "guard_prepare_sep7_uri")]
+async fn prepare_sep7_uri(uri: String, network: Option<String>) -> Result<Sep7Request, String> {
+    let network = network::resolve(network)?;
+    let uri = uri.trim();
+
+    let rest = uri.strip_prefix(URI_SCHEME).ok_or("Not a web+stellar: URI")?;
+    let operation = rest.split('?').next().unwrap_or_default().to_string();
+    let parsed = url::Url::parse(uri).map_err(|e| format!("Invalid SEP-7 URI: {}", e))?;
+    let params: HashMap<String, String> = parsed.query_pairs().into_owned().collect();
+    let param = |name: &str| params.get(name).filter(|v| !v.is_empty()).cloned();
+
+    if let Some(passphrase) = param("network_passphrase") {
+        if passphrase != network::passphrase(&network)? {
+            return Err(format!("URI is for a different network than {}", network));
+        }
+    }
+
+    let origin_domain = match param("origin_domain") {
+        Some(domain) => {
+            verify_uri_signature(uri, &domain, param("signature").as_deref()).await?;
+            Some(domain)
+        }
+        None => None,
+    };
+
+    if let Some(callback) = param("callback") {
+        if !callback.starts_with("url:https://") {
+            return Err("SEP-7 callback must be an https url: callback".to_string());
+        }
+    }
+
+    let mut request = Sep7Request {
+        id: 0,
+        operation: operation.clone(),
+        network: network.clone(),
+        xdr: String::new(),
+        destination: param("destination"),
+        amount: param("amount"),
+        asset_code: param("asset_code"),
+        asset_issuer: param("asset_issuer"),
+        memo: param("memo"),
+        memo_type: param("memo_type"),
+        msg: param("msg"),
+        origin_domain,
+        callback: param("callback"),
+        created_at: ic_cdk::api::time(),
+    };
+
+    request.xdr = match operation.as_str() {
+        "pay" => {
+            let destination = request.destination.as_deref().ok_or("Payment URI has no destination")?;
+            let amount = request.amount.as_deref().ok_or("Payment URI has no amount")?;
+            let asset = parse_asset(
+                request.asset_code.as_deref().unwrap_or("XLM"),
+                request.asset_issuer.as_deref(),
+            )?;
+            let memo = parse_memo(request.memo_type.as_deref(), request.memo.as_deref())?;
+
+            let payment = Operation {
+                source_account: None,
+                body: OperationBody::Payment(PaymentOp {
+                    destination: MuxedAccount::Ed25519(Uint256(decode_stellar_address(destination)?)),
+                    asset,
+                    amount: parse_stellar_amount(amount)?,
+                }),
+            };
+            build_transaction_xdr(vec![payment], memo, &network).await?
+        }
+        "tx" => prepare_tx(param("xdr").ok_or("Transaction URI has no xdr")?, param("replace"), &network).await?,
+        other => return Err(format!("Unsupported SEP-7 operation: {}", other)),
+    };
+
+    let caller = ic_cdk::caller();
+    let now = ic_cdk::api::time();
+    request.id = NEXT_SEP7_ID.with(|next| {
+        let mut next = next.borrow_mut();
+        let id = *next;
+        *next += 1;
+        id
+    });
+
+    PENDING_SEP7.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        pending.retain(|_, stored| stored.created_at + PENDING_TTL_NANOS > now);
+        pending.insert((caller, request.id), request.clone());
+    });
+
+    ic_cdk::println!("Prepared SEP-7 {} request {}", request.operation, request.id);
+    Ok(request)
+}
+
+// Sign a confirmed request and submit it, or hand it to the URI's callback when one was given
+#[ic_cdk::update(guard = "guard_confirm_sep7_request")]
+async fn confirm_sep7_request(id: u64) -> Result<Sep7Outcome, TxError> {
+    let request = PENDING_SEP7
+        .with(|pending| pending.borrow_mut().remove(&(ic_cdk::caller(), id)))
+        .ok_or_else(|| format!("Unknown or expired SEP-7 request: {}", id))?;
+
+    let Some(callback) = request.callback.as_deref().and_then(|c| c.strip_prefix("url:")) else {
+        let kind = if request.operation == "pay" { TxKind::Payment } else { TxKind::Other };
+        let params = serde_json::json!({
+            "sep7_operation": request.operation,
+            "destination": request.destination,
+            "amount": request.amount,
+            "asset_code": request.asset_code,
+            "asset_issuer": request.asset_issuer,
+            "memo": request.memo,
+            "origin_domain": request.origin_domain,
+        });
+        let submitted = sign_transaction_stellar(request.xdr, &request.network, TxRequest::new(kind, params)).await?;
+        return Ok(Sep7Outcome::Submitted(submitted));
+    };
+
+    let TransactionEnvelope::Tx(tx_envelope) = decode_envelope(&request.xdr)? else {
+        return Err("Invalid transaction envelope type".into());
+    };
+    let signed = sign_envelope(tx_envelope, &network::passphrase(&request.network)?).await?;
+    let signed_xdr = encode_envelope(&TransactionEnvelope::Tx(signed))?;
+
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("xdr", &signed_xdr)
+        .finish();
+    let response = outcall::post(
+        callback,
+        vec![header("Content-Type", "application/x-www-form-urlencoded")],
+        body.into_bytes(),
+        20_000,
+        Transform::Headers,
+    )
+    .await?;
+
+    if !response.is_success() {
+        return Err(format!("Callback rejected the transaction: HTTP {} {}", response.status, response.body).into());
+    }
+
+    Ok(Sep7Outcome::Callback {
+        url: callback.to_string(),
+        response: response.body,
+    })
+}
+
+#[ic_cdk::query]
+fn get_sep7_requests() -> Vec<Sep7Request> {
+    let caller = ic_cdk::caller();
+    PENDING_SEP7.with(|pending| {
+        pending
+            .borrow()
+            .iter()
+            .filter(|((owner, _), _)| *owner == caller)
+            .map(|(_, request)| request.clone())
+            .collect()
+    })
+}
+
+// A tx= request must end up sourced from the caller. `replace` may ask for the source
+// account (and with it the sequence number) to be filled in by the wallet.
+async fn prepare_tx(xdr: String, replace: Option<String>, network: &str) -> Result<String, String> {
+    let TransactionEnvelope::Tx(mut tx_envelope) = decode_envelope(&xdr)? else {
+        return Err("SEP-7 transaction must be a V1 envelope".to_string());
+    };
+
+    let account = public_key_stellar().await?;
+    let account_key = decode_stellar_address(&account)?;
+
+    let replaces_source = replace
+        .as_deref()
+        .map(|fields| fields.split(',').any(|field| field.trim().starts_with("sourceAccount")))
+        .unwrap_or(false);
+
+    if replaces_source {
+        tx_envelope.tx.source_account = MuxedAccount::Ed25519(Uint256(account_key));
+        tx_envelope.tx.seq_num = SequenceNumber(get_sequence_number(&account, network).await? + 1);
+    } else if muxed_account_key(&tx_envelope.tx.source_account) != account_key {
+        return Err("SEP-7 transaction is not sourced from your account".to_string());
+    }
+
+    // Any signatures were made over a different transaction once fields are replaced
+    tx_envelope.signatures = Default::default();
+    encode_envelope(&TransactionEnvelope::Tx(tx_envelope))
+}
+
+// SEP-7 signatures cover a 36-byte prefix (35 zero bytes then 4) followed by
+// "stellar.sep.7 - URI Scheme" and the URI up to, not including, &signature=
+async fn verify_uri_signature(uri: &str, origin_domain: &str, signature: Option<&str>) -> Result<(), String> {
+    let signature = signature.ok_or("URI has an origin_domain but no signature")?;
+    let signed_part = uri
+        .rsplit_once("&signature=")
+        .map(|(signed, _)| signed)
+        .ok_or("The signature must be the last URI parameter")?;
+
+    let toml = fetch_stellar_toml(origin_domain).await?;
+    let signing_key = decode_stellar_address(toml.require(URI_REQUEST_SIGNING_KEY)?)?;
+
+    let mut payload = vec![0u8; 36];
+    payload[35] = 4;
+    payload.extend_from_slice(b"stellar.sep.7 - URI Scheme");
+    payload.extend_from_slice(signed_part.as_bytes());
+
+    // An unescaped '+' in the base64 signature comes back from query parsing as a space
+    let signature = STANDARD
+        .decode(signature.replace(' ', "+"))
+        .map_err(|_| "URI signature is not valid base64")?;
+    let signature = Ed25519Signature::from_slice(&signature)
+        .map_err(|_| "URI signature must be 64 bytes")?;
+
+    VerifyingKey::from_bytes(&signing_key)
+        .map_err(|e| format!("Invalid URI_REQUEST_SIGNING_KEY: {}", e))?
+        .verify_strict(&payload, &signature)
+        .map_err(|_| format!("URI signature does not match {}", origin_domain))
+}
@@ -0,0 +1,451 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use stellar_xdr::curr::{
+    AccountId, BytesM, ContractEvent, Hash, HostFunction, Int128Parts, InvokeContractArgs, InvokeHostFunctionOp, Limited,
+    Limits, Memo, MuxedAccount, Operation, OperationBody, PublicKey, ReadXdr, ScAddress,
+    ScBytes, ScMap, ScMapEntry, ScString, ScSymbol, ScVal, ScVec, SequenceNumber, SorobanAuthorizationEntry,
+    SorobanCredentials, SorobanTransactionData, Transaction, TransactionEnvelope, TransactionExt,
+    TransactionMeta, TransactionV1Envelope, UInt128Parts, Uint256, VecM, WriteXdr,
+};
+
+use crate::confirmations;
+use crate::network;
+use crate::providers;
+use crate::rate_limit::guard_invoke_contract;
+use crate::transform::Transform;
+use crate::tx_ledger::{self, TxKind, TxRequest, TxStatus};
+use crate::tx_result::{SubmittedTransaction, TxError};
+use crate::{decode_stellar_address, encode_strkey, get_sequence_number, public_key_stellar, sign_transaction_xdr};
+
+// Inclusion fee on top of the resource fee returned by simulateTransaction
+const BASE_FEE: u32 = 100;
+// getTransaction checks before handing the transaction to the confirmation poller;
+// each outcall takes a few seconds, about one ledger close
+const MAX_POLLS: u32 = 5;
+// Transaction meta carries every ledger entry the call touched
+const MAX_XDR_BYTES: usize = 200_000;
+
+// Soroban value as Candid sees it; converted to and from ScVal
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ContractValue {
+    Void,
+    Bool(bool),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    U128(u128),
+    I128(i128),
+    Symbol(String),
+    String(String),
+    Bytes(Vec<u8>),
+    // G... account or C... contract
+    Address(String),
+    Vec(Vec<ContractValue>),
+    Map(Vec<(ContractValue, ContractValue)>),
+    // Any other ScVal, as base64 XDR
+    Xdr(String),
+}
+
+// What a call left behind once its transaction is in a ledger
+pub struct Receipt {
+    pub return_value: ScVal,
+    // Contract events with their Soroban RPC event IDs, in emission order
+    pub events: Vec<(String, ContractEvent)>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ContractCall {
+    pub transaction: SubmittedTransaction,
+    // None while the transaction is still pending
+    pub return_value: Option<ContractValue>,
+}
+
+impl ContractValue {
+    pub fn to_sc_val(&self) -> Result<ScVal, String> {
+        Ok(match self {
+            ContractValue::Void => ScVal::Void,
+            ContractValue::Bool(value) => ScVal::Bool(*value),
+            ContractValue::U32(value) => ScVal::U32(*value),
+            ContractValue::I32(value) => ScVal::I32(*value),
+            ContractValue::U64(value) => ScVal::U64(*value),
+            ContractValue::I64(value) => ScVal::I64(*value),
+            ContractValue::U128(value) => ScVal::U128(UInt128Parts { hi: (*value >> 64) as u64, lo: *value as u64 }),
+            ContractValue::I128(value) => ScVal::I128(Int128Parts { hi: (*value >> 64) as i64, lo: *value as u64 }),
+            ContractValue::Symbol(symbol) => ScVal::Symbol(ScSymbol(
+                symbol.clone().try_into().map_err(|_| format!("Invalid symbol: {}", symbol))?,
+            )),
+            ContractValue::String(string) => {
+                ScVal::String(ScString(string.clone().try_into().map_err(|_| "String too long")?))
+            }
+            ContractValue::Bytes(bytes) => ScVal::Bytes(ScBytes(BytesM::try_from(bytes.clone()).map_err(|_| "Bytes too long")?)),
+            ContractValue::Address(address) => ScVal::Address(parse_address(address)?),
+            ContractValue::Vec(items) => {
+                let items = items.iter().map(ContractValue::to_sc_val).collect::<Result<Vec<_>, _>>()?;
+                ScVal::Vec(Some(ScVec(items.try_into().map_err(|_| "Vec too long")?)))
+            }
+            // Soroban rejects maps whose keys are not sorted
+            ContractValue::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, val)| Ok(ScMapEntry { key: key.to_sc_val()?, val: val.to_sc_val()? }))
+                    .collect::<Result<Vec<_>, String>>()?;
+                ScVal::Map(Some(ScMap::sorted_from(entries).map_err(|e| format!("Invalid map: {}", e))?))
+            }
+            ContractValue::Xdr(xdr) => from_xdr(xdr)?,
+        })
+    }
+
+    pub fn from_sc_val(value: &ScVal) -> ContractValue {
+        match value {
+            ScVal::Void => ContractValue::Void,
+            ScVal::Bool(value) => ContractValue::Bool(*value),
+            ScVal::U32(value) => ContractValue::U32(*value),
+            ScVal::I32(value) => ContractValue::I32(*value),
+            ScVal::U64(value) => ContractValue::U64(*value),
+            ScVal::I64(value) => ContractValue::I64(*value),
+            ScVal::U128(parts) => ContractValue::U128(((parts.hi as u128) << 64) | parts.lo as u128),
+            ScVal::I128(parts) => ContractValue::I128(((parts.hi as i128) << 64) | parts.lo as i128),
+            ScVal::Symbol(symbol) => ContractValue::Symbol(symbol.to_utf8_string_lossy()),
+            ScVal::String(string) => ContractValue::String(string.to_utf8_string_lossy()),
+            ScVal::Bytes(bytes) => ContractValue::Bytes(bytes.to_vec()),
+            ScVal::Address(address) => ContractValue::Address(format_address(address)),
+            ScVal::Vec(Some(items)) => ContractValue::Vec(items.iter().map(ContractValue::from_sc_val).collect()),
+            ScVal::Map(Some(entries)) => ContractValue::Map(
+                entries
+                    .iter()
+                    .map(|entry| (ContractValue::from_sc_val(&entry.key), ContractValue::from_sc_val(&entry.val)))
+                    .collect(),
+            ),
+            other => ContractValue::Xdr(to_xdr(other).unwrap_or_default()),
+        }
+    }
+}
+
+pub fn parse_address(address: &str) -> Result<ScAddress, String> {
+    let key = decode_stellar_address(address)?;
+    match address.chars().next() {
+        Some('G') => Ok(ScAddress::Account(AccountId(PublicKey::PublicKeyTypeEd25519(Uint256(key))))),
+        Some('C') => Ok(ScAddress::Contract(Hash(key))),
+        _ => Err(format!("Expected a G... account or C... contract address: {}", address)),
+    }
+}
+
+pub fn format_address(address: &ScAddress) -> String {
+    match address {
+        ScAddress::Account(AccountId(PublicKey::PublicKeyTypeEd25519(Uint256(key)))) => crate::encode_stellar_address(key),
+        // 2 << 3 is the contract version byte ('C...')
+        ScAddress::Contract(Hash(hash)) => encode_strkey(0x10, hash),
+    }
+}
+
+pub fn to_xdr<T: WriteXdr>(value: &T) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    let mut writer = Limited::new(&mut bytes, Limits { depth: 100, len: MAX_XDR_BYTES });
+    value.write_xdr(&mut writer).map_err(|e| format!("Failed to serialize XDR: {}", e))?;
+    Ok(STANDARD.encode(bytes))
+}
+
+pub fn from_xdr<T: ReadXdr>(xdr_base64: &str) -> Result<T, String> {
+    let bytes = STANDARD.decode(xdr_base64.trim()).map_err(|e| format!("Failed to decode XDR: {}", e))?;
+    let mut reader = Limited::new(bytes.as_slice(), Limits { depth: 100, len: MAX_XDR_BYTES });
+    T::read_xdr(&mut reader).map_err(|e| format!("Failed to parse XDR: {}", e))
+}
+
+// JSON-RPC call to the network's Soroban RPC providers, returning `result`
+pub async fn rpc_call(
+    network: &str,
+    method: &str,
+    params: Value,
+    max_response_bytes: u64,
+    transform: Transform,
+) -> Result<Value, String> {
+    let body = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 8675309,
+        "method": method,
+        "params": params,
+    });
+
+    let response = providers::rpc_post(network, body.to_string(), max_response_bytes, transform).await?;
+    if !response.is_success() {
+        return Err(format!("Soroban RPC {} failed: HTTP {} {}", method, response.status, response.body));
+    }
+
+    let mut json: Value = serde_json::from_str(&response.body)
+        .map_err(|e| format!("Failed to parse {} response: {}", method, e))?;
+    if let Some(error) = json.get("error") {
+        return Err(format!("Soroban RPC {} error: {}", method, error));
+    }
+    match json.get_mut("result") {
+        Some(result) => Ok(result.take()),
+        None => Err(format!("Soroban RPC {} returned no result", method)),
+    }
+}
+
+// A single InvokeHostFunction transaction calling `function` on `contract_id`, without resources yet
+pub fn invocation(
+    source: [u8; 32],
+    sequence: i64,
+    contract_id: &str,
+    function: &str,
+    args: &[ContractValue],
+) -> Result<Transaction, String> {
+    let ScAddress::Contract(contract) = parse_address(contract_id)? else {
+        return Err(format!("Not a contract address: {}", contract_id));
+    };
+    let args = args.iter().map(ContractValue::to_sc_val).collect::<Result<Vec<_>, _>>()?;
+
+    let operation = Operation {
+        source_account: None,
+        body: OperationBody::InvokeHostFunction(InvokeHostFunctionOp {
+            host_function: HostFunction::InvokeContract(InvokeContractArgs {
+                contract_address: ScAddress::Contract(contract),
+                function_name: ScSymbol(
+                    function.try_into().map_err(|_| format!("Invalid function name: {}", function))?,
+                ),
+                args: args.try_into().map_err(|_| "Too many arguments")?,
+            }),
+            auth: VecM::default(),
+        }),
+    };
+
+    Ok(Transaction {
+        source_account: MuxedAccount::Ed25519(Uint256(source)),
+        fee: BASE_FEE,
+        seq_num: SequenceNumber(sequence),
+        cond: confirmations::validity_window(),
+        memo: Memo::None,
+        operations: vec![operation].try_into().map_err(|_| "Too many operations")?,
+        ext: TransactionExt::V0,
+    })
+}
+
+pub struct Simulation {
+    pub transaction_data: SorobanTransactionData,
+    pub auth: Vec<SorobanAuthorizationEntry>,
+    pub return_value: ScVal,
+}
+
+pub async fn simulate(network: &str, transaction: &Transaction) -> Result<Simulation, String> {
+    let envelope = TransactionEnvelope::Tx(TransactionV1Envelope {
+        tx: transaction.clone(),
+        signatures: VecM::default(),
+    });
+    let params = serde_json::json!({ "transaction": to_xdr(&envelope)? });
+    let result = rpc_call(network, "simulateTransaction", params, 100_000, Transform::RpcSimulateTransaction).await?;
+
+    if let Some(error) = result.get("error").and_then(|e| e.as_str()) {
+        return Err(format!("Simulation failed: {}", error));
+    }
+    if result.get("restorePreamble").is_some() {
+        return Err("The contract touches archived ledger entries; restore them before calling it".to_string());
+    }
+
+    let transaction_data = result["transactionData"]
+        .as_str()
+        .ok_or("Simulation returned no transaction data")?;
+    let first = &result["results"][0];
+    let auth = first["auth"]
+        .as_array()
+        .map(|entries| entries.iter().filter_map(|entry| entry.as_str()).map(from_xdr).collect())
+        .transpose()?
+        .unwrap_or_default();
+
+    Ok(Simulation {
+        transaction_data: from_xdr(transaction_data)?,
+        auth,
+        return_value: first["xdr"].as_str().map(from_xdr).transpose()?.unwrap_or(ScVal::Void),
+    })
+}
+
+// Result of a read-only call, from simulation alone. Simulation needs a source account but
+// not a funded one, so reads go out from the all-zero account and need no signature.
+pub async fn read_contract(
+    network: &str,
+    contract_id: &str,
+    function: &str,
+    args: &[ContractValue],
+) -> Result<ContractValue, String> {
+    let transaction = invocation([0; 32], 0, contract_id, function, args)?;
+    let simulation = simulate(network, &transaction).await?;
+    Ok(ContractValue::from_sc_val(&simulation.return_value))
+}
+
+pub async fn latest_ledger(network: &str) -> Result<u32, String> {
+    let result = rpc_call(network, "getLatestLedger", serde_json::json!({}), 10_000, Transform::RpcLatestLedger).await?;
+    result["sequence"]
+        .as_u64()
+        .map(|sequence| sequence as u32)
+        .ok_or_else(|| "getLatestLedger returned no sequence".to_string())
+}
+
+// Apply the simulated footprint, resource fee and authorizations to the transaction
+fn assemble(mut transaction: Transaction, simulation: Simulation) -> Result<Transaction, String> {
+    if let Some(entry) = simulation
+        .auth
+        .iter()
+        .find(|entry| !matches!(entry.credentials, SorobanCredentials::SourceAccount))
+    {
+        let SorobanCredentials::Address(credentials) = &entry.credentials else { unreachable!() };
+        return Err(format!(
+            "The call needs a separate authorization from {}",
+            format_address(&credentials.address)
+        ));
+    }
+
+    let resource_fee = u32::try_from(simulation.transaction_data.resource_fee)
+        .map_err(|_| "Resource fee out of range")?;
+    transaction.fee = BASE_FEE.checked_add(resource_fee).ok_or("Resource fee out of range")?;
+
+    let mut operations = transaction.operations.to_vec();
+    if let Some(OperationBody::InvokeHostFunction(op)) = operations.first_mut().map(|op| &mut op.body) {
+        op.auth = simulation.auth.try_into().map_err(|_| "Too many authorization entries")?;
+    }
+    transaction.operations = operations.try_into().map_err(|_| "Too many operations")?;
+    transaction.ext = TransactionExt::V1(simulation.transaction_data);
+
+    Ok(transaction)
+}
+
+// Submit through sendTransaction and record the outcome in the caller's transaction ledger
+async fn send_transaction(signed_xdr: &str, network: &str, request: &TxRequest) -> Result<SubmittedTransaction, TxError> {
+    let params = serde_json::json!({ "transaction": signed_xdr });
+    let result = rpc_call(network, "sendTransaction", params, 20_000, Transform::RpcSendTransaction).await?;
+
+    let hash = result["hash"].as_str().unwrap_or_default().to_string();
+    let outcome = match result["status"].as_str().unwrap_or_default() {
+        "PENDING" | "DUPLICATE" => Ok(SubmittedTransaction {
+            explorer_url: crate::explorer_tx_url(network, &hash),
+            hash,
+            network: network.to_string(),
+            status: TxStatus::Pending,
+        }),
+        "TRY_AGAIN_LATER" => return Err(TxError::retry_later("Stellar network is busy; try again shortly".to_string())),
+        status => {
+            let error_result_xdr = result["errorResultXdr"].as_str().unwrap_or_default();
+            Err(confirmations::failure(error_result_xdr)
+                .unwrap_or_else(|| TxError::unknown(format!("Transaction rejected ({})", status))))
+        }
+    };
+
+    let status = match &outcome {
+        Ok(submitted) => submitted.status.clone(),
+        Err(e) => TxStatus::Failed(e.message.clone()),
+    };
+    if let Err(e) = tx_ledger::record(signed_xdr, network, request, status) {
+        ic_cdk::println!("Failed to record transaction: {}", e);
+    }
+    outcome
+}
+
+// Soroban RPC event ID: the operation's TOID (ledger, application order, operation index 0)
+// zero-padded to 19 digits, then the event's index within the operation
+pub fn event_id(ledger: u32, application_order: u32, index: usize) -> String {
+    let toid = ((ledger as u64) << 32) | ((application_order as u64) << 12);
+    format!("{:019}-{:010}", toid, index)
+}
+
+// Final status and receipt from getTransaction, or None while it is not in a ledger yet
+async fn fetch_result(hash: &str, network: &str) -> Result<Option<Result<Receipt, TxError>>, String> {
+    let params = serde_json::json!({ "hash": hash });
+    let result = rpc_call(network, "getTransaction", params, MAX_XDR_BYTES as u64, Transform::RpcGetTransaction).await?;
+
+    match result["status"].as_str().unwrap_or_default() {
+        "SUCCESS" => {
+            let meta = result["resultMetaXdr"].as_str().ok_or("Transaction has no result meta")?;
+            let TransactionMeta::V3(meta) = from_xdr(meta)? else {
+                return Err("Transaction meta has no Soroban return value".to_string());
+            };
+            let Some(soroban_meta) = meta.soroban_meta else {
+                return Err("Transaction meta has no Soroban return value".to_string());
+            };
+
+            let ledger = result["ledger"].as_u64().unwrap_or_default() as u32;
+            let application_order = result["applicationOrder"].as_u64().unwrap_or_default() as u32;
+            let events = soroban_meta
+                .events
+                .iter()
+                .enumerate()
+                .map(|(index, event)| (event_id(ledger, application_order, index), event.clone()))
+                .collect();
+            Ok(Some(Ok(Receipt { return_value: soroban_meta.return_value, events })))
+        }
+        "FAILED" => {
+            let result_xdr = result["resultXdr"].as_str().unwrap_or_default();
+            Ok(Some(Err(confirmations::failure(result_xdr)
+                .unwrap_or_else(|| TxError::unknown("Transaction failed in ledger".to_string())))))
+        }
+        _ => Ok(None),
+    }
+}
+
+// Simulate, assemble, sign and submit a call from the caller's account, then wait briefly
+// for its receipt; None when it is still pending
+pub async fn call_contract(
+    contract_id: &str,
+    function: &str,
+    args: &[ContractValue],
+    network: &str,
+    request: TxRequest,
+) -> Result<(SubmittedTransaction, Option<Receipt>), TxError> {
+    let source_address = public_key_stellar().await?;
+    let sequence = get_sequence_number(&source_address, network).await?;
+    let transaction = invocation(decode_stellar_address(&source_address)?, sequence + 1, contract_id, function, args)?;
+
+    let simulation = simulate(network, &transaction).await?;
+    let transaction = assemble(transaction, simulation)?;
+    ic_cdk::println!("Calling {}.{} with fee {}", contract_id, function, transaction.fee);
+
+    let unsigned = to_xdr(&TransactionEnvelope::Tx(TransactionV1Envelope {
+        tx: transaction,
+        signatures: VecM::default(),
+    }))?;
+    let signed = sign_transaction_xdr(&unsigned, network).await?;
+    let mut submitted = send_transaction(&signed, network, &request).await?;
+
+    for _ in 0..MAX_POLLS {
+        match fetch_result(&submitted.hash, network).await {
+            Ok(Some(Ok(receipt))) => {
+                tx_ledger::update_status(ic_cdk::caller(), &submitted.hash, TxStatus::Success);
+                submitted.status = TxStatus::Success;
+                return Ok((submitted, Some(receipt)));
+            }
+            Ok(Some(Err(e))) => {
+                tx_ledger::update_status(ic_cdk::caller(), &submitted.hash, TxStatus::Failed(e.message.clone()));
+                return Err(e);
+            }
+            Ok(None) => {}
+            // Replicas that disagree on whether the ledger has closed yet fail the outcall
+            Err(e) => ic_cdk::println!("getTransaction for {} failed: {}", submitted.hash, e),
+        }
+    }
+
+    // Still pending: the confirmation poller follows it from here
+    confirmations::track_pending();
+    Ok((submitted, None))
+}
+
+#[ic_cdk::update(guard = "guard_invoke_contract")]
+async fn invoke_contract(
+    contract_id: String,
+    function: String,
+    args: Vec<ContractValue>,
+    network: Option<String>,
+) -> Result<ContractCall, TxError> {
+    let network = network::resolve(network)?;
+    let request = TxRequest::new(TxKind::ContractCall, serde_json::json!({
+        "contract_id": contract_id,
+        "function": function,
+        "args": args,
+        "network": network,
+    }));
+    let (transaction, receipt) = call_contract(&contract_id, &function, &args, &network, request).await?;
+
+    Ok(ContractCall {
+        transaction,
+        return_value: receipt.map(|receipt| ContractValue::from_sc_val(&receipt.return_value)),
+    })
+}
@@ -0,0 +1,122 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+
+use crate::anchor::{self, AnchorTransfer};
+use crate::bridge_release::{self, BridgeRelease};
+use crate::confirmations;
+use crate::cycles::{self, CyclesUsage};
+use crate::event_store;
+use crate::network::{self, NetworkConfig};
+use crate::rate_limit::{self, EndpointUsage, RateLimits};
+use crate::sep10::{self, CachedToken, TokenKey};
+use crate::sep7::{self, PendingSep7};
+use crate::stellar_indexer::{self, CandidContractEvent, IndexerState};
+use crate::subscriptions::{self, Subscription};
+use crate::token::{self, TokenInfo};
+use crate::tx_ledger::{self, TxRecord};
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct InitArgs {
+    pub networks: Vec<NetworkConfig>,
+    // Nodes in the subnet the canister runs on, used to price outcalls and signing (default 13)
+    pub subnet_size: Option<u32>,
+}
+
+// Everything that has to survive an upgrade. Fields added later must be `Option`:
+// Candid only accepts a missing field for `opt` types, even with `#[serde(default)]`.
+#[derive(CandidType, Deserialize, Default)]
+struct StableState {
+    #[serde(default)]
+    tx_ledger: Vec<(Principal, Vec<TxRecord>)>,
+    networks: Option<Vec<NetworkConfig>>,
+    subnet_size: Option<u32>,
+    cycles_usage: Option<Vec<CyclesUsage>>,
+    rate_limits: Option<RateLimits>,
+    rate_limit_usage: Option<Vec<EndpointUsage>>,
+    token_contracts: Option<Vec<(Principal, Vec<TokenInfo>)>>,
+    stellar_indexer: Option<IndexerState>,
+    stellar_events: Option<Vec<CandidContractEvent>>,
+    event_subscriptions: Option<Vec<Subscription>>,
+    bridge_releases: Option<Vec<BridgeRelease>>,
+    anchor_transfers: Option<Vec<((Principal, String), AnchorTransfer)>>,
+    sep10_tokens: Option<Vec<(TokenKey, CachedToken)>>,
+    sep7_requests: Option<Vec<PendingSep7>>,
+    sep7_next_id: Option<u64>,
+}
+
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    configure(args);
+}
+
+fn configure(args: Option<InitArgs>) {
+    let Some(args) = args else {
+        return;
+    };
+
+    network::configure(args.networks).expect("Invalid network configuration");
+    if let Some(size) = args.subnet_size {
+        cycles::set_subnet_size(size).expect("Invalid subnet size");
+    }
+}
+
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    let (sep7_requests, sep7_next_id) = sep7::snapshot();
+    let state = StableState {
+        tx_ledger: tx_ledger::snapshot(),
+        networks: Some(network::snapshot()),
+        subnet_size: Some(cycles::subnet_size()),
+        cycles_usage: Some(cycles::snapshot()),
+        rate_limits: Some(rate_limit::snapshot_limits()),
+        rate_limit_usage: Some(rate_limit::snapshot_usage()),
+        token_contracts: Some(token::snapshot()),
+        stellar_indexer: Some(stellar_indexer::snapshot()),
+        stellar_events: Some(event_store::snapshot()),
+        event_subscriptions: Some(subscriptions::snapshot()),
+        bridge_releases: Some(bridge_release::snapshot()),
+        anchor_transfers: Some(anchor::snapshot()),
+        sep10_tokens: Some(sep10::snapshot()),
+        sep7_requests: Some(sep7_requests),
+        sep7_next_id: Some(sep7_next_id),
+    };
+
+    ic_cdk::storage::stable_save((state,)).expect("Failed to save state to stable memory");
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    // The first upgrade from a build without saved state finds nothing to restore
+    let state = match ic_cdk::storage::stable_restore::<(StableState,)>() {
+        Ok((state,)) => state,
+        Err(e) => {
+            ic_cdk::println!("No saved state restored: {}", e);
+            StableState::default()
+        }
+    };
+
+    tx_ledger::restore(state.tx_ledger);
+    network::restore(state.networks.unwrap_or_default());
+    if let Some(size) = state.subnet_size {
+        cycles::set_subnet_size(size).expect("Invalid saved subnet size");
+    }
+    cycles::restore(state.cycles_usage.unwrap_or_default());
+    if let Some(limits) = state.rate_limits {
+        rate_limit::restore_limits(limits);
+    }
+    rate_limit::restore_usage(state.rate_limit_usage.unwrap_or_default());
+    token::restore(state.token_contracts.unwrap_or_default());
+    stellar_indexer::restore(state.stellar_indexer.unwrap_or_default());
+    event_store::restore(state.stellar_events.unwrap_or_default());
+    if let Some(saved) = state.event_subscriptions {
+        subscriptions::restore(saved);
+    }
+    bridge_release::restore(state.bridge_releases.unwrap_or_default());
+    anchor::restore(state.anchor_transfers.unwrap_or_default());
+    sep10::restore(state.sep10_tokens.unwrap_or_default());
+    sep7::restore(state.sep7_requests.unwrap_or_default(), state.sep7_next_id.unwrap_or(1));
+    configure(args);
+    // Timers do not survive an upgrade
+    confirmations::track_pending();
+    stellar_indexer::resume();
+}
@@ -1,28 +1,35 @@
-use ic_cdk::api::management_canister::http_request::{
-    HttpHeader, HttpMethod, HttpResponse, CanisterHttpRequestArgument
-};
 use serde::{Deserialize, Serialize};
-use stellar_xdr::curr;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use stellar_xdr::curr::ScVal;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
 use candid::CandidType;
-use hex;
-use std::str::FromStr;
+use ic_cdk_timers::TimerId;
 
+use crate::bridge_release::{self, LockSource};
 use crate::eth::send_eth_evm;
-
-
-
-
-// Contract ID we want to fetch events for
-const CONTRACT_ID: &str = "CDTA5IYGUGRI4PAGXJL7TPBEIC3EZY6V23ILF5EDVXFVLCGGMVOK4CRL";
-// RPC endpoint URL
-const RPC_URL: &str = "https://soroban-testnet.stellar.org";
-
-// Store events in memory
-thread_local! {
-    static EVENTS: RefCell<HashMap<String, CandidContractEvent>> = RefCell::new(HashMap::new());
-}
+use crate::event_store;
+use crate::providers;
+use crate::require_controller;
+use crate::soroban::{self, ContractValue};
+use crate::subscriptions::{self, EventFilter, EventHandler};
+use crate::token;
+use crate::transform::Transform;
+
+// Bridge contract: subscribed until subscriptions are changed, and called by execute_bridge_lock
+pub const CONTRACT_ID: &str = "CDTA5IYGUGRI4PAGXJL7TPBEIC3EZY6V23ILF5EDVXFVLCGGMVOK4CRL";
+// Network the contract is deployed on; its RPC endpoint comes from the network registry
+pub const INDEXER_NETWORK: &str = "testnet";
+
+const INDEX_INTERVAL: Duration = Duration::from_secs(30);
+// Ledgers one getEvents request covers, about half an hour of 5 second ledgers
+const MAX_LEDGERS_PER_WINDOW: u32 = 360;
+const EVENTS_PER_PAGE: u32 = 1000;
+// getEvents pages read per timer tick, so a long backlog is worked off over several ticks
+const MAX_PAGES_PER_PASS: u32 = 5;
+// Oldest gaps are dropped beyond this many
+const MAX_GAPS: usize = 100;
+// Releases pay native ETH, in wei
+const ETH_DECIMALS: u32 = 18;
 
 // Request structure for the JSON-RPC call
 #[derive(Serialize, Debug)]
@@ -35,541 +42,578 @@ struct GetEventsRequest {
 
 #[derive(Serialize, Debug)]
 struct GetEventsParams {
-    #[serde(rename = "startLedger")]
-    start_ledger: u32,
+    #[serde(rename = "startLedger", skip_serializing_if = "Option::is_none")]
+    start_ledger: Option<u32>,
+    #[serde(rename = "endLedger")]
+    end_ledger: u32,
     #[serde(rename = "xdrFormat")]
     xdr_format: String,
     filters: Vec<EventFilter>,
     pagination: PaginationOptions,
 }
 
-#[derive(Serialize, Debug)]
-struct EventFilter {
-    #[serde(rename = "type")]
-    filter_type: String,
-    #[serde(rename = "contractIds")]
-    contract_ids: Vec<String>,
-    topics: Vec<String>,
-}
-
 #[derive(Serialize, Debug)]
 struct PaginationOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
     limit: u32,
 }
 
 // Response structures for the JSON-RPC result
 #[derive(Deserialize, Debug, Clone)]
 struct RpcResponse {
-    id: u32,
     result: EventsResponse,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 struct EventsResponse {
     events: Vec<ContractEvent>,
-    latest_ledger: u32,
+    // Where the next page starts
+    #[serde(default)]
+    cursor: Option<String>,
 }
 
+// One event with `xdrFormat: "base64"`: topics and value are base64 ScVal XDR
 #[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 struct ContractEvent {
+    #[serde(rename = "type", default)]
+    event_type: String,
     contract_id: String,
     id: String,
     ledger: u32,
+    tx_hash: String,
     topic: Vec<String>,
-    value: EventValue,
+    value: String,
+    #[serde(default)]
     paging_token: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-struct EventValue {
-    xdr: String,
+// Value of the bridge contract's `lock` event
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct BridgeLockEvent {
+    pub from_token: String,
+    pub dest_token: String,
+    pub in_amount: i128,
+    // EVM chain ID, sent as its big-endian bytes
+    pub dest_chain: u64,
+    pub recipient_address: String,
+}
+
+impl BridgeLockEvent {
+    pub fn from_value(value: &ContractValue) -> Result<BridgeLockEvent, String> {
+        let ContractValue::Map(entries) = value else {
+            return Err(format!("Lock event value is not a map: {:?}", value));
+        };
+        let field = |name: &str| {
+            entries
+                .iter()
+                .find(|(key, _)| *key == ContractValue::Symbol(name.to_string()))
+                .map(|(_, value)| value)
+                .ok_or_else(|| format!("Lock event has no {}", name))
+        };
+
+        let from_token = match field("from_token")? {
+            ContractValue::Address(address) => address.clone(),
+            other => return Err(format!("from_token is not an address: {:?}", other)),
+        };
+        let dest_token = match field("dest_token")? {
+            ContractValue::String(token) | ContractValue::Symbol(token) => token.clone(),
+            other => return Err(format!("dest_token is not a string: {:?}", other)),
+        };
+        let in_amount = match field("in_amount")? {
+            ContractValue::I128(amount) => *amount,
+            other => return Err(format!("in_amount is not an i128: {:?}", other)),
+        };
+        let dest_chain = match field("dest_chain")? {
+            ContractValue::Bytes(bytes) if bytes.len() <= 8 => {
+                bytes.iter().fold(0u64, |chain, byte| (chain << 8) | *byte as u64)
+            }
+            other => return Err(format!("dest_chain is not a chain ID: {:?}", other)),
+        };
+        let recipient_address = match field("recipient_address")? {
+            ContractValue::String(address) => address.clone(),
+            other => return Err(format!("recipient_address is not a string: {:?}", other)),
+        };
+
+        Ok(BridgeLockEvent { from_token, dest_token, in_amount, dest_chain, recipient_address })
+    }
 }
 
 // Candid-compatible types for the interface
-#[derive(Debug, Clone, CandidType)]
+#[derive(Debug, Clone, CandidType, Deserialize)]
 pub struct CandidContractEvent {
     pub contract_id: String,
     pub id: String,
     pub ledger: u32,
+    pub tx_hash: String,
+    // Base64 ScVal XDR of each topic
     pub topic: Vec<String>,
+    // Base64 ScVal XDR of the value
     pub xdr_value: String,
     pub paging_token: String,
+    pub topic_values: Vec<ContractValue>,
+    pub value: ContractValue,
+    // Set for `lock` events of the bridge contract
+    pub bridge_lock: Option<BridgeLockEvent>,
 }
 
-impl From<ContractEvent> for CandidContractEvent {
-    fn from(event: ContractEvent) -> Self {
-        CandidContractEvent {
+impl TryFrom<ContractEvent> for CandidContractEvent {
+    type Error = String;
+
+    fn try_from(event: ContractEvent) -> Result<Self, String> {
+        let topic_values = event
+            .topic
+            .iter()
+            .map(|topic| soroban::from_xdr::<ScVal>(topic).map(|topic| ContractValue::from_sc_val(&topic)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = ContractValue::from_sc_val(&soroban::from_xdr::<ScVal>(&event.value)?);
+
+        Ok(CandidContractEvent {
             contract_id: event.contract_id,
             id: event.id,
             ledger: event.ledger,
+            tx_hash: event.tx_hash,
             topic: event.topic,
-            xdr_value: event.value.xdr,
+            xdr_value: event.value,
             paging_token: event.paging_token,
+            topic_values,
+            value,
+            bridge_lock: None,
+        })
+    }
+}
+
+impl CandidContractEvent {
+    // Decode the value of a `lock` event of a bridge contract
+    pub fn with_bridge_lock(mut self) -> Result<Self, String> {
+        if self.topic_values.first() == Some(&ContractValue::Symbol("lock".to_string())) {
+            self.bridge_lock = Some(BridgeLockEvent::from_value(&self.value)?);
         }
+        Ok(self)
     }
 }
 
-// Define the transform function for HTTP responses
+// Status of the timer-driven indexer, kept across upgrades
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct IndexerState {
+    pub enabled: bool,
+    // First ledger of the next getEvents window
+    pub next_ledger: u32,
+    // getEvents cursor while a window at `next_ledger` spans several pages
+    pub paging_token: Option<String>,
+    pub last_processed_ledger: Option<u32>,
+    // Retention window of the RPC node at the last pass
+    pub latest_ledger: Option<u32>,
+    pub oldest_ledger: Option<u32>,
+    // Nanoseconds since the epoch
+    pub last_run_at: Option<u64>,
+    pub last_success_at: Option<u64>,
+    pub last_error: Option<String>,
+    pub events_indexed: u64,
+    pub gaps: Vec<LedgerGap>,
+}
 
+// Ledgers that fell out of the RPC retention window before they were indexed
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct LedgerGap {
+    pub from_ledger: u32,
+    pub to_ledger: u32,
+    pub detected_at: u64,
+}
 
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct IndexerStatus {
+    pub state: IndexerState,
+    pub subscriptions: u32,
+    pub network: String,
+    pub timer_running: bool,
+    // Ledgers between the last processed one and the latest one the RPC node had
+    pub lag_ledgers: Option<u32>,
+    pub seconds_since_last_success: Option<u64>,
+}
 
+thread_local! {
+    static INDEXER: RefCell<IndexerState> = RefCell::new(IndexerState::default());
+    static INDEX_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static INDEXING: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn snapshot() -> IndexerState {
+    INDEXER.with(|state| state.borrow().clone())
+}
+
+pub fn restore(state: IndexerState) {
+    INDEXER.with(|indexer| *indexer.borrow_mut() = state);
+}
+
+fn update_state(f: impl FnOnce(&mut IndexerState)) {
+    INDEXER.with(|state| f(&mut state.borrow_mut()))
+}
+
+// Restart the timer after an upgrade when the indexer was running
+pub fn resume() {
+    if snapshot().enabled {
+        start_timer();
+    }
+}
+
+fn start_timer() {
+    if INDEX_TIMER.with(|timer| timer.borrow().is_some()) {
+        return;
+    }
+    let timer_id = ic_cdk_timers::set_timer_interval(INDEX_INTERVAL, || ic_cdk::spawn(index_pass()));
+    INDEX_TIMER.with(|timer| *timer.borrow_mut() = Some(timer_id));
+}
+
+// Clears the in-flight flag even when the pass traps, since ic_cdk drops the future then
+struct PassGuard;
+
+impl Drop for PassGuard {
+    fn drop(&mut self) {
+        INDEXING.with(|indexing| indexing.set(false));
+    }
+}
 
+async fn index_pass() {
+    if INDEXING.with(|indexing| indexing.replace(true)) {
+        return;
+    }
+    let _guard = PassGuard;
+
+    let result = index_pages().await;
+    let now = ic_cdk::api::time();
+    update_state(|state| {
+        state.last_run_at = Some(now);
+        match result {
+            Ok(()) => {
+                state.last_success_at = Some(now);
+                state.last_error = None;
+            }
+            Err(e) => {
+                ic_cdk::println!("Stellar indexer pass failed: {}", e);
+                state.last_error = Some(e);
+            }
+        }
+    });
+}
+
+async fn index_pages() -> Result<(), String> {
+    let (oldest_ledger, latest_ledger) = fetch_ledger_range().await?;
+    let now = ic_cdk::api::time();
+    update_state(|state| {
+        state.latest_ledger = Some(latest_ledger);
+        state.oldest_ledger = Some(oldest_ledger);
+
+        if state.next_ledger < oldest_ledger {
+            ic_cdk::println!(
+                "Ledgers {}..{} left the RPC retention window before they were indexed",
+                state.next_ledger,
+                oldest_ledger - 1
+            );
+            state.gaps.push(LedgerGap { from_ledger: state.next_ledger, to_ledger: oldest_ledger - 1, detected_at: now });
+            if state.gaps.len() > MAX_GAPS {
+                state.gaps.remove(0);
+            }
+            state.next_ledger = oldest_ledger;
+            state.paging_token = None;
+        }
+    });
+
+    let filters = subscriptions::event_filters(&subscriptions::snapshot())?;
+    for _ in 0..MAX_PAGES_PER_PASS {
+        let state = snapshot();
+        if state.next_ledger > latest_ledger {
+            break;
+        }
+        // endLedger is exclusive; a closed window keeps the response the same on every replica
+        let end_ledger = latest_ledger.min(state.next_ledger + MAX_LEDGERS_PER_WINDOW - 1) + 1;
+        // getEvents without filters returns every event, so with nothing subscribed the window is skipped
+        let page = if filters.is_empty() {
+            EventsResponse { events: vec![], cursor: None }
+        } else {
+            fetch_events(state.next_ledger, end_ledger, state.paging_token, filters.clone()).await?
+        };
+        let page_full = page.events.len() >= EVENTS_PER_PAGE as usize;
+
+        for event in page.events {
+            // Move past the event before acting on it; bridge_release keeps a re-read event from paying twice
+            update_state(|state| {
+                state.paging_token = Some(event.id.clone());
+                state.events_indexed += 1;
+            });
+            process_event(event).await;
+        }
+
+        if page_full {
+            if let Some(cursor) = page.cursor {
+                update_state(|state| state.paging_token = Some(cursor));
+            }
+        } else {
+            update_state(|state| {
+                state.next_ledger = end_ledger;
+                state.paging_token = None;
+                state.last_processed_ledger = Some(end_ledger - 1);
+            });
+        }
+    }
+    Ok(())
+}
+
+async fn process_event(event: ContractEvent) {
+    let event_type = event.event_type.clone();
+    let event = match CandidContractEvent::try_from(event) {
+        Ok(event) => event,
+        Err(e) => {
+            ic_cdk::println!("Could not decode event: {}", e);
+            return;
+        }
+    };
+    // None when the subscription was removed while its events were being read
+    let event = match subscriptions::handler_for(&event.contract_id, &event_type, &event.topic_values) {
+        Some(EventHandler::BridgeRelease) => match event.with_bridge_lock() {
+            Ok(event) => event,
+            Err(e) => {
+                ic_cdk::println!("Could not decode lock event: {}", e);
+                return;
+            }
+        },
+        Some(EventHandler::StoreOnly) => event,
+        None => return,
+    };
+    event_store::insert(event.clone());
+
+    let Some(lock) = event.bridge_lock else {
+        return;
+    };
+    ic_cdk::println!("Bridge lock {} in {}: {:?}", event.id, event.tx_hash, lock);
+
+    let source = LockSource::Stellar { event_id: event.id.clone() };
+    let release = async {
+        let from_token = token::fetch_token_info(&lock.from_token, INDEXER_NETWORK).await?;
+        let amount_wei = scale_amount(lock.in_amount, from_token.decimals, ETH_DECIMALS)?;
+        send_eth_evm(lock.recipient_address, amount_wei, lock.dest_chain.to_string()).await
+    };
+    match bridge_release::release_once(source, release).await {
+        Ok(txn_hash) => ic_cdk::println!("ETH sent successfully! Transaction Hash: {}", txn_hash),
+        Err(e) => ic_cdk::println!("Error sending ETH: {}", e),
+    }
+}
+
+// Lock amount in the locked token's units to the released token's, rounding down
+fn scale_amount(amount: i128, from_decimals: u32, to_decimals: u32) -> Result<u128, String> {
+    let amount = u128::try_from(amount)
+        .ok()
+        .filter(|amount| *amount > 0)
+        .ok_or_else(|| format!("Invalid lock amount {}", amount))?;
+    let scaled = if to_decimals >= from_decimals {
+        10u128
+            .checked_pow(to_decimals - from_decimals)
+            .and_then(|factor| amount.checked_mul(factor))
+            .ok_or_else(|| format!("Lock amount {} is too large to release", amount))?
+    } else {
+        10u128.checked_pow(from_decimals - to_decimals).map_or(0, |factor| amount / factor)
+    };
+    if scaled == 0 {
+        return Err(format!("Lock amount {} is less than the smallest unit released", amount));
+    }
+    Ok(scaled)
+}
 
 #[derive(Serialize, Debug)]
-struct GetLatestLedgerRequest {
+struct GetHealthRequest {
     jsonrpc: String,
     id: u32,
     method: String,
 }
 
-async fn fetch_latest_ledger() -> Result<u32, String> {
-    let request = GetLatestLedgerRequest {
+// Oldest and latest ledger the RPC node still serves events for
+async fn fetch_ledger_range() -> Result<(u32, u32), String> {
+    let request = GetHealthRequest {
         jsonrpc: "2.0".to_string(),
         id: 8675309,
-        method: "getLatestLedger".to_string(),
+        method: "getHealth".to_string(),
     };
 
     let request_body = serde_json::to_string(&request)
         .map_err(|e| format!("Failed to serialize request: {}", e))?;
 
-    let request_headers = vec![
-        HttpHeader {
-            name: "Content-Type".to_string(),
-            value: "application/json".to_string(),
-        },
-    ];
-
-    let request_arg = CanisterHttpRequestArgument {
-        url: RPC_URL.to_string(),
-        method: HttpMethod::POST,
-        body: Some(request_body.into_bytes()),
-        max_response_bytes: Some(2_000_000),
-        transform: None,
-        headers: request_headers,
-    };
+    let response = providers::rpc_post(INDEXER_NETWORK, request_body, 2_000, Transform::RpcHealth).await?;
+    let json_value: serde_json::Value = serde_json::from_str(&response.body)
+        .map_err(|e| format!("Failed to parse JSON response: {}", e))?;
 
-    let result = ic_cdk::api::management_canister::http_request::http_request(
-        request_arg,
-        50_000_000_000,
-    )
-    .await;
+    let ledger = |key: &str| json_value["result"][key].as_u64().map(|ledger| ledger as u32);
+    match (ledger("oldestLedger"), ledger("latestLedger")) {
+        (Some(oldest), Some(latest)) => Ok((oldest, latest)),
+        _ => Err(format!("Failed to read the ledger range from getHealth: {}", response.body)),
+    }
+}
 
-    let (response,): (HttpResponse,) = match result {
-        Ok(res) => res,
-        Err((rejection_code, message)) => {
-            return Err(format!("HTTP request failed with code {:?}: {}", rejection_code, message));
-        }
+// Events in [start_ledger, end_ledger), resuming after `cursor` when given
+async fn fetch_events(
+    start_ledger: u32,
+    end_ledger: u32,
+    cursor: Option<String>,
+    filters: Vec<EventFilter>,
+) -> Result<EventsResponse, String> {
+    let request = GetEventsRequest {
+        jsonrpc: "2.0".to_string(),
+        id: 8675309,
+        method: "getEvents".to_string(),
+        params: GetEventsParams {
+            // getEvents refuses a start ledger together with a cursor
+            start_ledger: if cursor.is_none() { Some(start_ledger) } else { None },
+            end_ledger,
+            xdr_format: "base64".to_string(),
+            filters,
+            pagination: PaginationOptions {
+                cursor,
+                limit: EVENTS_PER_PAGE,
+            },
+        },
     };
 
-    let response_body = String::from_utf8(response.body)
-        .map_err(|e| format!("Failed to decode response body: {}", e))?;
-
-    ic_cdk::println!("Latest ledger response: {}", response_body);
-
-    let json_value: serde_json::Value = serde_json::from_str(&response_body)
-        .map_err(|e| format!("Failed to parse JSON response: {}", e))?;
+    let request_body = serde_json::to_string(&request)
+        .map_err(|e| format!("Failed to serialize request: {}", e))?;
 
-    if let Some(result) = json_value.get("result") {
-        if let Some(sequence) = result.get("sequence").and_then(|s| s.as_u64()) {
-            ic_cdk::println!("Latest ledger sequence: {}", sequence);
-            return Ok(sequence as u32);
-        }
+    let response = providers::rpc_post(INDEXER_NETWORK, request_body, 2_000_000, Transform::RpcEvents).await?;
+    let json_value: serde_json::Value = serde_json::from_str(&response.body)
+        .map_err(|e| format!("Failed to parse events response: {} {}", e, response.body))?;
+    if let Some(error) = json_value.get("error") {
+        return Err(format!("getEvents failed: {}", error));
     }
-
-    Err("Failed to extract ledger sequence from response".to_string())
+    let response: RpcResponse = serde_json::from_value(json_value)
+        .map_err(|e| format!("Failed to parse events response: {}", e))?;
+    Ok(response.result)
 }
 
+// Start indexing from `start_ledger`, or from where the indexer stopped (the latest
+// ledger the first time). Events are read every INDEX_INTERVAL.
+#[ic_cdk::update]
+async fn start_stellar_indexer(start_ledger: Option<u32>) -> Result<IndexerStatus, String> {
+    require_controller("run the Stellar indexer")?;
 
+    let next_ledger = match start_ledger {
+        Some(ledger) => Some(ledger),
+        None if snapshot().next_ledger == 0 => Some(fetch_ledger_range().await?.1),
+        None => None,
+    };
+    update_state(|state| {
+        if let Some(ledger) = next_ledger {
+            state.next_ledger = ledger;
+            state.paging_token = None;
+        }
+        state.enabled = true;
+    });
+    start_timer();
+    Ok(get_stellar_indexer_status())
+}
 
 #[ic_cdk::update]
-async fn fetch_stellar_events(ledger: u32) -> Result<String, String> {
-    ic_cdk::println!("Starting stellar events monitoring");
-    
-    let contract_id = CONTRACT_ID.to_string();
-    ic_cdk::println!("Contract ID: {}", contract_id);
-
-    let mut current_ledger = ledger; // Starting ledger
-    let mut result_summary = String::new();
-    
-    loop {
-        // Get the latest ledger in each iteration
-        let latest_ledger = match fetch_latest_ledger().await {
-            Ok(ledger) => ledger,
-            Err(e) => {
-                ic_cdk::println!("Error fetching latest ledger: {}", e);
-                continue;
-            }
-        };
-        
-        let request = GetEventsRequest {
-            jsonrpc: "2.0".to_string(),
-            id: 8675309,
-            method: "getEvents".to_string(),
-            params: GetEventsParams {
-                start_ledger: current_ledger,
-                xdr_format: "json".to_string(),
-                filters: vec![EventFilter {
-                    filter_type: "contract".to_string(),
-                    contract_ids: vec![contract_id.clone()],
-                    topics: vec![],
-                }],
-                pagination: PaginationOptions {
-                    limit: 1000,
-                },
-            },
-        };
-        
-        let request_body = serde_json::to_string(&request)
-            .map_err(|e| format!("Failed to serialize request: {}", e))?;
-        ic_cdk::println!("Request body: {}", request_body);
-        
-        let request_headers = vec![
-            HttpHeader {
-                name: "Content-Type".to_string(),
-                value: "application/json".to_string(),
-            },
-        ];
-        
-        let request_arg = CanisterHttpRequestArgument {
-            url: RPC_URL.to_string(),
-            method: HttpMethod::POST,
-            body: Some(request_body.into_bytes()),
-            max_response_bytes: Some(2_000_000),
-            transform: None,
-            headers: request_headers,
-        };
-        
-        match ic_cdk::api::management_canister::http_request::http_request(
-            request_arg,
-            50_000_000_000,
-        )
-        .await {
-            Ok((response,)) => {
-                if let Ok(response_body) = String::from_utf8(response.body.clone()) {
-                    if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(&response_body) {
-                        if let Some(result) = json_value.get("result") {
-                            ic_cdk::println!("Result: {:?}", result);
-                            if let Some(events) = result.get("events") {
-                                ic_cdk::println!("Events: {:?}", events);
-                                if let Some(events_array) = events.as_array() {
-                                    if !events_array.is_empty() {
-                                        ic_cdk::println!("=== EVENTS CAUGHT! Processing {} events ===", events_array.len());
-                                        result_summary.push_str(&format!("\n=== Events for Ledger {} ===\n", current_ledger));
-                                        
-                                        let mut events_processed = false;
-                                        
-                                        for event in events_array {
-                                            ic_cdk::println!("\n=== EVENT DETAILS ===");
-                                            ic_cdk::println!("Transaction Hash: {}", event.get("txHash").and_then(|v| v.as_str()).unwrap_or("N/A"));
-                                            ic_cdk::println!("Event ID: {}", event.get("id").and_then(|v| v.as_str()).unwrap_or("N/A"));
-                                            
-                                            if let Some(value_json) = event.get("valueJson") {
-                                                if let Some(map) = value_json.get("map").and_then(|m| m.as_array()) {
-                                                    let mut dest_address = String::new();
-                                                    let mut amount_to_send: f64 = 0.0;
-                                                    let mut dest_chain: u64 = 0;
-
-                                                    for item in map {
-                                                        if let Some(key) = item.get("key").and_then(|k| k.get("symbol")).and_then(|s| s.as_str()) {
-                                                            match key {
-                                                                "dest_chain" => {
-                                                                    if let Some(bytes) = item.get("val").and_then(|v| v.get("bytes")).and_then(|b| b.as_str()) {
-                                                                        if let Ok(decimal) = u64::from_str_radix(bytes, 16) {
-                                                                            ic_cdk::println!("Destination Chain: {}", decimal);
-                                                                            dest_chain = decimal;
-                                                                        }
-                                                                    }
-                                                                },
-                                                                "dest_token" => {
-                                                                    if let Some(token) = item.get("val").and_then(|v| v.get("string")).and_then(|s| s.as_str()) {
-                                                                        ic_cdk::println!("Destination Token: {}", token);
-                                                                    }
-                                                                },
-                                                                "from_token" => {
-                                                                    if let Some(addr) = item.get("val").and_then(|v| v.get("address")).and_then(|s| s.as_str()) {
-                                                                        ic_cdk::println!("From Token: {}", addr);
-                                                                    }
-                                                                },
-                                                                "in_amount" => {
-                                                                    // Try multiple possible i128 representations
-                                                                    let mut amount_found = false;
-                                                                    
-                                                                    // First try: i128.lo (your original code)
-                                                                    if let Some(amount) = item.get("val").and_then(|v| v.get("i128")).and_then(|i| i.get("lo")) {
-                                                                        let amount_val = amount.as_u64().unwrap_or(0);
-                                                                        ic_cdk::println!("Input Amount (i128.lo): {} XLM (raw: {})", amount_val as f64 / 10_000_000.0, amount_val);
-                                                                        amount_to_send = amount_val as f64;
-                                                                        amount_found = true;
-                                                                    }
-                                                                    
-                                                                    // Second try: direct i128 as string/number
-                                                                    if !amount_found {
-                                                                        if let Some(i128_val) = item.get("val").and_then(|v| v.get("i128")) {
-                                                                            // Try as direct number
-                                                                            if let Some(amount_num) = i128_val.as_u64() {
-                                                                                ic_cdk::println!("Input Amount (i128 direct): {} XLM (raw: {})", amount_num as f64 / 10_000_000.0, amount_num);
-                                                                                amount_to_send = amount_num as f64;
-                                                                                amount_found = true;
-                                                                            }
-                                                                            // Try as string
-                                                                            else if let Some(amount_str) = i128_val.as_str() {
-                                                                                if let Ok(amount_parsed) = amount_str.parse::<u64>() {
-                                                                                    ic_cdk::println!("Input Amount (i128 string): {} XLM (raw: {})", amount_parsed as f64 / 10_000_000.0, amount_parsed);
-                                                                                    amount_to_send = amount_parsed as f64;
-                                                                                    amount_found = true;
-                                                                                }
-                                                                            }
-                                                                        }
-                                                                    }
-                                                                    
-                                                                    // Debug: print the full structure if not found
-                                                                    if !amount_found {
-                                                                        ic_cdk::println!("Could not parse in_amount. Full val structure: {:?}", item.get("val"));
-                                                                    }
-                                                                },
-                                                                "recipient_address" => {
-                                                                    if let Some(addr) = item.get("val").and_then(|v| v.get("string")).and_then(|s| s.as_str()) {
-                                                                        ic_cdk::println!("Destination Address: {}", addr);
-                                                                        dest_address = addr.to_string();
-                                                                    }
-                                                                },
-                                                                _ => {
-                                                                    // Debug: print unknown keys to help understand the structure
-                                                                    ic_cdk::println!("Unknown event field: {}", key);
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-
-                                                    // Only send ETH if we caught a valid event with all required data
-                                                    if !dest_address.is_empty() && amount_to_send > 0.0 && dest_chain > 0 {
-                                                        ic_cdk::println!("=== EVENT CAUGHT - CALLING send_eth_evm ===");
-                                                        ic_cdk::println!("Sending ETH to: {}", dest_address);
-                                                        ic_cdk::println!("Amount: {}", amount_to_send);
-                                                        ic_cdk::println!("Chain: {}", dest_chain);
-                                                        
-                                                        match send_eth_evm(dest_address, 100 as f64, dest_chain.to_string()).await {
-                                                            Ok(txn_hash) => {
-                                                                ic_cdk::println!("ETH sent successfully! Transaction Hash: {}", txn_hash);
-                                                                events_processed = true;
-                                                            },
-                                                            Err(e) => {
-                                                                ic_cdk::println!("Error sending ETH: {}", e);
-                                                            }
-                                                        }
-                                                    } else {
-                                                        ic_cdk::println!("Event caught but missing required data - skipping send_eth_evm");
-                                                        if dest_address.is_empty() { ic_cdk::println!("Missing: dest_address"); }
-                                                        if amount_to_send <= 0.0 { ic_cdk::println!("Missing: amount_to_send"); }
-                                                        if dest_chain == 0 { ic_cdk::println!("Missing: dest_chain"); }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        
-                                        // If events were found and processed, exit the loop
-                                        if events_processed {
-                                            ic_cdk::println!("Events processed successfully. Stopping monitoring.");
-                                            return Ok(result_summary);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            },
-            Err((code, msg)) => {
-                ic_cdk::println!("HTTP request failed: code = {:?}, message = {}", code, msg);
-            }
-        }
+fn stop_stellar_indexer() -> Result<(), String> {
+    require_controller("run the Stellar indexer")?;
 
-        // Increment current ledger
-        current_ledger += 1;
+    update_state(|state| state.enabled = false);
+    if let Some(timer_id) = INDEX_TIMER.with(|timer| timer.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer_id);
     }
+    Ok(())
 }
 
-// #[ic_cdk::update]
-// async fn fetch_stellar_events(ledger: u32) -> Result<String, String> {
-//     ic_cdk::println!("Starting stellar events monitoring");
-    
-//     let contract_id = CONTRACT_ID.to_string();
-//     ic_cdk::println!("Contract ID: {}", contract_id);
-
-//     let mut current_ledger = ledger; // Starting ledger
-//     let mut result_summary = String::new();
-    
-//     loop {
-//         // Get the latest ledger in each iteration
-//         let latest_ledger = match fetch_latest_ledger().await {
-//             Ok(ledger) => ledger,
-//             Err(e) => {
-//                 ic_cdk::println!("Error fetching latest ledger: {}", e);
-                
-//                 continue;
-//             }
-//         };
-        
-     
-//     //    if current_ledger <= latest_ledger {
-        
-//         let request = GetEventsRequest {
-//             jsonrpc: "2.0".to_string(),
-//             id: 8675309,
-//             method: "getEvents".to_string(),
-//             params: GetEventsParams {
-//                 start_ledger: current_ledger,
-//                 xdr_format: "json".to_string(),
-//                 filters: vec![EventFilter {
-//                     filter_type: "contract".to_string(),
-//                     contract_ids: vec![contract_id.clone()],
-//                     topics: vec![],
-//                 }],
-//                 pagination: PaginationOptions {
-//                     limit: 1000,
-//                 },
-//             },
-//         };
-        
-//         let request_body = serde_json::to_string(&request)
-//             .map_err(|e| format!("Failed to serialize request: {}", e))?;
-//         ic_cdk::println!("Request body: {}", request_body);
-        
-//         let request_headers = vec![
-//             HttpHeader {
-//                 name: "Content-Type".to_string(),
-//                 value: "application/json".to_string(),
-//             },
-//         ];
-        
-//         let request_arg = CanisterHttpRequestArgument {
-//             url: RPC_URL.to_string(),
-//             method: HttpMethod::POST,
-//             body: Some(request_body.into_bytes()),
-//             max_response_bytes: Some(2_000_000),
-//             transform: None,
-//             headers: request_headers,
-//         };
-        
-//         match ic_cdk::api::management_canister::http_request::http_request(
-//             request_arg,
-//             50_000_000_000,
-//         )
-//         .await {
-//             Ok((response,)) => {
-
-//                 if let Ok(response_body) = String::from_utf8(response.body.clone()) {
-//                     if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(&response_body) {
-//                         if let Some(result) = json_value.get("result") {
-//                             ic_cdk::println!("Result: {:?}", result);
-//                             if let Some(events) = result.get("events") {
-//                                 ic_cdk::println!("Events: {:?}", events);
-//                                 if let Some(events_array) = events.as_array() {
-//                                     if !events_array.is_empty() {
-//                                         result_summary.push_str(&format!("\n=== Events for Ledger {} ===\n", current_ledger));
-                                        
-//                                         for event in events_array {
-//                                             ic_cdk::println!("\n=== EVENT DETAILS ===");
-//                                             ic_cdk::println!("Transaction Hash: {}", event.get("txHash").and_then(|v| v.as_str()).unwrap_or("N/A"));
-//                                             ic_cdk::println!("Event ID: {}", event.get("id").and_then(|v| v.as_str()).unwrap_or("N/A"));
-                                            
-//                                             if let Some(value_json) = event.get("valueJson") {
-//                                                 if let Some(map) = value_json.get("map").and_then(|m| m.as_array()) {
-//                                                     let mut dest_address = String::new();
-//                                                     let mut amount_to_send: f64 = 0.0;
-//                                                     let mut dest_chain: u64 = 0;
-
-//                                                     for item in map {
-//                                                         if let Some(key) = item.get("key").and_then(|k| k.get("symbol")).and_then(|s| s.as_str()) {
-//                                                             match key {
-//                                                                 "dest_chain" => {
-//                                                                     if let Some(bytes) = item.get("val").and_then(|v| v.get("bytes")).and_then(|b| b.as_str()) {
-//                                                                         if let Ok(decimal) = u64::from_str_radix(bytes, 16) {
-//                                                                             ic_cdk::println!("Destination Chain: {}", decimal);
-//                                                                             dest_chain = decimal;
-//                                                                         }
-//                                                                     }
-//                                                                 },
-//                                                                 "dest_token" => {
-//                                                                     if let Some(token) = item.get("val").and_then(|v| v.get("string")).and_then(|s| s.as_str()) {
-//                                                                         ic_cdk::println!("Destination Token: {}", token);
-//                                                                     }
-//                                                                 },
-//                                                                 "from_token" => {
-//                                                                     if let Some(addr) = item.get("val").and_then(|v| v.get("address")).and_then(|s| s.as_str()) {
-//                                                                         ic_cdk::println!("From Token: {}", addr);
-//                                                                     }
-//                                                                 },
-//                                                                 "in_amount" => {
-//                                                                     if let Some(amount) = item.get("val").and_then(|v| v.get("i128")).and_then(|i| i.get("lo")) {
-//                                                                         let amount_val = amount.as_u64().unwrap_or(0);
-//                                                                         ic_cdk::println!("Input Amount: {} XLM (raw: {})", amount_val as f64 / 10_000_000.0, amount_val);
-//                                                                         amount_to_send = amount_val as f64;
-//                                                                     }
-//                                                                 },
-//                                                                 "recipient_address" => {
-//                                                                     if let Some(addr) = item.get("val").and_then(|v| v.get("string")).and_then(|s| s.as_str()) {
-//                                                                         ic_cdk::println!("Destination Address: {}", addr);
-//                                                                         dest_address = addr.to_string();
-//                                                                     }
-//                                                                 },
-//                                                                 _ => {}
-//                                                             }
-//                                                         }
-//                                                     }
-
-//                                                     // Send ETH if we have all required values
-//                                                     if !dest_address.is_empty() && amount_to_send > 0.0 && dest_chain > 0 {
-//                                                         ic_cdk::println!("Sending ETH to: {}", dest_address);
-//                                                         ic_cdk::println!("Amount: {}", amount_to_send);
-//                                                         ic_cdk::println!("Chain: {}", dest_chain);
-//                                                         if let Err(e) = send_eth_evm(dest_address, 0.01 as f64, dest_chain.to_string()).await {
-//                                                             ic_cdk::println!("Error sending ETH: {}", e);
-//                                                         }
-//                                                     }
-//                                                 }
-//                                             }
-//                                         }
-//                                     }
-//                                 }
-//                             }
-//                         }
-//                     }
-//                 }
-//             },
-//             Err((code, msg)) => {
-//                 ic_cdk::println!("HTTP request failed: code = {:?}, message = {}", code, msg);
-//             }
-//         }
-
-//         // Increment current ledger
-//         current_ledger += 1;
-        
-//     // }
-
-//     }
-
-
-
-// }
-
-// Query function to get stored events
 #[ic_cdk::query]
-fn get_events() -> Vec<CandidContractEvent> {
-    EVENTS.with(|events| {
-        events.borrow()
-            .values()
-            .cloned()
-            .collect()
-    })
+fn get_stellar_indexer_status() -> IndexerStatus {
+    let state = snapshot();
+    let lag_ledgers = state
+        .latest_ledger
+        .map(|latest| latest.saturating_sub(state.last_processed_ledger.unwrap_or(state.next_ledger.saturating_sub(1))));
+    let seconds_since_last_success = state
+        .last_success_at
+        .map(|at| ic_cdk::api::time().saturating_sub(at) / 1_000_000_000);
+
+    IndexerStatus {
+        state,
+        subscriptions: subscriptions::snapshot().len() as u32,
+        network: INDEXER_NETWORK.to_string(),
+        timer_running: INDEX_TIMER.with(|timer| timer.borrow().is_some()),
+        lag_ledgers,
+        seconds_since_last_success,
+    }
 }
 
-// Query function to get a specific event by ID
-#[ic_cdk::query]
-fn get_event_by_id(id: String) -> Option<CandidContractEvent> {
-    EVENTS.with(|events| {
-        events.borrow()
-            .get(&id)
-            .cloned()
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Synthetic getEvents response in the shape soroban-testnet.stellar.org returns, with a
+    // made-up lock (amount and recipient) on the testnet bridge contract
+    const RPC_EVENTS: &str = include_str!("../testdata/rpc_events.json");
+
+    fn recorded_event() -> CandidContractEvent {
+        let response: RpcResponse = serde_json::from_str(RPC_EVENTS).unwrap();
+        let event = response.result.events.into_iter().next().unwrap();
+        CandidContractEvent::try_from(event).unwrap().with_bridge_lock().unwrap()
+    }
+
+    #[test]
+    fn lock_event_decodes_from_xdr() {
+        let event = recorded_event();
+
+        assert_eq!(event.topic_values, vec![ContractValue::Symbol("lock".to_string())]);
+        assert_eq!(event.tx_hash, "6cbb7f714bd08cea7c30cab7818a35c510cbbfc0a6aa06172a1e94146ecf0165");
+        assert_eq!(
+            event.bridge_lock,
+            Some(BridgeLockEvent {
+                from_token: "CDLZFC3SYJYDZT7K67VZ75HPJVIEUVNIXF47ZG2FB2RMQQVU2HHGCYSC".to_string(),
+                dest_token: "ETH".to_string(),
+                // Larger than u64::MAX, so only the full i128 is right
+                in_amount: 25_000_000_000_000_000_000,
+                dest_chain: 11_155_111,
+                recipient_address: "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn event_id_is_in_its_ledger() {
+        let event = recorded_event();
+        let toid: u64 = event.id.split('-').next().unwrap().parse().unwrap();
+        assert_eq!(toid >> 32, event.ledger as u64);
+    }
+
+    #[test]
+    fn lock_amounts_scale_to_the_released_decimals() {
+        // 2.5 XLM-like units (7 decimals) to wei
+        assert_eq!(scale_amount(25_000_000, 7, 18), Ok(2_500_000_000_000_000_000));
+        assert_eq!(scale_amount(25_000_000_000_000_000_000, 18, 18), Ok(25_000_000_000_000_000_000));
+        // Rounds down past the released precision
+        assert_eq!(scale_amount(1_999, 21, 18), Ok(1));
+        assert!(scale_amount(999, 21, 18).is_err());
+        assert!(scale_amount(0, 7, 18).is_err());
+        assert!(scale_amount(-1, 7, 18).is_err());
+        assert!(scale_amount(i128::MAX, 0, 18).is_err());
+    }
+
+    #[test]
+    fn xdr_value_is_the_raw_event_value() {
+        let event = recorded_event();
+        let value: ScVal = soroban::from_xdr(&event.xdr_value).unwrap();
+        assert_eq!(ContractValue::from_sc_val(&value), event.value);
+    }
+
+    #[test]
+    fn lock_is_only_decoded_for_bridge_subscriptions() {
+        let response: RpcResponse = serde_json::from_str(RPC_EVENTS).unwrap();
+        let event = response.result.events.into_iter().next().unwrap();
+        assert_eq!(event.event_type, "contract");
+        assert_eq!(CandidContractEvent::try_from(event).unwrap().bridge_lock, None);
+    }
+
+    #[test]
+    fn lock_event_without_amount_is_rejected() {
+        let value = ContractValue::Map(vec![(
+            ContractValue::Symbol("dest_token".to_string()),
+            ContractValue::String("ETH".to_string()),
+        )]);
+        assert!(BridgeLockEvent::from_value(&value).is_err());
+    }
 }
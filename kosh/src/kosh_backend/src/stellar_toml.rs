@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::outcall;
+use crate::transform::Transform;
+
+// Fields we read from a domain's stellar.toml (SEP-1)
+pub const WEB_AUTH_ENDPOINT: &str = "WEB_AUTH_ENDPOINT";
+pub const SIGNING_KEY: &str = "SIGNING_KEY";
+pub const NETWORK_PASSPHRASE: &str = "NETWORK_PASSPHRASE";
+pub const TRANSFER_SERVER: &str = "TRANSFER_SERVER";
+pub const TRANSFER_SERVER_SEP0024: &str = "TRANSFER_SERVER_SEP0024";
+
+#[derive(Debug, Clone, Default)]
+pub struct StellarToml {
+    fields: HashMap<String, String>,
+    currencies: Vec<HashMap<String, String>>,
+}
+
+impl StellarToml {
+    // Keeps the top-level `KEY = value` pairs and each [[CURRENCIES]] entry; other
+    // tables such as [DOCUMENTATION] are skipped.
+    pub fn parse(body: &str) -> Self {
+        let mut fields = HashMap::new();
+        let mut currencies: Vec<HashMap<String, String>> = Vec::new();
+        let mut in_currency = false;
+        let mut in_table = false;
+
+        for line in body.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                in_table = true;
+                in_currency = line.replace(' ', "") == "[[CURRENCIES]]";
+                if in_currency {
+                    currencies.push(HashMap::new());
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let key = key.trim().trim_matches('"').to_string();
+            let value = value.trim();
+            let value = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => value[1..]
+                    .split(quote)
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                _ => value.split('#').next().unwrap_or_default().trim().to_string(),
+            };
+
+            if !in_table {
+                fields.insert(key, value);
+            } else if in_currency {
+                if let Some(currency) = currencies.last_mut() {
+                    currency.insert(key, value);
+                }
+            }
+        }
+
+        StellarToml { fields, currencies }
+    }
+
+    // Issuer of a classic asset listed under [[CURRENCIES]]
+    pub fn currency_issuer(&self, asset_code: &str) -> Option<&str> {
+        self.currencies
+            .iter()
+            .find(|currency| currency.get("code").map(|c| c.as_str()) == Some(asset_code))
+            .and_then(|currency| currency.get("issuer"))
+            .map(|issuer| issuer.as_str())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(|v| v.as_str()).filter(|v| !v.is_empty())
+    }
+
+    pub fn require(&self, key: &str) -> Result<&str, String> {
+        self.get(key)
+            .ok_or_else(|| format!("stellar.toml does not define {}", key))
+    }
+}
+
+pub fn validate_domain(domain: &str) -> Result<(), String> {
+    let valid = !domain.is_empty()
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == ':');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid home domain: {}", domain))
+    }
+}
+
+pub async fn fetch_stellar_toml(domain: &str) -> Result<StellarToml, String> {
+    validate_domain(domain)?;
+
+    let url = format!("https://{}/.well-known/stellar.toml", domain);
+    ic_cdk::println!("Fetching stellar.toml from {}", url);
+
+    let response = outcall::get(&url, vec![], 100_000, Transform::Headers).await?;
+    if !response.is_success() {
+        return Err(format!(
+            "Failed to fetch stellar.toml for {}: HTTP {}",
+            domain, response.status
+        ));
+    }
+
+    Ok(StellarToml::parse(&response.body))
+}
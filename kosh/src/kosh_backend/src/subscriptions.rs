@@ -0,0 +1,290 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use stellar_xdr::curr::ScAddress;
+
+use crate::soroban::{self, ContractValue};
+use crate::require_controller;
+use crate::stellar_indexer::CONTRACT_ID;
+
+// getEvents accepts at most 5 filters, each with at most 5 contract IDs and 5 topic filters
+const MAX_FILTERS: usize = 5;
+const MAX_CONTRACTS_PER_FILTER: usize = 5;
+const MAX_TOPIC_FILTERS: usize = 5;
+// Soroban events have at most 4 topics
+const MAX_TOPIC_SEGMENTS: usize = 4;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventType {
+    Contract,
+    System,
+    Diagnostic,
+}
+
+impl EventType {
+    fn rpc_name(&self) -> &'static str {
+        match self {
+            EventType::Contract => "contract",
+            EventType::System => "system",
+            EventType::Diagnostic => "diagnostic",
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum EventHandler {
+    StoreOnly,
+    // Store, and release on the EVM side for `lock` events
+    BridgeRelease,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum TopicSegment {
+    Value(ContractValue),
+    // Any single topic
+    Any,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SubscriptionRequest {
+    pub contract_id: String,
+    // An event matches when it matches any one of these; each filter must have as many
+    // segments as the event has topics. No filters matches every event.
+    pub topics: Vec<Vec<TopicSegment>>,
+    pub event_type: EventType,
+    pub handler: EventHandler,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Subscription {
+    pub id: u64,
+    pub contract_id: String,
+    pub topics: Vec<Vec<TopicSegment>>,
+    pub event_type: EventType,
+    pub handler: EventHandler,
+}
+
+// One getEvents filter
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct EventFilter {
+    #[serde(rename = "type")]
+    pub filter_type: String,
+    #[serde(rename = "contractIds")]
+    pub contract_ids: Vec<String>,
+    // Base64 ScVal XDR per segment, or "*"
+    pub topics: Vec<Vec<String>>,
+}
+
+thread_local! {
+    // The bridge contract until subscriptions are changed
+    static SUBSCRIPTIONS: RefCell<Vec<Subscription>> = RefCell::new(vec![Subscription {
+        id: 1,
+        contract_id: CONTRACT_ID.to_string(),
+        topics: vec![],
+        event_type: EventType::Contract,
+        handler: EventHandler::BridgeRelease,
+    }]);
+}
+
+pub fn snapshot() -> Vec<Subscription> {
+    SUBSCRIPTIONS.with(|subscriptions| subscriptions.borrow().clone())
+}
+
+pub fn restore(saved: Vec<Subscription>) {
+    SUBSCRIPTIONS.with(|subscriptions| *subscriptions.borrow_mut() = saved);
+}
+
+fn topic_xdr(segment: &TopicSegment) -> Result<String, String> {
+    match segment {
+        TopicSegment::Value(value) => soroban::to_xdr(&value.to_sc_val()?),
+        TopicSegment::Any => Ok("*".to_string()),
+    }
+}
+
+// Subscriptions with the same event type and topics share a filter
+pub fn event_filters(subscriptions: &[Subscription]) -> Result<Vec<EventFilter>, String> {
+    let mut filters: Vec<EventFilter> = Vec::new();
+
+    for subscription in subscriptions {
+        let topics = subscription
+            .topics
+            .iter()
+            .map(|filter| filter.iter().map(topic_xdr).collect::<Result<Vec<_>, _>>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let filter_type = subscription.event_type.rpc_name().to_string();
+
+        let shared = filters.iter_mut().find(|filter| {
+            filter.filter_type == filter_type
+                && filter.topics == topics
+                && filter.contract_ids.len() < MAX_CONTRACTS_PER_FILTER
+        });
+        match shared {
+            Some(filter) if filter.contract_ids.contains(&subscription.contract_id) => {}
+            Some(filter) => filter.contract_ids.push(subscription.contract_id.clone()),
+            None => filters.push(EventFilter {
+                filter_type,
+                contract_ids: vec![subscription.contract_id.clone()],
+                topics,
+            }),
+        }
+    }
+
+    if filters.len() > MAX_FILTERS {
+        return Err(format!(
+            "Subscriptions need {} getEvents filters, more than the {} one request allows",
+            filters.len(),
+            MAX_FILTERS
+        ));
+    }
+    Ok(filters)
+}
+
+fn topics_match(filter: &[TopicSegment], topics: &[ContractValue]) -> bool {
+    filter.len() == topics.len()
+        && filter.iter().zip(topics).all(|(segment, topic)| match segment {
+            TopicSegment::Any => true,
+            TopicSegment::Value(value) => value == topic,
+        })
+}
+
+// Handler for an event: BridgeRelease when any matching subscription asks for it,
+// None when no subscription matches
+pub fn handler_for(contract_id: &str, event_type: &str, topics: &[ContractValue]) -> Option<EventHandler> {
+    SUBSCRIPTIONS.with(|subscriptions| {
+        subscriptions
+            .borrow()
+            .iter()
+            .filter(|subscription| {
+                subscription.contract_id == contract_id
+                    && subscription.event_type.rpc_name() == event_type
+                    && (subscription.topics.is_empty()
+                        || subscription.topics.iter().any(|filter| topics_match(filter, topics)))
+            })
+            .map(|subscription| subscription.handler)
+            .reduce(|a, b| if b == EventHandler::BridgeRelease { b } else { a })
+    })
+}
+
+fn validate(request: &SubscriptionRequest) -> Result<(), String> {
+    match soroban::parse_address(&request.contract_id)? {
+        ScAddress::Contract(_) => {}
+        _ => return Err(format!("{} is not a contract address", request.contract_id)),
+    }
+    if request.topics.len() > MAX_TOPIC_FILTERS {
+        return Err(format!("At most {} topic filters per subscription", MAX_TOPIC_FILTERS));
+    }
+    if request.topics.iter().any(|filter| filter.is_empty() || filter.len() > MAX_TOPIC_SEGMENTS) {
+        return Err(format!("Topic filters need 1 to {} segments", MAX_TOPIC_SEGMENTS));
+    }
+    if request.handler == EventHandler::BridgeRelease
+        && (request.event_type != EventType::Contract || request.contract_id != CONTRACT_ID)
+    {
+        return Err(format!("Only contract events of the bridge contract {} can release bridge transfers", CONTRACT_ID));
+    }
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn add_event_subscription(request: SubscriptionRequest) -> Result<Subscription, String> {
+    require_controller("manage event subscriptions")?;
+    validate(&request)?;
+
+    let mut subscriptions = snapshot();
+    let subscription = Subscription {
+        id: subscriptions.iter().map(|subscription| subscription.id).max().unwrap_or(0) + 1,
+        contract_id: request.contract_id,
+        topics: request.topics,
+        event_type: request.event_type,
+        handler: request.handler,
+    };
+    subscriptions.push(subscription.clone());
+    event_filters(&subscriptions)?;
+
+    restore(subscriptions);
+    Ok(subscription)
+}
+
+#[ic_cdk::update]
+fn remove_event_subscription(id: u64) -> Result<(), String> {
+    require_controller("manage event subscriptions")?;
+
+    SUBSCRIPTIONS.with(|subscriptions| {
+        let mut subscriptions = subscriptions.borrow_mut();
+        let before = subscriptions.len();
+        subscriptions.retain(|subscription| subscription.id != id);
+        if subscriptions.len() == before {
+            return Err(format!("No subscription {}", id));
+        }
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn list_event_subscriptions() -> Vec<Subscription> {
+    snapshot()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OTHER: &str = "CDLZFC3SYJYDZT7K67VZ75HPJVIEUVNIXF47ZG2FB2RMQQVU2HHGCYSC";
+
+    fn subscription(id: u64, contract_id: &str, topics: Vec<Vec<TopicSegment>>, event_type: EventType) -> Subscription {
+        Subscription { id, contract_id: contract_id.to_string(), topics, event_type, handler: EventHandler::StoreOnly }
+    }
+
+    fn symbol(name: &str) -> TopicSegment {
+        TopicSegment::Value(ContractValue::Symbol(name.to_string()))
+    }
+
+    #[test]
+    fn same_topics_share_a_filter() {
+        let filters = event_filters(&[
+            subscription(1, CONTRACT_ID, vec![], EventType::Contract),
+            subscription(2, OTHER, vec![], EventType::Contract),
+            subscription(3, OTHER, vec![vec![symbol("transfer"), TopicSegment::Any]], EventType::Contract),
+            subscription(4, OTHER, vec![], EventType::Diagnostic),
+        ])
+        .unwrap();
+
+        assert_eq!(filters.len(), 3);
+        assert_eq!(filters[0].contract_ids, vec![CONTRACT_ID.to_string(), OTHER.to_string()]);
+        // Symbol "transfer" as ScVal XDR
+        assert_eq!(filters[1].topics, vec![vec!["AAAADwAAAAh0cmFuc2Zlcg==".to_string(), "*".to_string()]]);
+        assert_eq!(filters[2].filter_type, "diagnostic");
+    }
+
+    #[test]
+    fn too_many_filters_are_refused() {
+        let subscriptions: Vec<Subscription> = (0..6)
+            .map(|i| subscription(i, OTHER, vec![vec![TopicSegment::Value(ContractValue::U32(i as u32))]], EventType::Contract))
+            .collect();
+        assert!(event_filters(&subscriptions[..5]).is_ok());
+        assert!(event_filters(&subscriptions).is_err());
+    }
+
+    #[test]
+    fn only_the_bridge_contract_releases() {
+        let request = |contract_id: &str, event_type| SubscriptionRequest {
+            contract_id: contract_id.to_string(),
+            topics: vec![],
+            event_type,
+            handler: EventHandler::BridgeRelease,
+        };
+        assert!(validate(&request(CONTRACT_ID, EventType::Contract)).is_ok());
+        assert!(validate(&request(OTHER, EventType::Contract)).is_err());
+        assert!(validate(&request(CONTRACT_ID, EventType::Diagnostic)).is_err());
+    }
+
+    #[test]
+    fn topic_filters_match_by_length_and_value() {
+        let filter = vec![symbol("transfer"), TopicSegment::Any];
+        let transfer = ContractValue::Symbol("transfer".to_string());
+        let from = ContractValue::Address(OTHER.to_string());
+
+        assert!(topics_match(&filter, &[transfer.clone(), from.clone()]));
+        assert!(!topics_match(&filter, &[transfer]));
+        assert!(!topics_match(&filter, &[ContractValue::Symbol("mint".to_string()), from]));
+    }
+}
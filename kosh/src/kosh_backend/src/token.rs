@@ -0,0 +1,323 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::network;
+use crate::public_key_stellar;
+use crate::rate_limit::{
+    guard_add_token_contract, guard_approve_token, guard_get_token_balance, guard_get_token_info,
+    guard_transfer_token,
+};
+use crate::soroban::{self, ContractCall, ContractValue};
+use crate::tx_ledger::{TxKind, TxRequest};
+use crate::tx_result::TxError;
+
+// About 30 days of 5 second ledgers, for approvals without an explicit expiration
+const DEFAULT_APPROVAL_LEDGERS: u32 = 30 * 17_280;
+// i128 holds 38 full decimal digits; a token claiming more can't be shown or parsed
+const MAX_DECIMALS: u32 = 38;
+
+// Metadata of a SEP-41 token contract
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct TokenInfo {
+    pub contract_id: String,
+    pub network: String,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u32,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TokenBalance {
+    pub contract_id: String,
+    pub address: String,
+    pub symbol: String,
+    pub decimals: u32,
+    // Decimal amount, e.g. "12.5" for 125000000 with 7 decimals
+    pub balance: String,
+}
+
+thread_local! {
+    // Token contracts each principal follows, shown by get_account_assets
+    static TRACKED: RefCell<BTreeMap<Principal, Vec<TokenInfo>>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub fn snapshot() -> Vec<(Principal, Vec<TokenInfo>)> {
+    TRACKED.with(|tracked| tracked.borrow().iter().map(|(p, t)| (*p, t.clone())).collect())
+}
+
+pub fn restore(entries: Vec<(Principal, Vec<TokenInfo>)>) {
+    TRACKED.with(|tracked| *tracked.borrow_mut() = entries.into_iter().collect());
+}
+
+fn tracked_tokens(network: &str) -> Vec<TokenInfo> {
+    TRACKED.with(|tracked| {
+        tracked
+            .borrow()
+            .get(&ic_cdk::caller())
+            .map(|tokens| tokens.iter().filter(|token| token.network == network).cloned().collect())
+            .unwrap_or_default()
+    })
+}
+
+// Raw i128 amount to a decimal string with the token's decimals
+pub fn format_token_amount(amount: i128, decimals: u32) -> Result<String, String> {
+    if decimals > MAX_DECIMALS {
+        return Err(format!("Tokens with {} decimals are not supported", decimals));
+    }
+    if decimals == 0 {
+        return Ok(amount.to_string());
+    }
+    let digits = format!("{:0>width$}", amount.unsigned_abs(), width = decimals as usize + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals as usize);
+    Ok(format!("{}{}.{}", if amount < 0 { "-" } else { "" }, whole, fraction))
+}
+
+// Decimal amount string to the raw i128 amount, rejecting more places than the token has
+pub fn parse_token_amount(amount: &str, decimals: u32) -> Result<i128, String> {
+    if decimals > MAX_DECIMALS {
+        return Err(format!("Tokens with {} decimals are not supported", decimals));
+    }
+    let amount = amount.trim();
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+
+    if whole.is_empty() && fraction.is_empty()
+        || !whole.chars().all(|c| c.is_ascii_digit())
+        || !fraction.chars().all(|c| c.is_ascii_digit())
+        || fraction.len() > decimals as usize
+    {
+        return Err(format!("Invalid amount: {}", amount));
+    }
+
+    let digits = format!("{}{:0<width$}", whole, fraction, width = decimals as usize);
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    digits.parse().map_err(|_| format!("Amount too large: {}", amount))
+}
+
+async fn read_string(network: &str, contract_id: &str, function: &str) -> Result<String, String> {
+    match soroban::read_contract(network, contract_id, function, &[]).await? {
+        ContractValue::String(value) | ContractValue::Symbol(value) => Ok(value),
+        other => Err(format!("{} of {} is not a string: {:?}", function, contract_id, other)),
+    }
+}
+
+pub async fn fetch_token_info(contract_id: &str, network: &str) -> Result<TokenInfo, String> {
+    let decimals = match soroban::read_contract(network, contract_id, "decimals", &[]).await? {
+        ContractValue::U32(decimals) => decimals,
+        other => return Err(format!("decimals of {} is not a u32: {:?}", contract_id, other)),
+    };
+    if decimals > MAX_DECIMALS {
+        return Err(format!("{} has {} decimals, more than the {} supported", contract_id, decimals, MAX_DECIMALS));
+    }
+
+    Ok(TokenInfo {
+        contract_id: contract_id.to_string(),
+        network: network.to_string(),
+        name: read_string(network, contract_id, "name").await?,
+        symbol: read_string(network, contract_id, "symbol").await?,
+        decimals,
+    })
+}
+
+async fn fetch_balance(token: &TokenInfo, address: &str) -> Result<TokenBalance, String> {
+    let args = [ContractValue::Address(address.to_string())];
+    let amount = match soroban::read_contract(&token.network, &token.contract_id, "balance", &args).await? {
+        ContractValue::I128(amount) => amount,
+        other => return Err(format!("balance of {} is not an i128: {:?}", token.contract_id, other)),
+    };
+
+    Ok(TokenBalance {
+        contract_id: token.contract_id.clone(),
+        address: address.to_string(),
+        symbol: token.symbol.clone(),
+        decimals: token.decimals,
+        balance: format_token_amount(amount, token.decimals)?,
+    })
+}
+
+// Tracked metadata when the caller follows the token, saving three simulations
+async fn token_info(contract_id: &str, network: &str) -> Result<TokenInfo, String> {
+    match tracked_tokens(network).into_iter().find(|token| token.contract_id == contract_id) {
+        Some(token) => Ok(token),
+        None => fetch_token_info(contract_id, network).await,
+    }
+}
+
+// Balances of every token contract the caller follows on `network`; tokens that cannot
+// be read are left out
+pub async fn tracked_balances(address: &str, network: &str) -> Vec<TokenBalance> {
+    let mut balances = Vec::new();
+    for token in tracked_tokens(network) {
+        match fetch_balance(&token, address).await {
+            Ok(balance) => balances.push(balance),
+            Err(e) => ic_cdk::println!("Could not read {} balance: {}", token.contract_id, e),
+        }
+    }
+    balances
+}
+
+#[ic_cdk::update(guard = "guard_get_token_info")]
+async fn get_token_info(contract_id: String, network: Option<String>) -> Result<TokenInfo, String> {
+    let network = network::resolve(network)?;
+    fetch_token_info(&contract_id, &network).await
+}
+
+// Balance of `address`, or of the caller's account when omitted
+#[ic_cdk::update(guard = "guard_get_token_balance")]
+async fn get_token_balance(
+    contract_id: String,
+    address: Option<String>,
+    network: Option<String>,
+) -> Result<TokenBalance, String> {
+    let network = network::resolve(network)?;
+    let address = match address {
+        Some(address) => address,
+        None => public_key_stellar().await?,
+    };
+
+    let token = token_info(&contract_id, &network).await?;
+    fetch_balance(&token, &address).await
+}
+
+#[ic_cdk::update(guard = "guard_transfer_token")]
+async fn transfer_token(
+    contract_id: String,
+    destination_address: String,
+    amount: String,
+    network: Option<String>,
+) -> Result<ContractCall, TxError> {
+    let network = network::resolve(network)?;
+    let token = token_info(&contract_id, &network).await?;
+    let raw_amount = parse_token_amount(&amount, token.decimals)?;
+    let source_address = public_key_stellar().await?;
+
+    let args = [
+        ContractValue::Address(source_address),
+        ContractValue::Address(destination_address.clone()),
+        ContractValue::I128(raw_amount),
+    ];
+    let request = TxRequest::new(TxKind::Payment, serde_json::json!({
+        "contract_id": contract_id,
+        "destination_address": destination_address,
+        "amount": amount,
+        "network": network,
+    }));
+    let (transaction, receipt) = soroban::call_contract(&contract_id, "transfer", &args, &network, request).await?;
+
+    Ok(ContractCall {
+        transaction,
+        return_value: receipt.map(|receipt| ContractValue::from_sc_val(&receipt.return_value)),
+    })
+}
+
+// Let `spender` transfer up to `amount` from the caller's account until `expiration_ledger`
+// (about 30 days from now when omitted); an amount of 0 revokes the allowance
+#[ic_cdk::update(guard = "guard_approve_token")]
+async fn approve_token(
+    contract_id: String,
+    spender: String,
+    amount: String,
+    expiration_ledger: Option<u32>,
+    network: Option<String>,
+) -> Result<ContractCall, TxError> {
+    let network = network::resolve(network)?;
+    let token = token_info(&contract_id, &network).await?;
+    let raw_amount = parse_token_amount(&amount, token.decimals)?;
+    let expiration_ledger = match expiration_ledger {
+        Some(ledger) => ledger,
+        None => soroban::latest_ledger(&network).await? + DEFAULT_APPROVAL_LEDGERS,
+    };
+    let source_address = public_key_stellar().await?;
+
+    let args = [
+        ContractValue::Address(source_address),
+        ContractValue::Address(spender.clone()),
+        ContractValue::I128(raw_amount),
+        ContractValue::U32(expiration_ledger),
+    ];
+    let request = TxRequest::new(TxKind::ContractCall, serde_json::json!({
+        "contract_id": contract_id,
+        "function": "approve",
+        "spender": spender,
+        "amount": amount,
+        "expiration_ledger": expiration_ledger,
+        "network": network,
+    }));
+    let (transaction, receipt) = soroban::call_contract(&contract_id, "approve", &args, &network, request).await?;
+
+    Ok(ContractCall {
+        transaction,
+        return_value: receipt.map(|receipt| ContractValue::from_sc_val(&receipt.return_value)),
+    })
+}
+
+// Follow a token contract so get_account_assets shows its balance
+#[ic_cdk::update(guard = "guard_add_token_contract")]
+async fn add_token_contract(contract_id: String, network: Option<String>) -> Result<TokenInfo, String> {
+    let network = network::resolve(network)?;
+    let token = fetch_token_info(&contract_id, &network).await?;
+
+    TRACKED.with(|tracked| {
+        let mut tracked = tracked.borrow_mut();
+        let tokens = tracked.entry(ic_cdk::caller()).or_default();
+        tokens.retain(|existing| existing.contract_id != contract_id || existing.network != network);
+        tokens.push(token.clone());
+    });
+    Ok(token)
+}
+
+#[ic_cdk::update]
+fn remove_token_contract(contract_id: String, network: Option<String>) -> Result<(), String> {
+    let network = network::resolve(network)?;
+    TRACKED.with(|tracked| {
+        if let Some(tokens) = tracked.borrow_mut().get_mut(&ic_cdk::caller()) {
+            tokens.retain(|token| token.contract_id != contract_id || token.network != network);
+        }
+    });
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn list_token_contracts(network: Option<String>) -> Result<Vec<TokenInfo>, String> {
+    Ok(tracked_tokens(&network::resolve(network)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_format_with_the_token_decimals() {
+        assert_eq!(format_token_amount(125_000_000, 7).unwrap(), "12.5000000");
+        assert_eq!(format_token_amount(5, 7).unwrap(), "0.0000005");
+        assert_eq!(format_token_amount(-5, 2).unwrap(), "-0.05");
+        assert_eq!(format_token_amount(42, 0).unwrap(), "42");
+        assert_eq!(format_token_amount(i128::MAX, 38).unwrap(), "1.70141183460469231731687303715884105727");
+        assert!(format_token_amount(1, 39).is_err());
+    }
+
+    #[test]
+    fn amounts_parse_to_raw_units() {
+        assert_eq!(parse_token_amount("12.5", 7), Ok(125_000_000));
+        assert_eq!(parse_token_amount("007.50", 2), Ok(750));
+        assert_eq!(parse_token_amount("0.000", 7), Ok(0));
+        // Empty whole or fraction part
+        assert_eq!(parse_token_amount(".5", 1), Ok(5));
+        assert_eq!(parse_token_amount("3.", 2), Ok(300));
+        assert!(parse_token_amount(".", 2).is_err());
+        assert!(parse_token_amount("", 2).is_err());
+
+        assert!(parse_token_amount("1.001", 2).is_err());
+        assert!(parse_token_amount("-1", 2).is_err());
+        assert!(parse_token_amount("1e5", 2).is_err());
+
+        // Overflow past i128
+        assert_eq!(parse_token_amount("1.70141183460469231731687303715884105727", 38), Ok(i128::MAX));
+        assert!(parse_token_amount("1.70141183460469231731687303715884105728", 38).is_err());
+        assert!(parse_token_amount("1", 39).is_err());
+    }
+}
@@ -0,0 +1,545 @@
+use candid::Func;
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs, TransformContext, TransformFunc};
+use num_traits::ToPrimitive;
+use serde_json::{Map, Value};
+
+// How an outcall response is normalized before replicas compare it. Every replica fetches
+// the response on its own, so anything that can differ between them (timestamps, the latest
+// ledger, rate-limit counters, headers) has to be dropped or the outcall fails consensus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transform {
+    // Drop headers only; for anchors, federation servers and other third-party documents
+    Headers,
+    // GET /accounts/{id}
+    HorizonAccount,
+    // GET /transactions/{hash}
+    HorizonTransaction,
+    // POST /transactions
+    HorizonSubmission,
+    // POST /transactions_async
+    HorizonAsyncSubmission,
+    // GET /accounts/{id}/payments and /operations with join=transactions
+    HorizonHistory,
+    // GET /trade_aggregations
+    HorizonTradeAggregations,
+    // getLatestLedger
+    RpcLatestLedger,
+    // getHealth
+    RpcHealth,
+    // getEvents
+    RpcEvents,
+    // simulateTransaction
+    RpcSimulateTransaction,
+    // sendTransaction
+    RpcSendTransaction,
+    // getTransaction
+    RpcGetTransaction,
+}
+
+// Granularity ledger numbers from getLatestLedger and getHealth are rounded to
+const LEDGER_ROUNDING: u64 = 10;
+
+const TRANSFORMS: [Transform; 13] = [
+    Transform::Headers,
+    Transform::HorizonAccount,
+    Transform::HorizonTransaction,
+    Transform::HorizonSubmission,
+    Transform::HorizonAsyncSubmission,
+    Transform::HorizonHistory,
+    Transform::HorizonTradeAggregations,
+    Transform::RpcLatestLedger,
+    Transform::RpcHealth,
+    Transform::RpcEvents,
+    Transform::RpcSimulateTransaction,
+    Transform::RpcSendTransaction,
+    Transform::RpcGetTransaction,
+];
+
+impl Transform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transform::Headers => "headers",
+            Transform::HorizonAccount => "horizon_account",
+            Transform::HorizonTransaction => "horizon_transaction",
+            Transform::HorizonSubmission => "horizon_submission",
+            Transform::HorizonAsyncSubmission => "horizon_async_submission",
+            Transform::HorizonHistory => "horizon_history",
+            Transform::HorizonTradeAggregations => "horizon_trade_aggregations",
+            Transform::RpcLatestLedger => "rpc_latest_ledger",
+            Transform::RpcHealth => "rpc_health",
+            Transform::RpcEvents => "rpc_events",
+            Transform::RpcSimulateTransaction => "rpc_simulate_transaction",
+            Transform::RpcSendTransaction => "rpc_send_transaction",
+            Transform::RpcGetTransaction => "rpc_get_transaction",
+        }
+    }
+
+    // The transform travels to transform_http_response as the context bytes
+    pub fn context(&self) -> TransformContext {
+        TransformContext {
+            function: TransformFunc(Func {
+                principal: ic_cdk::id(),
+                method: "transform_http_response".to_string(),
+            }),
+            context: self.name().as_bytes().to_vec(),
+        }
+    }
+
+    fn from_context(context: &[u8]) -> Transform {
+        TRANSFORMS
+            .into_iter()
+            .find(|transform| transform.name().as_bytes() == context)
+            .unwrap_or(Transform::Headers)
+    }
+}
+
+#[ic_cdk::query]
+fn transform_http_response(raw: TransformArgs) -> HttpResponse {
+    let transform = Transform::from_context(&raw.context);
+    let status = raw.response.status.0.to_u16().unwrap_or(500);
+
+    HttpResponse {
+        status: normalize_status(transform, status, &raw.response.body).into(),
+        body: normalize(transform, status, &raw.response.body),
+        headers: vec![],
+    }
+}
+
+// Horizon answers the replicas that submit after the first with 409 DUPLICATE; they get the
+// 201 the first one saw, to go with the PENDING body normalize gives them
+pub fn normalize_status(transform: Transform, status: u16, body: &[u8]) -> u16 {
+    if transform == Transform::HorizonAsyncSubmission && status == 409 {
+        let duplicate = serde_json::from_slice::<Value>(body).is_ok_and(|json| json["tx_status"] == "DUPLICATE");
+        if duplicate {
+            return 201;
+        }
+    }
+    status
+}
+
+// Canonical body for `transform`: only the fields its caller reads, re-serialized with
+// sorted keys and no whitespace so equivalent responses become byte-identical.
+pub fn normalize(transform: Transform, status: u16, body: &[u8]) -> Vec<u8> {
+    if transform == Transform::Headers {
+        return body.to_vec();
+    }
+
+    // Load balancer error pages and the like; the status is all callers use from them
+    let Ok(json) = serde_json::from_slice::<Value>(body) else {
+        return vec![];
+    };
+
+    let success = (200..300).contains(&status);
+    let kept = match transform {
+        Transform::Headers => json,
+        Transform::HorizonAccount if success => pick(&json, &["id", "sequence", "balances"]),
+        Transform::HorizonTransaction | Transform::HorizonSubmission if success => {
+            pick(&json, &["hash", "ledger", "successful", "result_xdr"])
+        }
+        Transform::HorizonAsyncSubmission => horizon_async_submission(&json),
+        Transform::HorizonHistory if success => horizon_history(&json),
+        Transform::HorizonTradeAggregations if success => serde_json::json!({
+            "_embedded": { "records": pick_each(&json["_embedded"]["records"], &["timestamp", "open", "close"]) }
+        }),
+        Transform::HorizonAccount
+        | Transform::HorizonTransaction
+        | Transform::HorizonSubmission
+        | Transform::HorizonHistory
+        | Transform::HorizonTradeAggregations => horizon_problem(&json),
+        Transform::RpcLatestLedger => round_ledgers(rpc_response(&json, &["sequence"]), &["sequence"], &[]),
+        Transform::RpcHealth => {
+            round_ledgers(rpc_response(&json, &["latestLedger", "oldestLedger"]), &["latestLedger"], &["oldestLedger"])
+        }
+        Transform::RpcEvents => rpc_response(&json, &["events", "cursor"]),
+        Transform::RpcSimulateTransaction => {
+            rpc_response(&json, &["transactionData", "minResourceFee", "results", "error", "restorePreamble"])
+        }
+        Transform::RpcSendTransaction => rpc_send_transaction(&json),
+        Transform::RpcGetTransaction => {
+            rpc_response(&json, &["status", "ledger", "applicationOrder", "resultXdr", "resultMetaXdr"])
+        }
+    };
+
+    serde_json::to_vec(&kept).unwrap_or_default()
+}
+
+// Horizon error documents (RFC 7807), keeping what submissions decode failures from
+fn horizon_problem(json: &Value) -> Value {
+    let mut problem = pick(json, &["title", "status", "detail"]);
+    if let Some(extras) = json.get("extras") {
+        problem["extras"] = pick(extras, &["hash", "result_xdr", "result_codes"]);
+    }
+    problem
+}
+
+// Every replica submits the transaction, so all but the first see it as a duplicate
+fn horizon_async_submission(json: &Value) -> Value {
+    let mut submission = merge(pick(json, &["hash", "tx_status", "errorResultXdr"]), horizon_problem(json));
+    if submission["tx_status"] == "DUPLICATE" {
+        submission["tx_status"] = Value::from("PENDING");
+        // The 409 problem fields of the duplicate answer
+        if let Some(submission) = submission.as_object_mut() {
+            for key in ["title", "status", "detail"] {
+                submission.remove(key);
+            }
+        }
+    }
+    submission
+}
+
+fn horizon_history(json: &Value) -> Value {
+    let records: Vec<Value> = json["_embedded"]["records"]
+        .as_array()
+        .map(|records| {
+            records
+                .iter()
+                .map(|record| {
+                    let mut record = record.clone();
+                    if let Some(record) = record.as_object_mut() {
+                        record.remove("_links");
+                        if let Some(transaction) = record.get("transaction") {
+                            let transaction = pick(transaction, &["memo_type", "memo"]);
+                            record.insert("transaction".to_string(), transaction);
+                        }
+                    }
+                    record
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    serde_json::json!({ "_embedded": { "records": records } })
+}
+
+// JSON-RPC envelope with `result` cut down to `fields`; errors are kept as they are
+fn rpc_response(json: &Value, fields: &[&str]) -> Value {
+    let mut response = pick(json, &["error"]);
+    if let Some(result) = json.get("result") {
+        response["result"] = pick(result, fields);
+    }
+    response
+}
+
+// Replicas read the ledger a few seconds apart, so the newest ledger is rounded down and the
+// oldest retained one up; they then agree unless a multiple of LEDGER_ROUNDING closes in between
+fn round_ledgers(mut response: Value, down: &[&str], up: &[&str]) -> Value {
+    if let Some(result) = response.get_mut("result") {
+        for (key, round_up) in down.iter().map(|key| (key, false)).chain(up.iter().map(|key| (key, true))) {
+            if let Some(ledger) = result.get(*key).and_then(Value::as_u64) {
+                let rounded = if round_up { ledger.div_ceil(LEDGER_ROUNDING) } else { ledger / LEDGER_ROUNDING };
+                result[*key] = Value::from(rounded * LEDGER_ROUNDING);
+            }
+        }
+    }
+    response
+}
+
+// Every replica submits the transaction, so all but the first see it as a duplicate
+fn rpc_send_transaction(json: &Value) -> Value {
+    let mut response = rpc_response(json, &["status", "hash", "errorResultXdr"]);
+    if response["result"]["status"] == "DUPLICATE" {
+        response["result"]["status"] = Value::from("PENDING");
+    }
+    response
+}
+
+fn pick(json: &Value, keys: &[&str]) -> Value {
+    let mut kept = Map::new();
+    for key in keys {
+        if let Some(value) = json.get(*key) {
+            kept.insert(key.to_string(), value.clone());
+        }
+    }
+    Value::Object(kept)
+}
+
+fn pick_each(array: &Value, keys: &[&str]) -> Value {
+    Value::Array(
+        array
+            .as_array()
+            .map(|items| items.iter().map(|item| pick(item, keys)).collect())
+            .unwrap_or_default(),
+    )
+}
+
+fn merge(mut into: Value, from: Value) -> Value {
+    if let (Some(into), Value::Object(from)) = (into.as_object_mut(), from) {
+        for (key, value) in from {
+            into.entry(key).or_insert(value);
+        }
+    }
+    into
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Responses recorded from horizon-testnet.stellar.org and soroban-testnet.stellar.org
+    const HORIZON_ACCOUNT: &str = include_str!("../testdata/horizon_account.json");
+    const HORIZON_TRANSACTION: &str = include_str!("../testdata/horizon_transaction.json");
+    const HORIZON_SUBMISSION_FAILED: &str = include_str!("../testdata/horizon_submission_failed.json");
+    const HORIZON_ASYNC_SUBMISSION: &str = include_str!("../testdata/horizon_async_submission.json");
+    const HORIZON_ASYNC_DUPLICATE: &str = include_str!("../testdata/horizon_async_duplicate.json");
+    const HORIZON_PAYMENTS: &str = include_str!("../testdata/horizon_payments.json");
+    const HORIZON_TRADE_AGGREGATIONS: &str = include_str!("../testdata/horizon_trade_aggregations.json");
+    const RPC_LATEST_LEDGER: &str = include_str!("../testdata/rpc_latest_ledger.json");
+    const RPC_HEALTH: &str = include_str!("../testdata/rpc_health.json");
+    const RPC_EVENTS: &str = include_str!("../testdata/rpc_events.json");
+    const RPC_SIMULATE_TRANSACTION: &str = include_str!("../testdata/rpc_simulate_transaction.json");
+    const RPC_SEND_TRANSACTION: &str = include_str!("../testdata/rpc_send_transaction.json");
+    const RPC_GET_TRANSACTION: &str = include_str!("../testdata/rpc_get_transaction.json");
+
+    fn normalized(transform: Transform, status: u16, body: &str) -> Value {
+        serde_json::from_slice(&normalize(transform, status, body.as_bytes())).unwrap()
+    }
+
+    // The same response as another replica might have seen it
+    fn with_changes(body: &str, changes: &[(&str, Value)]) -> String {
+        let mut json: Value = serde_json::from_str(body).unwrap();
+        for (pointer, value) in changes {
+            *json.pointer_mut(pointer).unwrap() = value.clone();
+        }
+        serde_json::to_string_pretty(&json).unwrap()
+    }
+
+    #[test]
+    fn context_round_trips_every_transform() {
+        for transform in TRANSFORMS {
+            assert_eq!(Transform::from_context(transform.name().as_bytes()), transform);
+        }
+        assert_eq!(Transform::from_context(b""), Transform::Headers);
+    }
+
+    #[test]
+    fn headers_transform_keeps_the_body() {
+        let body = b"[[DOCUMENTATION]]\nORG_NAME=\"Anchor\"\n";
+        assert_eq!(normalize(Transform::Headers, 200, body), body.to_vec());
+    }
+
+    #[test]
+    fn account_keeps_sequence_and_balances() {
+        let account = normalized(Transform::HorizonAccount, 200, HORIZON_ACCOUNT);
+
+        assert_eq!(account["sequence"], "1425718148431873");
+        assert_eq!(account["balances"].as_array().unwrap().len(), 2);
+        assert_eq!(account["balances"][0]["asset_code"], "USDC");
+        assert!(account.get("_links").is_none());
+        assert!(account.get("last_modified_time").is_none());
+    }
+
+    #[test]
+    fn account_ignores_fields_that_differ_between_replicas() {
+        let other = with_changes(
+            HORIZON_ACCOUNT,
+            &[
+                ("/last_modified_time", Value::from("2025-06-02T10:11:12Z")),
+                ("/_links/self/href", Value::from("https://horizon-testnet-2.stellar.org/accounts/x")),
+            ],
+        );
+        assert_eq!(
+            normalize(Transform::HorizonAccount, 200, HORIZON_ACCOUNT.as_bytes()),
+            normalize(Transform::HorizonAccount, 200, other.as_bytes())
+        );
+    }
+
+    #[test]
+    fn transaction_keeps_outcome() {
+        let transaction = normalized(Transform::HorizonTransaction, 200, HORIZON_TRANSACTION);
+
+        assert_eq!(transaction["successful"], true);
+        assert_eq!(transaction["ledger"], 331_947);
+        assert_eq!(transaction["result_xdr"], "AAAAAAAAAGQAAAAAAAAAAQAAAAAAAAABAAAAAAAAAAA=");
+        assert!(transaction.get("envelope_xdr").is_none());
+        assert!(transaction.get("created_at").is_none());
+    }
+
+    #[test]
+    fn failed_submission_keeps_result_xdr() {
+        let problem = normalized(Transform::HorizonSubmission, 400, HORIZON_SUBMISSION_FAILED);
+
+        assert_eq!(problem["title"], "Transaction Failed");
+        assert_eq!(problem["extras"]["result_xdr"], "AAAAAAAAAGT/////AAAAAQAAAAAAAAAB/////gAAAAA=");
+        assert_eq!(problem["extras"]["result_codes"]["transaction"], "tx_failed");
+        assert!(problem["extras"].get("envelope_xdr").is_none());
+        assert!(problem.get("type").is_none());
+    }
+
+    #[test]
+    fn async_submission_keeps_status() {
+        let submission = normalized(Transform::HorizonAsyncSubmission, 201, HORIZON_ASYNC_SUBMISSION);
+
+        assert_eq!(submission["tx_status"], "PENDING");
+        assert_eq!(
+            submission["hash"],
+            "6cbb7f714bd08cea7c30cab7818a35c510cbbfc0a6aa06172a1e94146ecf0165"
+        );
+    }
+
+    #[test]
+    fn async_duplicate_from_other_replicas_matches_the_first() {
+        let first = normalize(Transform::HorizonAsyncSubmission, 201, HORIZON_ASYNC_SUBMISSION.as_bytes());
+        let duplicate = normalize(Transform::HorizonAsyncSubmission, 409, HORIZON_ASYNC_DUPLICATE.as_bytes());
+        assert_eq!(first, duplicate);
+
+        let duplicate_status = normalize_status(Transform::HorizonAsyncSubmission, 409, HORIZON_ASYNC_DUPLICATE.as_bytes());
+        assert_eq!(duplicate_status, 201);
+        assert_eq!(normalize_status(Transform::HorizonAsyncSubmission, 400, HORIZON_SUBMISSION_FAILED.as_bytes()), 400);
+        assert_eq!(normalize_status(Transform::HorizonSubmission, 409, HORIZON_ASYNC_DUPLICATE.as_bytes()), 409);
+    }
+
+    #[test]
+    fn history_drops_links_and_transaction_envelopes() {
+        let page = normalized(Transform::HorizonHistory, 200, HORIZON_PAYMENTS);
+        let records = page["_embedded"]["records"].as_array().unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["type"], "payment");
+        assert_eq!(records[0]["paging_token"], "1425714647990273");
+        assert_eq!(records[0]["transaction"]["memo"], "invoice 42");
+        assert!(records[0].get("_links").is_none());
+        assert!(records[0]["transaction"].get("envelope_xdr").is_none());
+        assert!(page.get("_links").is_none());
+    }
+
+    #[test]
+    fn trade_aggregations_keep_open_and_close() {
+        let aggregations = normalized(Transform::HorizonTradeAggregations, 200, HORIZON_TRADE_AGGREGATIONS);
+        let records = aggregations["_embedded"]["records"].as_array().unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["open"], "0.2598000");
+        assert_eq!(records[1]["close"], "0.2647000");
+        assert!(records[0].get("base_volume").is_none());
+        assert!(aggregations.get("_links").is_none());
+    }
+
+    #[test]
+    fn closed_trade_aggregations_match_across_replicas() {
+        // Pages fetched a little apart link to different cursors; only the prices are compared
+        let other = with_changes(
+            HORIZON_TRADE_AGGREGATIONS,
+            &[
+                ("/_links/next/href", Value::from("https://horizon.stellar.org/trade_aggregations?cursor=1748862000001")),
+                ("/_embedded/records/1/trade_count", Value::from("98")),
+                ("/_embedded/records/1/base_volume", Value::from("301245.5000000")),
+            ],
+        );
+        assert_eq!(
+            normalize(Transform::HorizonTradeAggregations, 200, HORIZON_TRADE_AGGREGATIONS.as_bytes()),
+            normalize(Transform::HorizonTradeAggregations, 200, other.as_bytes())
+        );
+    }
+
+    #[test]
+    fn latest_ledger_keeps_sequence_only() {
+        let response = normalized(Transform::RpcLatestLedger, 200, RPC_LATEST_LEDGER);
+        assert_eq!(response, serde_json::json!({ "result": { "sequence": 331_960 } }));
+
+        let later = with_changes(RPC_LATEST_LEDGER, &[("/result/sequence", Value::from(331_967))]);
+        assert_eq!(normalized(Transform::RpcLatestLedger, 200, &later), response);
+    }
+
+    #[test]
+    fn health_keeps_the_retention_window() {
+        let response = normalized(Transform::RpcHealth, 200, RPC_HEALTH);
+        assert_eq!(
+            response,
+            serde_json::json!({ "result": { "latestLedger": 331_960, "oldestLedger": 211_010 } })
+        );
+
+        let later = with_changes(
+            RPC_HEALTH,
+            &[("/result/latestLedger", Value::from(331_962)), ("/result/oldestLedger", Value::from(211_003))],
+        );
+        assert_eq!(normalized(Transform::RpcHealth, 200, &later), response);
+    }
+
+    #[test]
+    fn events_ignore_the_moving_ledger_window() {
+        let other = with_changes(
+            RPC_EVENTS,
+            &[
+                ("/result/latestLedger", Value::from(331_961)),
+                ("/result/latestLedgerCloseTime", Value::from("1748858480")),
+                ("/result/oldestLedger", Value::from(210_000)),
+            ],
+        );
+        let first = normalize(Transform::RpcEvents, 200, RPC_EVENTS.as_bytes());
+        assert_eq!(first, normalize(Transform::RpcEvents, 200, other.as_bytes()));
+
+        let response: Value = serde_json::from_slice(&first).unwrap();
+        let events = response["result"]["events"].as_array().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["contractId"], "CDTA5IYGUGRI4PAGXJL7TPBEIC3EZY6V23ILF5EDVXFVLCGGMVOK4CRL");
+        assert_eq!(response["result"]["cursor"], "0001425701509009409-0000000001");
+    }
+
+    #[test]
+    fn simulation_keeps_resources_and_results() {
+        let response = normalized(Transform::RpcSimulateTransaction, 200, RPC_SIMULATE_TRANSACTION);
+        let result = &response["result"];
+
+        assert_eq!(result["minResourceFee"], "124483");
+        assert_eq!(result["results"][0]["xdr"], "AAAAEAAAAAEAAAACAAAADwAAAAVIZWxsbwAAAAAAAA8AAAAFd29ybGQAAAA=");
+        assert!(result["transactionData"].is_string());
+        assert!(result.get("latestLedger").is_none());
+        assert!(result.get("cost").is_none());
+        assert!(result.get("events").is_none());
+    }
+
+    #[test]
+    fn duplicate_submissions_from_other_replicas_match_the_first() {
+        let duplicate = with_changes(
+            RPC_SEND_TRANSACTION,
+            &[
+                ("/result/status", Value::from("DUPLICATE")),
+                ("/result/latestLedger", Value::from(331_962)),
+            ],
+        );
+        let first = normalize(Transform::RpcSendTransaction, 200, RPC_SEND_TRANSACTION.as_bytes());
+        assert_eq!(first, normalize(Transform::RpcSendTransaction, 200, duplicate.as_bytes()));
+
+        let response: Value = serde_json::from_slice(&first).unwrap();
+        assert_eq!(response["result"]["status"], "PENDING");
+    }
+
+    #[test]
+    fn get_transaction_keeps_outcome_and_meta() {
+        let response = normalized(Transform::RpcGetTransaction, 200, RPC_GET_TRANSACTION);
+        assert_eq!(
+            response["result"],
+            serde_json::json!({
+                "status": "SUCCESS",
+                "ledger": 331_962,
+                "applicationOrder": 1,
+                "resultXdr": "AAAAAAAB5YsAAAAAAAAAAQAAAAAAAAAYAAAAAAAAAAA=",
+                "resultMetaXdr": "AAAAAwAAAAAAAAACAAAAAwAFBPoAAAAAAAAAAA==",
+            })
+        );
+    }
+
+    #[test]
+    fn rpc_errors_are_kept() {
+        let body = r#"{"jsonrpc":"2.0","id":8675309,"error":{"code":-32600,"message":"startLedger must be between the oldest ledger: 210000 and the latest ledger: 331960"}}"#;
+        let response = normalized(Transform::RpcEvents, 200, body);
+        assert_eq!(response["error"]["code"], -32600);
+        assert!(response.get("result").is_none());
+    }
+
+    #[test]
+    fn key_order_does_not_matter() {
+        let reordered = r#"{"balances":[],"sequence":"7","id":"G"}"#;
+        let ordered = r#"{"id":"G","sequence":"7","balances":[]}"#;
+        assert_eq!(
+            normalize(Transform::HorizonAccount, 200, reordered.as_bytes()),
+            normalize(Transform::HorizonAccount, 200, ordered.as_bytes())
+        );
+    }
+
+    #[test]
+    fn error_pages_are_dropped() {
+        let page = b"<html><body>502 Bad Gateway<br>request id 81f3c</body></html>";
+        assert!(normalize(Transform::HorizonAccount, 502, page).is_empty());
+    }
+}
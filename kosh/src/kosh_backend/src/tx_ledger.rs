@@ -0,0 +1,195 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use stellar_xdr::curr::TransactionEnvelope;
+
+use crate::network;
+use crate::{decode_envelope, transaction_hash};
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+// Keeps one page within the response size limit
+const MAX_PAGE_SIZE: u64 = 200;
+// Records kept per principal; the oldest settled ones go first. Every record is a submitted
+// transaction that paid a fee, so this also bounds the ledger as a whole.
+const MAX_RECORDS_PER_PRINCIPAL: usize = 1_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum TxKind {
+    Payment,
+    Swap,
+    Trustline,
+    Bridge,
+    ContractCall,
+    Other,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum TxStatus {
+    Pending,
+    Success,
+    Failed(String),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TxRecord {
+    pub hash: String,
+    pub network: String,
+    pub kind: TxKind,
+    // Arguments of the endpoint that built the transaction, as JSON
+    pub params: String,
+    pub envelope_xdr: String,
+    pub submitted_at: u64,
+    pub status: TxStatus,
+}
+
+// What the caller asked for, carried alongside a transaction until it is submitted
+#[derive(Clone, Debug)]
+pub struct TxRequest {
+    pub kind: TxKind,
+    pub params: serde_json::Value,
+}
+
+impl TxRequest {
+    pub fn new(kind: TxKind, params: serde_json::Value) -> Self {
+        TxRequest { kind, params }
+    }
+}
+
+thread_local! {
+    // Submitted transactions per principal, oldest first; saved across upgrades by stable_state
+    static TX_LEDGER: RefCell<BTreeMap<Principal, Vec<TxRecord>>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub fn record(signed_xdr: &str, network: &str, request: &TxRequest, status: TxStatus) -> Result<TxRecord, String> {
+    let TransactionEnvelope::Tx(envelope) = decode_envelope(signed_xdr)? else {
+        return Err("Invalid transaction envelope type".to_string());
+    };
+
+    let record = TxRecord {
+        hash: hex::encode(transaction_hash(&envelope.tx, &network::passphrase(network)?)?),
+        network: network.to_string(),
+        kind: request.kind.clone(),
+        params: request.params.to_string(),
+        envelope_xdr: signed_xdr.to_string(),
+        submitted_at: ic_cdk::api::time(),
+        status,
+    };
+
+    TX_LEDGER.with(|ledger| {
+        let mut ledger = ledger.borrow_mut();
+        let records = ledger.entry(ic_cdk::caller()).or_default();
+        records.push(record.clone());
+        prune(records, MAX_RECORDS_PER_PRINCIPAL);
+    });
+
+    Ok(record)
+}
+
+// Drop the oldest settled records beyond `max_records`; pending ones stay for the poller
+fn prune(records: &mut Vec<TxRecord>, max_records: usize) {
+    let mut excess = records.len().saturating_sub(max_records);
+    records.retain(|record| {
+        let drop = excess > 0 && record.status != TxStatus::Pending;
+        if drop {
+            excess -= 1;
+        }
+        !drop
+    });
+}
+
+pub fn update_status(owner: Principal, hash: &str, status: TxStatus) {
+    TX_LEDGER.with(|ledger| {
+        if let Some(record) = ledger
+            .borrow_mut()
+            .get_mut(&owner)
+            .and_then(|records| records.iter_mut().rev().find(|record| record.hash == hash))
+        {
+            record.status = status;
+        }
+    });
+}
+
+// Every record still waiting for a ledger, with its owner
+pub fn pending() -> Vec<(Principal, TxRecord)> {
+    TX_LEDGER.with(|ledger| {
+        ledger
+            .borrow()
+            .iter()
+            .flat_map(|(owner, records)| {
+                records
+                    .iter()
+                    .filter(|record| record.status == TxStatus::Pending)
+                    .map(move |record| (*owner, record.clone()))
+            })
+            .collect()
+    })
+}
+
+pub fn snapshot() -> Vec<(Principal, Vec<TxRecord>)> {
+    TX_LEDGER.with(|ledger| ledger.borrow().iter().map(|(p, r)| (*p, r.clone())).collect())
+}
+
+pub fn restore(entries: Vec<(Principal, Vec<TxRecord>)>) {
+    TX_LEDGER.with(|ledger| *ledger.borrow_mut() = entries.into_iter().collect());
+}
+
+// Caller's submitted transactions, newest first
+#[ic_cdk::query]
+fn list_transactions(offset: Option<u64>, limit: Option<u64>) -> Vec<TxRecord> {
+    let offset = offset.unwrap_or(0) as usize;
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE) as usize;
+
+    TX_LEDGER.with(|ledger| {
+        ledger
+            .borrow()
+            .get(&ic_cdk::caller())
+            .map(|records| records.iter().rev().skip(offset).take(limit).cloned().collect())
+            .unwrap_or_default()
+    })
+}
+
+#[ic_cdk::query]
+fn get_transaction(hash: String) -> Option<TxRecord> {
+    TX_LEDGER.with(|ledger| {
+        ledger
+            .borrow()
+            .get(&ic_cdk::caller())
+            .and_then(|records| records.iter().rev().find(|record| record.hash == hash).cloned())
+    })
+}
+
+#[ic_cdk::query]
+fn get_transaction_status(hash: String) -> Option<TxStatus> {
+    get_transaction(hash).map(|record| record.status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(hash: &str, status: TxStatus) -> TxRecord {
+        TxRecord {
+            hash: hash.to_string(),
+            network: "testnet".to_string(),
+            kind: TxKind::Payment,
+            params: "{}".to_string(),
+            envelope_xdr: String::new(),
+            submitted_at: 0,
+            status,
+        }
+    }
+
+    #[test]
+    fn pruning_keeps_pending_records() {
+        let mut records = vec![
+            record("a", TxStatus::Pending),
+            record("b", TxStatus::Success),
+            record("c", TxStatus::Failed("expired".to_string())),
+            record("d", TxStatus::Success),
+        ];
+        prune(&mut records, 2);
+        let hashes: Vec<&str> = records.iter().map(|record| record.hash.as_str()).collect();
+        assert_eq!(hashes, vec!["a", "d"]);
+    }
+}
@@ -0,0 +1,726 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use stellar_xdr::curr::{
+    AccountMergeResultCode, AllowTrustResultCode, BeginSponsoringFutureReservesResultCode,
+    BumpSequenceResultCode, ChangeTrustResultCode, ClaimClaimableBalanceResultCode,
+    ClawbackClaimableBalanceResultCode, ClawbackResultCode, CreateAccountResultCode,
+    CreateClaimableBalanceResultCode, EndSponsoringFutureReservesResultCode,
+    ExtendFootprintTtlResultCode, InflationResultCode, InnerTransactionResultResult,
+    InvokeHostFunctionResultCode, Limited, Limits, LiquidityPoolDepositResultCode,
+    LiquidityPoolWithdrawResultCode, ManageBuyOfferResultCode, ManageDataResultCode,
+    ManageSellOfferResultCode, OperationResult, OperationResultTr,
+    PathPaymentStrictReceiveResultCode, PathPaymentStrictSendResultCode, PaymentResultCode, ReadXdr,
+    RestoreFootprintResultCode, RevokeSponsorshipResultCode, SetOptionsResultCode,
+    SetTrustLineFlagsResultCode, TransactionResult, TransactionResultResult,
+};
+
+use crate::tx_ledger::TxStatus;
+
+// A transaction Stellar accepted: either already in a ledger, or queued and followed by
+// the confirmation poller (see get_transaction_status)
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SubmittedTransaction {
+    pub hash: String,
+    pub network: String,
+    pub status: TxStatus,
+    pub explorer_url: String,
+}
+
+// What the caller can do about a failed submission
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum RetryHint {
+    // Transient: submit the same transaction again later
+    RetryLater,
+    // Build and sign a fresh transaction (new sequence number, time bounds or prices)
+    Rebuild,
+    // Rebuild with a higher fee
+    IncreaseFee,
+    // Something on the account has to change first (funding, trustline, authorization)
+    UserAction,
+    // Fails the same way however often it is retried
+    DoNotRetry,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum TransactionErrorCode {
+    TooEarly,
+    TooLate,
+    MissingOperation,
+    BadSeq,
+    BadAuth,
+    InsufficientBalance,
+    NoAccount,
+    InsufficientFee,
+    BadAuthExtra,
+    InternalError,
+    NotSupported,
+    BadSponsorship,
+    BadMinSeqAgeOrGap,
+    Malformed,
+    SorobanInvalid,
+}
+
+// Result codes of every operation type, merged by name: `Underfunded` means the same
+// thing for a payment, an offer or a liquidity pool deposit
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum OperationErrorCode {
+    // Codes shared by all operations
+    BadAuth,
+    SourceAccountMissing,
+    OperationNotSupported,
+    TooManySubentries,
+    ExceededWorkLimit,
+    TooManySponsoring,
+    // Operation specific codes
+    Malformed,
+    Underfunded,
+    LowReserve,
+    AlreadyExist,
+    SrcNoTrust,
+    SrcNotAuthorized,
+    NoDestination,
+    NoTrust,
+    NotAuthorized,
+    LineFull,
+    NoIssuer,
+    TooFewOffers,
+    OfferCrossSelf,
+    OverSendMax,
+    UnderDestMin,
+    SellNoTrust,
+    BuyNoTrust,
+    SellNotAuthorized,
+    BuyNotAuthorized,
+    CrossSelf,
+    SellNoIssuer,
+    BuyNoIssuer,
+    NotFound,
+    TooManySigners,
+    BadFlags,
+    InvalidInflation,
+    CantChange,
+    UnknownFlag,
+    ThresholdOutOfRange,
+    BadSigner,
+    InvalidHomeDomain,
+    AuthRevocableRequired,
+    InvalidLimit,
+    SelfNotAllowed,
+    TrustLineMissing,
+    CannotDelete,
+    NotAuthMaintainLiabilities,
+    NoTrustLine,
+    TrustNotRequired,
+    CantRevoke,
+    NoAccount,
+    ImmutableSet,
+    HasSubEntries,
+    SeqnumTooFar,
+    DestFull,
+    IsSponsor,
+    NotTime,
+    NotSupportedYet,
+    NameNotFound,
+    InvalidName,
+    BadSeq,
+    DoesNotExist,
+    CannotClaim,
+    AlreadySponsored,
+    Recursive,
+    NotSponsored,
+    NotSponsor,
+    OnlyTransferable,
+    NotClawbackEnabled,
+    NotIssuer,
+    InvalidState,
+    BadPrice,
+    PoolFull,
+    UnderMinimum,
+    Trapped,
+    ResourceLimitExceeded,
+    EntryArchived,
+    InsufficientRefundableFee,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum TxErrorCode {
+    Transaction(TransactionErrorCode),
+    Operation(OperationErrorCode),
+    // Failed before Stellar saw the transaction: invalid arguments, signing or outcall errors
+    Request,
+    // No result XDR to decode, or a code this build does not know
+    Unknown(String),
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TxError {
+    pub code: TxErrorCode,
+    // Index of the failing operation for operation-level codes
+    pub operation_index: Option<u32>,
+    // e.g. "Payment", "ChangeTrust"
+    pub operation_type: Option<String>,
+    pub retry: RetryHint,
+    pub message: String,
+}
+
+impl TxError {
+    pub fn unknown(message: String) -> Self {
+        TxError {
+            code: TxErrorCode::Unknown(message.clone()),
+            operation_index: None,
+            operation_type: None,
+            retry: RetryHint::DoNotRetry,
+            message,
+        }
+    }
+
+    pub fn retry_later(message: String) -> Self {
+        TxError {
+            retry: RetryHint::RetryLater,
+            ..TxError::unknown(message)
+        }
+    }
+}
+
+// Lets submission endpoints use `?` on the String errors of the helpers they call
+impl From<String> for TxError {
+    fn from(message: String) -> Self {
+        TxError {
+            code: TxErrorCode::Request,
+            operation_index: None,
+            operation_type: None,
+            retry: RetryHint::DoNotRetry,
+            message,
+        }
+    }
+}
+
+impl From<&str> for TxError {
+    fn from(message: &str) -> Self {
+        TxError::from(message.to_string())
+    }
+}
+
+// Decode a base64 TransactionResult (Horizon's `extras.result_xdr`, `errorResultXdr` or a
+// transaction's `result_xdr`). Returns None for a successful result.
+pub fn decode_result_xdr(result_xdr: &str) -> Result<Option<TxError>, String> {
+    let bytes = STANDARD
+        .decode(result_xdr.trim())
+        .map_err(|e| format!("Failed to decode result XDR: {}", e))?;
+
+    let limits = Limits {
+        depth: 100,
+        len: 10000,
+    };
+    let mut limited = Limited::new(bytes.as_slice(), limits);
+    let result = TransactionResult::read_xdr(&mut limited)
+        .map_err(|e| format!("Failed to parse TransactionResult: {}", e))?;
+
+    Ok(match result.result {
+        TransactionResultResult::TxSuccess(_) | TransactionResultResult::TxFeeBumpInnerSuccess(_) => None,
+        TransactionResultResult::TxFeeBumpInnerFailed(pair) => inner_error(&pair.result.result),
+        TransactionResultResult::TxFailed(ops) => Some(operation_error(ops.as_slice())),
+        TransactionResultResult::TxTooEarly => Some(transaction_error(TransactionErrorCode::TooEarly)),
+        TransactionResultResult::TxTooLate => Some(transaction_error(TransactionErrorCode::TooLate)),
+        TransactionResultResult::TxMissingOperation => Some(transaction_error(TransactionErrorCode::MissingOperation)),
+        TransactionResultResult::TxBadSeq => Some(transaction_error(TransactionErrorCode::BadSeq)),
+        TransactionResultResult::TxBadAuth => Some(transaction_error(TransactionErrorCode::BadAuth)),
+        TransactionResultResult::TxInsufficientBalance => Some(transaction_error(TransactionErrorCode::InsufficientBalance)),
+        TransactionResultResult::TxNoAccount => Some(transaction_error(TransactionErrorCode::NoAccount)),
+        TransactionResultResult::TxInsufficientFee => Some(transaction_error(TransactionErrorCode::InsufficientFee)),
+        TransactionResultResult::TxBadAuthExtra => Some(transaction_error(TransactionErrorCode::BadAuthExtra)),
+        TransactionResultResult::TxInternalError => Some(transaction_error(TransactionErrorCode::InternalError)),
+        TransactionResultResult::TxNotSupported => Some(transaction_error(TransactionErrorCode::NotSupported)),
+        TransactionResultResult::TxBadSponsorship => Some(transaction_error(TransactionErrorCode::BadSponsorship)),
+        TransactionResultResult::TxBadMinSeqAgeOrGap => Some(transaction_error(TransactionErrorCode::BadMinSeqAgeOrGap)),
+        TransactionResultResult::TxMalformed => Some(transaction_error(TransactionErrorCode::Malformed)),
+        TransactionResultResult::TxSorobanInvalid => Some(transaction_error(TransactionErrorCode::SorobanInvalid)),
+    })
+}
+
+// Result of the inner transaction of a fee bump
+fn inner_error(result: &InnerTransactionResultResult) -> Option<TxError> {
+    let code = match result {
+        InnerTransactionResultResult::TxSuccess(_) => return None,
+        InnerTransactionResultResult::TxFailed(ops) => return Some(operation_error(ops.as_slice())),
+        InnerTransactionResultResult::TxTooEarly => TransactionErrorCode::TooEarly,
+        InnerTransactionResultResult::TxTooLate => TransactionErrorCode::TooLate,
+        InnerTransactionResultResult::TxMissingOperation => TransactionErrorCode::MissingOperation,
+        InnerTransactionResultResult::TxBadSeq => TransactionErrorCode::BadSeq,
+        InnerTransactionResultResult::TxBadAuth => TransactionErrorCode::BadAuth,
+        InnerTransactionResultResult::TxInsufficientBalance => TransactionErrorCode::InsufficientBalance,
+        InnerTransactionResultResult::TxNoAccount => TransactionErrorCode::NoAccount,
+        InnerTransactionResultResult::TxInsufficientFee => TransactionErrorCode::InsufficientFee,
+        InnerTransactionResultResult::TxBadAuthExtra => TransactionErrorCode::BadAuthExtra,
+        InnerTransactionResultResult::TxInternalError => TransactionErrorCode::InternalError,
+        InnerTransactionResultResult::TxNotSupported => TransactionErrorCode::NotSupported,
+        InnerTransactionResultResult::TxBadSponsorship => TransactionErrorCode::BadSponsorship,
+        InnerTransactionResultResult::TxBadMinSeqAgeOrGap => TransactionErrorCode::BadMinSeqAgeOrGap,
+        InnerTransactionResultResult::TxMalformed => TransactionErrorCode::Malformed,
+        InnerTransactionResultResult::TxSorobanInvalid => TransactionErrorCode::SorobanInvalid,
+    };
+    Some(transaction_error(code))
+}
+
+fn transaction_error(code: TransactionErrorCode) -> TxError {
+    use TransactionErrorCode::*;
+
+    let (retry, message) = match code {
+        TooEarly => (RetryHint::RetryLater, "Transaction is not valid yet (time bounds)"),
+        TooLate => (RetryHint::Rebuild, "Transaction expired before it was included in a ledger"),
+        MissingOperation => (RetryHint::DoNotRetry, "Transaction has no operations"),
+        BadSeq => (RetryHint::Rebuild, "Sequence number is out of date; rebuild the transaction"),
+        BadAuth => (RetryHint::DoNotRetry, "Transaction is missing a valid signature"),
+        InsufficientBalance => (RetryHint::UserAction, "Not enough XLM to pay the fee while keeping the minimum reserve"),
+        NoAccount => (RetryHint::UserAction, "Source account does not exist; fund it with XLM first"),
+        InsufficientFee => (RetryHint::IncreaseFee, "Fee is too low for current network conditions"),
+        BadAuthExtra => (RetryHint::DoNotRetry, "Transaction carries unnecessary signatures"),
+        InternalError => (RetryHint::RetryLater, "Stellar core reported an internal error"),
+        NotSupported => (RetryHint::DoNotRetry, "Transaction type is not supported by the network"),
+        BadSponsorship => (RetryHint::DoNotRetry, "Sponsorship operations are not balanced"),
+        BadMinSeqAgeOrGap => (RetryHint::RetryLater, "Sequence age or ledger gap precondition is not met yet"),
+        Malformed => (RetryHint::DoNotRetry, "Transaction is malformed"),
+        SorobanInvalid => (RetryHint::Rebuild, "Soroban resources are invalid; simulate and rebuild the transaction"),
+    };
+
+    TxError {
+        code: TxErrorCode::Transaction(code),
+        operation_index: None,
+        operation_type: None,
+        retry,
+        message: message.to_string(),
+    }
+}
+
+// tx_failed: report the first operation that did not succeed
+fn operation_error(results: &[OperationResult]) -> TxError {
+    for (index, result) in results.iter().enumerate() {
+        let (operation_type, code) = match result {
+            OperationResult::OpInner(tr) => match inner_code(tr) {
+                Some(code) => (Some(tr.name().to_string()), code),
+                None => continue,
+            },
+            OperationResult::OpBadAuth => (None, OperationErrorCode::BadAuth),
+            OperationResult::OpNoAccount => (None, OperationErrorCode::SourceAccountMissing),
+            OperationResult::OpNotSupported => (None, OperationErrorCode::OperationNotSupported),
+            OperationResult::OpTooManySubentries => (None, OperationErrorCode::TooManySubentries),
+            OperationResult::OpExceededWorkLimit => (None, OperationErrorCode::ExceededWorkLimit),
+            OperationResult::OpTooManySponsoring => (None, OperationErrorCode::TooManySponsoring),
+        };
+
+        let (retry, message) = operation_hint(&code);
+
+        return TxError {
+            code: TxErrorCode::Operation(code),
+            operation_index: Some(index as u32),
+            message: match &operation_type {
+                Some(op) => format!("Operation {} ({}) failed: {}", index, op, message),
+                None => format!("Operation {} failed: {}", index, message),
+            },
+            operation_type,
+            retry,
+        };
+    }
+
+    TxError::unknown("Transaction failed but every operation reported success".to_string())
+}
+
+// Failure code of an operation's own result, or None when it succeeded
+fn inner_code(result: &OperationResultTr) -> Option<OperationErrorCode> {
+    use OperationErrorCode as Code;
+
+    Some(match result {
+        OperationResultTr::CreateAccount(r) => match r.discriminant() {
+            CreateAccountResultCode::Success => return None,
+            CreateAccountResultCode::Malformed => Code::Malformed,
+            CreateAccountResultCode::Underfunded => Code::Underfunded,
+            CreateAccountResultCode::LowReserve => Code::LowReserve,
+            CreateAccountResultCode::AlreadyExist => Code::AlreadyExist,
+        },
+        OperationResultTr::Payment(r) => match r.discriminant() {
+            PaymentResultCode::Success => return None,
+            PaymentResultCode::Malformed => Code::Malformed,
+            PaymentResultCode::Underfunded => Code::Underfunded,
+            PaymentResultCode::SrcNoTrust => Code::SrcNoTrust,
+            PaymentResultCode::SrcNotAuthorized => Code::SrcNotAuthorized,
+            PaymentResultCode::NoDestination => Code::NoDestination,
+            PaymentResultCode::NoTrust => Code::NoTrust,
+            PaymentResultCode::NotAuthorized => Code::NotAuthorized,
+            PaymentResultCode::LineFull => Code::LineFull,
+            PaymentResultCode::NoIssuer => Code::NoIssuer,
+        },
+        OperationResultTr::PathPaymentStrictReceive(r) => match r.discriminant() {
+            PathPaymentStrictReceiveResultCode::Success => return None,
+            PathPaymentStrictReceiveResultCode::Malformed => Code::Malformed,
+            PathPaymentStrictReceiveResultCode::Underfunded => Code::Underfunded,
+            PathPaymentStrictReceiveResultCode::SrcNoTrust => Code::SrcNoTrust,
+            PathPaymentStrictReceiveResultCode::SrcNotAuthorized => Code::SrcNotAuthorized,
+            PathPaymentStrictReceiveResultCode::NoDestination => Code::NoDestination,
+            PathPaymentStrictReceiveResultCode::NoTrust => Code::NoTrust,
+            PathPaymentStrictReceiveResultCode::NotAuthorized => Code::NotAuthorized,
+            PathPaymentStrictReceiveResultCode::LineFull => Code::LineFull,
+            PathPaymentStrictReceiveResultCode::NoIssuer => Code::NoIssuer,
+            PathPaymentStrictReceiveResultCode::TooFewOffers => Code::TooFewOffers,
+            PathPaymentStrictReceiveResultCode::OfferCrossSelf => Code::OfferCrossSelf,
+            PathPaymentStrictReceiveResultCode::OverSendmax => Code::OverSendMax,
+        },
+        OperationResultTr::ManageSellOffer(r) => match r.discriminant() {
+            ManageSellOfferResultCode::Success => return None,
+            ManageSellOfferResultCode::Malformed => Code::Malformed,
+            ManageSellOfferResultCode::SellNoTrust => Code::SellNoTrust,
+            ManageSellOfferResultCode::BuyNoTrust => Code::BuyNoTrust,
+            ManageSellOfferResultCode::SellNotAuthorized => Code::SellNotAuthorized,
+            ManageSellOfferResultCode::BuyNotAuthorized => Code::BuyNotAuthorized,
+            ManageSellOfferResultCode::LineFull => Code::LineFull,
+            ManageSellOfferResultCode::Underfunded => Code::Underfunded,
+            ManageSellOfferResultCode::CrossSelf => Code::CrossSelf,
+            ManageSellOfferResultCode::SellNoIssuer => Code::SellNoIssuer,
+            ManageSellOfferResultCode::BuyNoIssuer => Code::BuyNoIssuer,
+            ManageSellOfferResultCode::NotFound => Code::NotFound,
+            ManageSellOfferResultCode::LowReserve => Code::LowReserve,
+        },
+        OperationResultTr::CreatePassiveSellOffer(r) => match r.discriminant() {
+            ManageSellOfferResultCode::Success => return None,
+            ManageSellOfferResultCode::Malformed => Code::Malformed,
+            ManageSellOfferResultCode::SellNoTrust => Code::SellNoTrust,
+            ManageSellOfferResultCode::BuyNoTrust => Code::BuyNoTrust,
+            ManageSellOfferResultCode::SellNotAuthorized => Code::SellNotAuthorized,
+            ManageSellOfferResultCode::BuyNotAuthorized => Code::BuyNotAuthorized,
+            ManageSellOfferResultCode::LineFull => Code::LineFull,
+            ManageSellOfferResultCode::Underfunded => Code::Underfunded,
+            ManageSellOfferResultCode::CrossSelf => Code::CrossSelf,
+            ManageSellOfferResultCode::SellNoIssuer => Code::SellNoIssuer,
+            ManageSellOfferResultCode::BuyNoIssuer => Code::BuyNoIssuer,
+            ManageSellOfferResultCode::NotFound => Code::NotFound,
+            ManageSellOfferResultCode::LowReserve => Code::LowReserve,
+        },
+        OperationResultTr::SetOptions(r) => match r.discriminant() {
+            SetOptionsResultCode::Success => return None,
+            SetOptionsResultCode::LowReserve => Code::LowReserve,
+            SetOptionsResultCode::TooManySigners => Code::TooManySigners,
+            SetOptionsResultCode::BadFlags => Code::BadFlags,
+            SetOptionsResultCode::InvalidInflation => Code::InvalidInflation,
+            SetOptionsResultCode::CantChange => Code::CantChange,
+            SetOptionsResultCode::UnknownFlag => Code::UnknownFlag,
+            SetOptionsResultCode::ThresholdOutOfRange => Code::ThresholdOutOfRange,
+            SetOptionsResultCode::BadSigner => Code::BadSigner,
+            SetOptionsResultCode::InvalidHomeDomain => Code::InvalidHomeDomain,
+            SetOptionsResultCode::AuthRevocableRequired => Code::AuthRevocableRequired,
+        },
+        OperationResultTr::ChangeTrust(r) => match r.discriminant() {
+            ChangeTrustResultCode::Success => return None,
+            ChangeTrustResultCode::Malformed => Code::Malformed,
+            ChangeTrustResultCode::NoIssuer => Code::NoIssuer,
+            ChangeTrustResultCode::InvalidLimit => Code::InvalidLimit,
+            ChangeTrustResultCode::LowReserve => Code::LowReserve,
+            ChangeTrustResultCode::SelfNotAllowed => Code::SelfNotAllowed,
+            ChangeTrustResultCode::TrustLineMissing => Code::TrustLineMissing,
+            ChangeTrustResultCode::CannotDelete => Code::CannotDelete,
+            ChangeTrustResultCode::NotAuthMaintainLiabilities => Code::NotAuthMaintainLiabilities,
+        },
+        OperationResultTr::AllowTrust(r) => match r.discriminant() {
+            AllowTrustResultCode::Success => return None,
+            AllowTrustResultCode::Malformed => Code::Malformed,
+            AllowTrustResultCode::NoTrustLine => Code::NoTrustLine,
+            AllowTrustResultCode::TrustNotRequired => Code::TrustNotRequired,
+            AllowTrustResultCode::CantRevoke => Code::CantRevoke,
+            AllowTrustResultCode::SelfNotAllowed => Code::SelfNotAllowed,
+            AllowTrustResultCode::LowReserve => Code::LowReserve,
+        },
+        OperationResultTr::AccountMerge(r) => match r.discriminant() {
+            AccountMergeResultCode::Success => return None,
+            AccountMergeResultCode::Malformed => Code::Malformed,
+            AccountMergeResultCode::NoAccount => Code::NoAccount,
+            AccountMergeResultCode::ImmutableSet => Code::ImmutableSet,
+            AccountMergeResultCode::HasSubEntries => Code::HasSubEntries,
+            AccountMergeResultCode::SeqnumTooFar => Code::SeqnumTooFar,
+            AccountMergeResultCode::DestFull => Code::DestFull,
+            AccountMergeResultCode::IsSponsor => Code::IsSponsor,
+        },
+        OperationResultTr::Inflation(r) => match r.discriminant() {
+            InflationResultCode::Success => return None,
+            InflationResultCode::NotTime => Code::NotTime,
+        },
+        OperationResultTr::ManageData(r) => match r.discriminant() {
+            ManageDataResultCode::Success => return None,
+            ManageDataResultCode::NotSupportedYet => Code::NotSupportedYet,
+            ManageDataResultCode::NameNotFound => Code::NameNotFound,
+            ManageDataResultCode::LowReserve => Code::LowReserve,
+            ManageDataResultCode::InvalidName => Code::InvalidName,
+        },
+        OperationResultTr::BumpSequence(r) => match r.discriminant() {
+            BumpSequenceResultCode::Success => return None,
+            BumpSequenceResultCode::BadSeq => Code::BadSeq,
+        },
+        OperationResultTr::ManageBuyOffer(r) => match r.discriminant() {
+            ManageBuyOfferResultCode::Success => return None,
+            ManageBuyOfferResultCode::Malformed => Code::Malformed,
+            ManageBuyOfferResultCode::SellNoTrust => Code::SellNoTrust,
+            ManageBuyOfferResultCode::BuyNoTrust => Code::BuyNoTrust,
+            ManageBuyOfferResultCode::SellNotAuthorized => Code::SellNotAuthorized,
+            ManageBuyOfferResultCode::BuyNotAuthorized => Code::BuyNotAuthorized,
+            ManageBuyOfferResultCode::LineFull => Code::LineFull,
+            ManageBuyOfferResultCode::Underfunded => Code::Underfunded,
+            ManageBuyOfferResultCode::CrossSelf => Code::CrossSelf,
+            ManageBuyOfferResultCode::SellNoIssuer => Code::SellNoIssuer,
+            ManageBuyOfferResultCode::BuyNoIssuer => Code::BuyNoIssuer,
+            ManageBuyOfferResultCode::NotFound => Code::NotFound,
+            ManageBuyOfferResultCode::LowReserve => Code::LowReserve,
+        },
+        OperationResultTr::PathPaymentStrictSend(r) => match r.discriminant() {
+            PathPaymentStrictSendResultCode::Success => return None,
+            PathPaymentStrictSendResultCode::Malformed => Code::Malformed,
+            PathPaymentStrictSendResultCode::Underfunded => Code::Underfunded,
+            PathPaymentStrictSendResultCode::SrcNoTrust => Code::SrcNoTrust,
+            PathPaymentStrictSendResultCode::SrcNotAuthorized => Code::SrcNotAuthorized,
+            PathPaymentStrictSendResultCode::NoDestination => Code::NoDestination,
+            PathPaymentStrictSendResultCode::NoTrust => Code::NoTrust,
+            PathPaymentStrictSendResultCode::NotAuthorized => Code::NotAuthorized,
+            PathPaymentStrictSendResultCode::LineFull => Code::LineFull,
+            PathPaymentStrictSendResultCode::NoIssuer => Code::NoIssuer,
+            PathPaymentStrictSendResultCode::TooFewOffers => Code::TooFewOffers,
+            PathPaymentStrictSendResultCode::OfferCrossSelf => Code::OfferCrossSelf,
+            PathPaymentStrictSendResultCode::UnderDestmin => Code::UnderDestMin,
+        },
+        OperationResultTr::CreateClaimableBalance(r) => match r.discriminant() {
+            CreateClaimableBalanceResultCode::Success => return None,
+            CreateClaimableBalanceResultCode::Malformed => Code::Malformed,
+            CreateClaimableBalanceResultCode::LowReserve => Code::LowReserve,
+            CreateClaimableBalanceResultCode::NoTrust => Code::NoTrust,
+            CreateClaimableBalanceResultCode::NotAuthorized => Code::NotAuthorized,
+            CreateClaimableBalanceResultCode::Underfunded => Code::Underfunded,
+        },
+        OperationResultTr::ClaimClaimableBalance(r) => match r.discriminant() {
+            ClaimClaimableBalanceResultCode::Success => return None,
+            ClaimClaimableBalanceResultCode::DoesNotExist => Code::DoesNotExist,
+            ClaimClaimableBalanceResultCode::CannotClaim => Code::CannotClaim,
+            ClaimClaimableBalanceResultCode::LineFull => Code::LineFull,
+            ClaimClaimableBalanceResultCode::NoTrust => Code::NoTrust,
+            ClaimClaimableBalanceResultCode::NotAuthorized => Code::NotAuthorized,
+        },
+        OperationResultTr::BeginSponsoringFutureReserves(r) => match r.discriminant() {
+            BeginSponsoringFutureReservesResultCode::Success => return None,
+            BeginSponsoringFutureReservesResultCode::Malformed => Code::Malformed,
+            BeginSponsoringFutureReservesResultCode::AlreadySponsored => Code::AlreadySponsored,
+            BeginSponsoringFutureReservesResultCode::Recursive => Code::Recursive,
+        },
+        OperationResultTr::EndSponsoringFutureReserves(r) => match r.discriminant() {
+            EndSponsoringFutureReservesResultCode::Success => return None,
+            EndSponsoringFutureReservesResultCode::NotSponsored => Code::NotSponsored,
+        },
+        OperationResultTr::RevokeSponsorship(r) => match r.discriminant() {
+            RevokeSponsorshipResultCode::Success => return None,
+            RevokeSponsorshipResultCode::DoesNotExist => Code::DoesNotExist,
+            RevokeSponsorshipResultCode::NotSponsor => Code::NotSponsor,
+            RevokeSponsorshipResultCode::LowReserve => Code::LowReserve,
+            RevokeSponsorshipResultCode::OnlyTransferable => Code::OnlyTransferable,
+            RevokeSponsorshipResultCode::Malformed => Code::Malformed,
+        },
+        OperationResultTr::Clawback(r) => match r.discriminant() {
+            ClawbackResultCode::Success => return None,
+            ClawbackResultCode::Malformed => Code::Malformed,
+            ClawbackResultCode::NotClawbackEnabled => Code::NotClawbackEnabled,
+            ClawbackResultCode::NoTrust => Code::NoTrust,
+            ClawbackResultCode::Underfunded => Code::Underfunded,
+        },
+        OperationResultTr::ClawbackClaimableBalance(r) => match r.discriminant() {
+            ClawbackClaimableBalanceResultCode::Success => return None,
+            ClawbackClaimableBalanceResultCode::DoesNotExist => Code::DoesNotExist,
+            ClawbackClaimableBalanceResultCode::NotIssuer => Code::NotIssuer,
+            ClawbackClaimableBalanceResultCode::NotClawbackEnabled => Code::NotClawbackEnabled,
+        },
+        OperationResultTr::SetTrustLineFlags(r) => match r.discriminant() {
+            SetTrustLineFlagsResultCode::Success => return None,
+            SetTrustLineFlagsResultCode::Malformed => Code::Malformed,
+            SetTrustLineFlagsResultCode::NoTrustLine => Code::NoTrustLine,
+            SetTrustLineFlagsResultCode::CantRevoke => Code::CantRevoke,
+            SetTrustLineFlagsResultCode::InvalidState => Code::InvalidState,
+            SetTrustLineFlagsResultCode::LowReserve => Code::LowReserve,
+        },
+        OperationResultTr::LiquidityPoolDeposit(r) => match r.discriminant() {
+            LiquidityPoolDepositResultCode::Success => return None,
+            LiquidityPoolDepositResultCode::Malformed => Code::Malformed,
+            LiquidityPoolDepositResultCode::NoTrust => Code::NoTrust,
+            LiquidityPoolDepositResultCode::NotAuthorized => Code::NotAuthorized,
+            LiquidityPoolDepositResultCode::Underfunded => Code::Underfunded,
+            LiquidityPoolDepositResultCode::LineFull => Code::LineFull,
+            LiquidityPoolDepositResultCode::BadPrice => Code::BadPrice,
+            LiquidityPoolDepositResultCode::PoolFull => Code::PoolFull,
+        },
+        OperationResultTr::LiquidityPoolWithdraw(r) => match r.discriminant() {
+            LiquidityPoolWithdrawResultCode::Success => return None,
+            LiquidityPoolWithdrawResultCode::Malformed => Code::Malformed,
+            LiquidityPoolWithdrawResultCode::NoTrust => Code::NoTrust,
+            LiquidityPoolWithdrawResultCode::Underfunded => Code::Underfunded,
+            LiquidityPoolWithdrawResultCode::LineFull => Code::LineFull,
+            LiquidityPoolWithdrawResultCode::UnderMinimum => Code::UnderMinimum,
+        },
+        OperationResultTr::InvokeHostFunction(r) => match r.discriminant() {
+            InvokeHostFunctionResultCode::Success => return None,
+            InvokeHostFunctionResultCode::Malformed => Code::Malformed,
+            InvokeHostFunctionResultCode::Trapped => Code::Trapped,
+            InvokeHostFunctionResultCode::ResourceLimitExceeded => Code::ResourceLimitExceeded,
+            InvokeHostFunctionResultCode::EntryArchived => Code::EntryArchived,
+            InvokeHostFunctionResultCode::InsufficientRefundableFee => Code::InsufficientRefundableFee,
+        },
+        OperationResultTr::ExtendFootprintTtl(r) => match r.discriminant() {
+            ExtendFootprintTtlResultCode::Success => return None,
+            ExtendFootprintTtlResultCode::Malformed => Code::Malformed,
+            ExtendFootprintTtlResultCode::ResourceLimitExceeded => Code::ResourceLimitExceeded,
+            ExtendFootprintTtlResultCode::InsufficientRefundableFee => Code::InsufficientRefundableFee,
+        },
+        OperationResultTr::RestoreFootprint(r) => match r.discriminant() {
+            RestoreFootprintResultCode::Success => return None,
+            RestoreFootprintResultCode::Malformed => Code::Malformed,
+            RestoreFootprintResultCode::ResourceLimitExceeded => Code::ResourceLimitExceeded,
+            RestoreFootprintResultCode::InsufficientRefundableFee => Code::InsufficientRefundableFee,
+        },
+    })
+}
+
+fn operation_hint(code: &OperationErrorCode) -> (RetryHint, &'static str) {
+    use OperationErrorCode::*;
+
+    match code {
+        BadAuth => (RetryHint::DoNotRetry, "missing or invalid signature for the operation source"),
+        SourceAccountMissing => (RetryHint::UserAction, "operation source account does not exist"),
+        OperationNotSupported => (RetryHint::DoNotRetry, "operation is not supported by the network"),
+        TooManySubentries => (RetryHint::UserAction, "account has too many trustlines, offers or signers"),
+        ExceededWorkLimit => (RetryHint::RetryLater, "operation did too much work"),
+        TooManySponsoring => (RetryHint::DoNotRetry, "account sponsors too many entries"),
+        Malformed => (RetryHint::DoNotRetry, "invalid operation parameters"),
+        Underfunded => (RetryHint::UserAction, "insufficient balance to complete this transaction"),
+        LowReserve => (RetryHint::UserAction, "this would leave the account below the minimum XLM reserve"),
+        AlreadyExist => (RetryHint::DoNotRetry, "destination account already exists"),
+        SrcNoTrust => (RetryHint::UserAction, "source account has no trustline for the asset"),
+        SrcNotAuthorized => (RetryHint::UserAction, "source account is not authorized to hold the asset"),
+        NoDestination => (
+            RetryHint::UserAction,
+            "destination account does not exist; it needs to be funded with at least 1 XLM first",
+        ),
+        NoTrust => (RetryHint::UserAction, "destination has no trustline for the asset"),
+        NotAuthorized => (RetryHint::UserAction, "account is not authorized to hold the asset"),
+        LineFull => (RetryHint::UserAction, "destination trustline limit would be exceeded"),
+        NoIssuer => (RetryHint::DoNotRetry, "asset issuer does not exist"),
+        TooFewOffers => (RetryHint::Rebuild, "not enough liquidity on the path"),
+        OfferCrossSelf => (RetryHint::Rebuild, "path would cross an offer of the same account"),
+        OverSendMax => (RetryHint::Rebuild, "price moved above the maximum send amount"),
+        UnderDestMin => (RetryHint::Rebuild, "price moved below the minimum destination amount"),
+        SellNoTrust => (RetryHint::UserAction, "no trustline for the asset being sold"),
+        BuyNoTrust => (RetryHint::UserAction, "no trustline for the asset being bought"),
+        SellNotAuthorized => (RetryHint::UserAction, "not authorized to sell the asset"),
+        BuyNotAuthorized => (RetryHint::UserAction, "not authorized to buy the asset"),
+        CrossSelf => (RetryHint::Rebuild, "offer would cross another offer of the same account"),
+        SellNoIssuer => (RetryHint::DoNotRetry, "issuer of the asset being sold does not exist"),
+        BuyNoIssuer => (RetryHint::DoNotRetry, "issuer of the asset being bought does not exist"),
+        NotFound => (RetryHint::DoNotRetry, "offer not found"),
+        TooManySigners => (RetryHint::DoNotRetry, "account already has the maximum number of signers"),
+        BadFlags => (RetryHint::DoNotRetry, "invalid combination of account flags"),
+        InvalidInflation => (RetryHint::DoNotRetry, "inflation destination does not exist"),
+        CantChange => (RetryHint::DoNotRetry, "account flags can no longer be changed"),
+        UnknownFlag => (RetryHint::DoNotRetry, "unknown account flag"),
+        ThresholdOutOfRange => (RetryHint::DoNotRetry, "threshold or signer weight out of range"),
+        BadSigner => (RetryHint::DoNotRetry, "signer cannot be the master key"),
+        InvalidHomeDomain => (RetryHint::DoNotRetry, "invalid home domain"),
+        AuthRevocableRequired => (RetryHint::DoNotRetry, "clawback requires the auth revocable flag"),
+        InvalidLimit => (RetryHint::UserAction, "trustline limit is below the current balance or liabilities"),
+        SelfNotAllowed => (RetryHint::DoNotRetry, "an issuer cannot trust its own asset"),
+        TrustLineMissing => (RetryHint::UserAction, "required trustline is missing"),
+        CannotDelete => (RetryHint::UserAction, "trustline is still in use by a liquidity pool"),
+        NotAuthMaintainLiabilities => (RetryHint::UserAction, "asset is not authorized to maintain liabilities"),
+        NoTrustLine => (RetryHint::DoNotRetry, "trustor has no trustline for the asset"),
+        TrustNotRequired => (RetryHint::DoNotRetry, "issuer does not require authorization"),
+        CantRevoke => (RetryHint::DoNotRetry, "issuer cannot revoke authorization"),
+        NoAccount => (RetryHint::DoNotRetry, "merge destination does not exist"),
+        ImmutableSet => (RetryHint::DoNotRetry, "account is immutable"),
+        HasSubEntries => (RetryHint::UserAction, "account still has trustlines or offers"),
+        SeqnumTooFar => (RetryHint::DoNotRetry, "sequence number is too far ahead to merge"),
+        DestFull => (RetryHint::DoNotRetry, "destination cannot receive the balance"),
+        IsSponsor => (RetryHint::UserAction, "account still sponsors other entries"),
+        NotTime => (RetryHint::DoNotRetry, "inflation is not running"),
+        NotSupportedYet => (RetryHint::DoNotRetry, "operation is not supported yet"),
+        NameNotFound => (RetryHint::DoNotRetry, "data entry not found"),
+        InvalidName => (RetryHint::DoNotRetry, "invalid data entry name"),
+        BadSeq => (RetryHint::DoNotRetry, "bump target is below the current sequence number"),
+        DoesNotExist => (RetryHint::DoNotRetry, "entry does not exist"),
+        CannotClaim => (RetryHint::RetryLater, "claimable balance predicate is not satisfied"),
+        AlreadySponsored => (RetryHint::DoNotRetry, "account is already sponsored"),
+        Recursive => (RetryHint::DoNotRetry, "sponsorship cannot be recursive"),
+        NotSponsored => (RetryHint::DoNotRetry, "account is not being sponsored"),
+        NotSponsor => (RetryHint::DoNotRetry, "account is not the sponsor"),
+        OnlyTransferable => (RetryHint::DoNotRetry, "sponsorship can only be transferred"),
+        NotClawbackEnabled => (RetryHint::DoNotRetry, "clawback is not enabled for the asset"),
+        NotIssuer => (RetryHint::DoNotRetry, "only the issuer can claw back"),
+        InvalidState => (RetryHint::DoNotRetry, "invalid trustline flag state"),
+        BadPrice => (RetryHint::Rebuild, "pool price moved outside the requested bounds"),
+        PoolFull => (RetryHint::DoNotRetry, "liquidity pool is full"),
+        UnderMinimum => (RetryHint::Rebuild, "withdrawal would return less than the requested minimum"),
+        Trapped => (RetryHint::DoNotRetry, "contract invocation trapped"),
+        ResourceLimitExceeded => (RetryHint::IncreaseFee, "Soroban resource limits exceeded"),
+        EntryArchived => (RetryHint::UserAction, "a ledger entry in the footprint is archived; restore it first"),
+        InsufficientRefundableFee => (RetryHint::IncreaseFee, "refundable Soroban fee is too low"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Recorded from horizon-testnet.stellar.org
+    const HORIZON_SUBMISSION_FAILED: &str = include_str!("../testdata/horizon_submission_failed.json");
+
+    fn decode(result_xdr: &str) -> TxError {
+        decode_result_xdr(result_xdr).unwrap().expect("a failed result")
+    }
+
+    #[test]
+    fn bad_seq_asks_for_a_rebuild() {
+        // tx_bad_seq
+        let error = decode("AAAAAAAAAGT////7AAAAAA==");
+        assert_eq!(error.code, TxErrorCode::Transaction(TransactionErrorCode::BadSeq));
+        assert_eq!(error.operation_index, None);
+        assert_eq!(error.retry, RetryHint::Rebuild);
+    }
+
+    #[test]
+    fn insufficient_fee_asks_for_a_higher_fee() {
+        // tx_insufficient_fee
+        let error = decode("AAAAAAAAAGT////3AAAAAA==");
+        assert_eq!(error.code, TxErrorCode::Transaction(TransactionErrorCode::InsufficientFee));
+        assert_eq!(error.operation_index, None);
+        assert_eq!(error.retry, RetryHint::IncreaseFee);
+    }
+
+    #[test]
+    fn underfunded_payment_needs_the_user() {
+        let response: serde_json::Value = serde_json::from_str(HORIZON_SUBMISSION_FAILED).unwrap();
+        assert_eq!(response["extras"]["result_codes"]["operations"][0], "op_underfunded");
+
+        let error = decode(response["extras"]["result_xdr"].as_str().unwrap());
+        assert_eq!(error.code, TxErrorCode::Operation(OperationErrorCode::Underfunded));
+        assert_eq!(error.operation_index, Some(0));
+        assert_eq!(error.operation_type.as_deref(), Some("Payment"));
+        assert_eq!(error.retry, RetryHint::UserAction);
+    }
+
+    #[test]
+    fn missing_trustline_needs_the_user() {
+        // tx_failed with op_no_trust on a payment
+        let error = decode("AAAAAAAAAGT/////AAAAAQAAAAAAAAAB////+gAAAAA=");
+        assert_eq!(error.code, TxErrorCode::Operation(OperationErrorCode::NoTrust));
+        assert_eq!(error.operation_index, Some(0));
+        assert_eq!(error.operation_type.as_deref(), Some("Payment"));
+        assert_eq!(error.retry, RetryHint::UserAction);
+    }
+
+    #[test]
+    fn failing_operation_is_found_after_successful_ones() {
+        // tx_failed with op_success on a payment, then op_under_dest_min on a path payment
+        let error = decode("AAAAAAAAAGT/////AAAAAgAAAAAAAAABAAAAAAAAAAAAAAAN////9AAAAAA=");
+        assert_eq!(error.code, TxErrorCode::Operation(OperationErrorCode::UnderDestMin));
+        assert_eq!(error.operation_index, Some(1));
+        assert_eq!(error.operation_type.as_deref(), Some("PathPaymentStrictSend"));
+        assert_eq!(error.retry, RetryHint::Rebuild);
+    }
+}
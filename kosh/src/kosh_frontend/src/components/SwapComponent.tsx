@@ -121,9 +121,9 @@ const SwapComponent = ({ actor, stellarAddress, selectedNetwork, onSwapComplete
       console.log('Trustline creation result:', result);
       
       if (result.Ok) {
-        const trustlineData = JSON.parse(result.Ok);
+        const trustlineData = result.Ok;
         
-        if (trustlineData.success) {
+        if (trustlineData.hash) {
           setTrustlineStatus(prev => ({
             ...prev,
             [tokenKey]: { checking: false, exists: true }
@@ -159,7 +159,7 @@ const SwapComponent = ({ actor, stellarAddress, selectedNetwork, onSwapComplete
           throw new Error(trustlineData.error || "Trustline creation failed");
         }
       } else {
-        throw new Error(result.Err || "Backend call failed");
+        throw new Error(result.Err?.message || "Backend call failed");
       }
 
     } catch (error) {
@@ -268,10 +268,10 @@ const SwapComponent = ({ actor, stellarAddress, selectedNetwork, onSwapComplete
       console.log('Raw swap result from backend:', result);
       
       if (result.Ok) {
-        // Your sign_transaction_stellar returns JSON string directly
-        const swapData = JSON.parse(result.Ok);
+        // Submission endpoints return a SubmittedTransaction; failures come back in Err
+        const swapData = result.Ok;
         
-        if (swapData.success) {
+        if (swapData.hash) {
           const hashDisplay = swapData.hash ? `${swapData.hash.substring(0, 12)}...` : 'N/A';
           toast({
             title: "Swap Successful! ✅",
@@ -306,7 +306,7 @@ const SwapComponent = ({ actor, stellarAddress, selectedNetwork, onSwapComplete
           throw new Error(errorMsg);
         }
       } else {
-        throw new Error(result.Err || "Backend call failed");
+        throw new Error(result.Err?.message || "Backend call failed");
       }
     } catch (error) {
       console.error('Error executing swap:', error);
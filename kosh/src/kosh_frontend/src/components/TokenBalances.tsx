@@ -49,14 +49,19 @@ const TokenBalances = ({ actor, selectedNetwork, onAddTrustline, stellarAddress
       console.log('Assets result:', result);
 
       if (result.Ok) {
-        const assetsData = JSON.parse(result.Ok);
-        if (assetsData.success) {
-          setAssets(assetsData.assets || []);
+        const assetsData = result.Ok;
+        if (assetsData.account_exists) {
+          // Candid optionals arrive as [] or [value]
+          setAssets(assetsData.assets.map((asset: any) => ({
+            ...asset,
+            asset_issuer: asset.asset_issuer[0],
+            limit: asset.limit[0],
+          })));
         } else {
-          console.error('Failed to fetch assets:', assetsData.error);
+          console.error('Failed to fetch assets: account not found');
           toast({
             title: "Failed to fetch assets",
-            description: assetsData.error || "Could not load token balances",
+            description: "Account not found",
             variant: "destructive",
           });
         }
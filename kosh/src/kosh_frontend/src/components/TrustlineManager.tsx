@@ -110,8 +110,8 @@ const TrustlineManager = ({ actor, stellarAddress, selectedNetwork, onTrustlineC
       );
 
       if (result.Ok) {
-        const trustlineData = JSON.parse(result.Ok);
-        if (trustlineData.success) {
+        const trustlineData = result.Ok;
+        if (trustlineData.hash) {
           toast({
             title: "Trustline Created! ✅",
             description: `Successfully created trustline for ${assetCode}`,
@@ -133,7 +133,7 @@ const TrustlineManager = ({ actor, stellarAddress, selectedNetwork, onTrustlineC
           throw new Error(trustlineData.error || "Failed to create trustline");
         }
       } else {
-        throw new Error(result.Err || "Failed to create trustline");
+        throw new Error(result.Err?.message || "Failed to create trustline");
       }
     } catch (error) {
       console.error('Error creating trustline:', error);
@@ -163,8 +163,8 @@ const TrustlineManager = ({ actor, stellarAddress, selectedNetwork, onTrustlineC
       );
 
       if (result.Ok) {
-        const trustlineData = JSON.parse(result.Ok);
-        if (trustlineData.success) {
+        const trustlineData = result.Ok;
+        if (trustlineData.hash) {
           toast({
             title: "Trustline Removed ✅",
             description: `Successfully removed trustline for ${assetCode}`,
@@ -178,7 +178,7 @@ const TrustlineManager = ({ actor, stellarAddress, selectedNetwork, onTrustlineC
           throw new Error(trustlineData.error || "Failed to remove trustline");
         }
       } else {
-        throw new Error(result.Err || "Failed to remove trustline");
+        throw new Error(result.Err?.message || "Failed to remove trustline");
       }
     } catch (error) {
       console.error('Error removing trustline:', error);
@@ -381,50 +381,17 @@ export const useAuth = () => {
       console.log('Backend response:', result);
       
       if (result.Ok) {
-        // Parse the JSON response from backend
-        try {
-          const responseData = JSON.parse(result.Ok);
-          console.log('Parsed response data:', responseData);
-          
-          if (responseData.success) {
-            return {
-              success: true,
-              message: responseData.hash ? 'Transaction submitted successfully!' : 'Transaction successful (hash pending)',
-              hash: responseData.hash,
-              explorer_url: responseData.explorer_url,
-              raw_response: responseData.raw_response,
-              details: responseData
-            };
-          } else {
-            console.error('Transaction failed:', responseData);
-            return {
-              success: false,
-              message: responseData.error || 'Transaction failed',
-              raw_response: responseData.raw_response,
-              details: responseData
-            };
-          }
-        } catch (parseError) {
-          console.warn('Failed to parse backend response as JSON:', parseError);
-          console.log('Raw response:', result.Ok);
-          
-          // Fallback for non-JSON response - try to extract hash manually
-          const hashMatch = result.Ok.match(/"hash"\s*:\s*"([^"]+)"/);
-          const hash = hashMatch ? hashMatch[1] : null;
-          
-          const networkPath = network === 'mainnet' ? 'public' : 'testnet';
-          return {
-            success: true,
-            message: hash ? 'Transaction submitted successfully!' : 'Transaction completed (parsing hash...)',
-            hash: hash,
-            explorer_url: hash ? `https://stellar.expert/explorer/${networkPath}/tx/${hash}` : null,
-            raw_response: result.Ok,
-            details: result.Ok
-          };
-        }
+        const submitted = result.Ok;
+        return {
+          success: true,
+          message: 'Transaction submitted successfully!',
+          hash: submitted.hash,
+          explorer_url: submitted.explorer_url,
+          details: submitted
+        };
       } else {
         console.error('Backend returned error:', result.Err);
-        throw new Error(result.Err);
+        throw new Error(result.Err?.message || 'Transaction failed');
       }
     } catch (error) {
       console.error('Failed to build and submit transaction:', error);